@@ -0,0 +1,284 @@
+use crate::model::{Animation, Skeleton};
+use crate::{binary_format, bvh_loader, gltf_loader, obj_loader};
+use std::path::Path;
+use std::sync::Arc;
+
+// animation clips loaded from any number of json/gltf files, tracks are indexed
+// by bone name so the same clip data can be shared by every model whose
+// skeleton uses those names
+#[derive(Default)]
+pub struct AnimationLibrary {
+    clips: Vec<Arc<Animation>>,
+    // file each clip was loaded from, same order as clips
+    sources: Vec<String>,
+}
+
+// result of matching the tracks of a clip with the bones of a skeleton
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClipBindingReport {
+    pub clip_name: String,
+    pub source: String,
+    pub skeleton_name: String,
+    pub matched_tracks: usize,
+    // tracks without a bone of the same name, they are ignored
+    pub unmatched_tracks: Vec<String>,
+    // bones without a track, they keep their bind pose
+    pub unanimated_bones: Vec<String>,
+}
+
+impl ClipBindingReport {
+    pub fn new(clip: &Animation, skeleton: &Skeleton, source: &str) -> Self {
+        let mut unmatched_tracks: Vec<String> = clip
+            .tracks()
+            .iter()
+            .map(|track| &track.bone_name)
+            .filter(|name| {
+                !skeleton
+                    .bones_ordered
+                    .iter()
+                    .any(|bone| &bone.name == *name)
+            })
+            .cloned()
+            .collect();
+        unmatched_tracks.sort();
+        let unanimated_bones = skeleton
+            .bones_ordered
+            .iter()
+            .filter(|bone| clip.track(&bone.name).is_none())
+            .map(|bone| bone.name.clone())
+            .collect();
+        Self {
+            clip_name: clip.name.clone(),
+            source: source.to_string(),
+            skeleton_name: skeleton.name.clone(),
+            matched_tracks: clip.tracks().len() - unmatched_tracks.len(),
+            unmatched_tracks,
+            unanimated_bones,
+        }
+    }
+
+    // at least one track drives a bone of the skeleton
+    pub fn is_bound(&self) -> bool {
+        self.matched_tracks > 0
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.unmatched_tracks.is_empty() && self.unanimated_bones.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "animation '{}' ({}) on skeleton '{}': {} tracks bound",
+            self.clip_name, self.source, self.skeleton_name, self.matched_tracks
+        );
+        if !self.unmatched_tracks.is_empty() {
+            summary += &format!(
+                ", {} tracks without bone [{}]",
+                self.unmatched_tracks.len(),
+                self.unmatched_tracks.join(", ")
+            );
+        }
+        if !self.unanimated_bones.is_empty() {
+            summary += &format!(", {} bones without track", self.unanimated_bones.len());
+        }
+        if !self.is_bound() {
+            summary += ", nothing to animate";
+        }
+        summary
+    }
+}
+
+// print a one line summary of every report with unmatched tracks or bones
+pub fn log_binding_reports(reports: &[ClipBindingReport]) {
+    for report in reports.iter().filter(|report| !report.is_clean()) {
+        eprintln!("{}", report.summary());
+    }
+}
+
+impl AnimationLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // load every clip of a .json/.gltf/.glb/.rrb/.bvh file, returns the number of clips added
+    pub fn load_file(&mut self, path: &str) -> anyhow::Result<usize> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        // (clips, length of one key time unit in seconds)
+        let (clips, time_unit) = match extension.as_deref() {
+            Some("json") => (obj_loader::json_anim_clips(path)?, 0.001),
+            Some("gltf") | Some("glb") => (gltf_loader::load_gltf_animations(path)?, 1.0),
+            Some(binary_format::BINARY_EXTENSION) => {
+                (binary_format::read_binary(path)?.animations, 0.001)
+            }
+            Some("bvh") => (
+                vec![bvh_loader::load_bvh(path, &bvh_loader::BvhOptions::default())?.1],
+                1.0,
+            ),
+            _ => return Err(anyhow::anyhow!("{}: unsupported animation file", path)),
+        };
+        let count = clips.len();
+        // the library clips are in seconds, like the ones of the model loaders
+        for mut clip in clips {
+            clip.scale_times(time_unit);
+            self.add_clip(clip, path);
+        }
+        Ok(count)
+    }
+
+    pub fn load_files(&mut self, paths: &[&str]) -> anyhow::Result<usize> {
+        let mut count = 0;
+        for path in paths {
+            count += self.load_file(path)?;
+        }
+        Ok(count)
+    }
+
+    pub fn add_clip(&mut self, clip: Animation, source: &str) -> Arc<Animation> {
+        if self.get(&clip.name).is_some() {
+            eprintln!(
+                "animation '{}' from {} already in the library, lookups by name return the first one",
+                clip.name, source
+            );
+        }
+        let clip = Arc::new(clip);
+        self.clips.push(Arc::clone(&clip));
+        self.sources.push(source.to_string());
+        clip
+    }
+
+    pub fn clips(&self) -> &[Arc<Animation>] {
+        &self.clips
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Animation>> {
+        self.clips
+            .iter()
+            .find(|clip| clip.name == name)
+            .map(Arc::clone)
+    }
+
+    // clips with at least one track for the skeleton, and a report for every clip
+    pub fn bind(&self, skeleton: &Skeleton) -> (Vec<Arc<Animation>>, Vec<ClipBindingReport>) {
+        let mut bound = Vec::new();
+        let mut reports = Vec::new();
+        for (clip, source) in self.clips.iter().zip(&self.sources) {
+            let report = ClipBindingReport::new(clip, skeleton, source);
+            if report.is_bound() {
+                bound.push(Arc::clone(clip));
+            }
+            reports.push(report);
+        }
+        (bound, reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary_format::BinaryWriteOptions;
+    use crate::model::{AnimatedBone, Bone, KeyTranslation, Model};
+
+    fn skeleton(names: &[&str]) -> Skeleton {
+        let bones: Vec<Bone> = names
+            .iter()
+            .enumerate()
+            .map(|(id, name)| Bone {
+                id: id as u32,
+                name: name.to_string(),
+                parent_id: id.checked_sub(1),
+                inverse_bind_matrix: cgmath::Matrix4::from_scale(1.0).into(),
+                index: id,
+                parent_offset: None,
+            })
+            .collect();
+        Skeleton {
+            name: "rig".to_string(),
+            bones: bones.iter().cloned().enumerate().collect(),
+            bones_ordered: bones,
+        }
+    }
+
+    // one translation key per track at `timestamp`
+    fn clip(name: &str, tracks: &[&str], timestamp: f32) -> Animation {
+        let mut clip = Animation::new(name);
+        for track in tracks {
+            clip.add_track(AnimatedBone {
+                bone_name: track.to_string(),
+                translation_keys: vec![KeyTranslation {
+                    timestamp,
+                    translation: [0.0, 0.0, 0.0],
+                }],
+                ..Default::default()
+            });
+        }
+        clip
+    }
+
+    #[test]
+    fn bind_reports_missing_and_extra_bones() {
+        let mut library = AnimationLibrary::new();
+        library.add_clip(clip("walk", &["hips", "spine", "tail"], 0.0), "walk.json");
+        library.add_clip(clip("wag", &["tail"], 0.0), "wag.json");
+        let (bound, reports) = library.bind(&skeleton(&["hips", "spine", "hand"]));
+
+        assert_eq!(bound.len(), 1);
+        assert_eq!(bound[0].name, "walk");
+        assert_eq!(
+            reports[0],
+            ClipBindingReport {
+                clip_name: "walk".to_string(),
+                source: "walk.json".to_string(),
+                skeleton_name: "rig".to_string(),
+                matched_tracks: 2,
+                unmatched_tracks: vec!["tail".to_string()],
+                unanimated_bones: vec!["hand".to_string()],
+            }
+        );
+        assert!(reports[0].is_bound() && !reports[0].is_clean());
+        assert!(!reports[1].is_bound());
+        assert!(reports[1].summary().ends_with("nothing to animate"));
+        assert!(Arc::ptr_eq(&library.get("walk").unwrap(), &bound[0]));
+    }
+
+    #[test]
+    fn load_file_by_extension() {
+        // binary key times are in milliseconds, the library clips in seconds
+        let path = std::env::temp_dir().join(format!("library_{}.RRB", std::process::id()));
+        let path = path.to_string_lossy();
+        let walk = clip("walk", &["hips"], 500.0);
+        binary_format::write_binary(
+            &path,
+            &Model::default(),
+            [&walk],
+            &BinaryWriteOptions::default(),
+        )
+        .unwrap();
+        let mut library = AnimationLibrary::new();
+        let count = library.load_file(&path);
+        std::fs::remove_file(path.as_ref()).unwrap();
+        assert_eq!(count.unwrap(), 1);
+        let walk = library.get("walk").unwrap();
+        assert_eq!(walk.tracks()[0].translation_keys[0].timestamp, 0.5);
+
+        // bvh frame times are already in seconds
+        let path = std::env::temp_dir().join(format!("library_{}.bvh", std::process::id()));
+        std::fs::write(
+            &path,
+            "HIERARCHY\nROOT hips\n{\nOFFSET 0 0 0\nCHANNELS 3 Zrotation Xrotation Yrotation\n\
+             End Site\n{\nOFFSET 0 1 0\n}\n}\nMOTION\nFrames: 2\nFrame Time: 0.25\n0 0 0\n0 0 0\n",
+        )
+        .unwrap();
+        let count = library.load_file(&path.to_string_lossy());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(count.unwrap(), 1);
+        let clip = &library.clips()[1];
+        assert_eq!(clip.tracks()[0].rotation_keys[1].timestamp, 0.25);
+
+        let error = library.load_file("clip.fbx").unwrap_err();
+        assert!(error.to_string().contains("unsupported animation file"));
+        assert_eq!(library.clips().len(), 2);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::camera::Camera;
+use crate::model::{BoneTransformsUniform, Skeleton, MAX_BONES};
+use cgmath::{InnerSpace, Matrix4, Vector3};
+
+// value used to pick the lod level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LodMetric {
+    // distance between the camera and the model bounds center
+    Distance,
+    // fraction of the screen height covered by the model bounds
+    ScreenSize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnimationLodLevel {
+    // Distance: used while the distance is <= threshold
+    // ScreenSize: used while the screen size is >= threshold
+    pub threshold: f32,
+    // seconds between two pose evaluations (0.0 evaluates every frame)
+    pub update_interval: f32,
+    // leaf bones keep their bind pose instead of being sampled
+    pub skip_leaf_bones: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationLodSettings {
+    pub metric: LodMetric,
+    // ordered from the most to the least detailed level,
+    // the last level is used when no threshold matches
+    pub levels: Vec<AnimationLodLevel>,
+    // blend between the last two evaluated poses while waiting for the next update
+    pub interpolate: bool,
+}
+
+impl Default for AnimationLodSettings {
+    fn default() -> Self {
+        Self {
+            metric: LodMetric::Distance,
+            levels: vec![
+                AnimationLodLevel {
+                    threshold: 10.0,
+                    update_interval: 0.0,
+                    skip_leaf_bones: false,
+                },
+                AnimationLodLevel {
+                    threshold: 25.0,
+                    update_interval: 1.0 / 15.0,
+                    skip_leaf_bones: false,
+                },
+                AnimationLodLevel {
+                    threshold: f32::MAX,
+                    update_interval: 1.0 / 5.0,
+                    skip_leaf_bones: true,
+                },
+            ],
+            interpolate: true,
+        }
+    }
+}
+
+// per model lod state: picks the level, throttles pose evaluation
+// and interpolates the uploaded bone matrices between evaluations
+pub struct AnimationLod {
+    pub settings: AnimationLodSettings,
+    current_level: usize,
+    // time since the last evaluation
+    time_since_update: f32,
+    previous_pose: BoneTransformsUniform,
+    target_pose: BoneTransformsUniform,
+    pose_count: usize,
+    // target pose changed and was not uploaded yet
+    dirty: bool,
+    // bind pose of every leaf bone relative to its parent (None for inner bones)
+    leaf_bind_poses: Vec<Option<Matrix4<f32>>>,
+}
+
+impl AnimationLod {
+    pub fn new(settings: AnimationLodSettings, skeleton: &Skeleton) -> Self {
+        let leaves = skeleton.leaf_bones();
+        let leaf_bind_poses = skeleton
+            .bones_ordered
+            .iter()
+            .zip(leaves)
+            .map(|(bone, is_leaf)| {
+                if is_leaf && bone.parent_id.is_some() {
+                    Some(skeleton.bind_local_matrix(bone))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        Self {
+            settings,
+            current_level: 0,
+            time_since_update: 0.0,
+            previous_pose: BoneTransformsUniform::new(),
+            target_pose: BoneTransformsUniform::new(),
+            pose_count: 0,
+            dirty: false,
+            leaf_bind_poses,
+        }
+    }
+
+    pub fn level(&self) -> AnimationLodLevel {
+        self.settings
+            .levels
+            .get(self.current_level)
+            .copied()
+            .unwrap_or(AnimationLodLevel {
+                threshold: f32::MAX,
+                update_interval: 0.0,
+                skip_leaf_bones: false,
+            })
+    }
+
+    pub fn level_index(&self) -> usize {
+        self.current_level
+    }
+
+    // overrides to pass to the animation player for the current level
+    pub fn leaf_overrides(&self) -> &[Option<Matrix4<f32>>] {
+        if self.level().skip_leaf_bones {
+            &self.leaf_bind_poses
+        } else {
+            &[]
+        }
+    }
+
+    // pick the level from the camera and the model bounds in world space
+    pub fn select_level(&mut self, camera: &Camera, center: Vector3<f32>, radius: f32) {
+        let distance = (center - camera.transform.position).magnitude();
+        let value = match self.settings.metric {
+            LodMetric::Distance => distance,
+            LodMetric::ScreenSize => screen_size(camera, distance, radius),
+        };
+        let last = self.settings.levels.len().saturating_sub(1);
+        self.current_level = self
+            .settings
+            .levels
+            .iter()
+            .position(|level| match self.settings.metric {
+                LodMetric::Distance => value <= level.threshold,
+                LodMetric::ScreenSize => value >= level.threshold,
+            })
+            .unwrap_or(last);
+    }
+
+    // advance the clock, returns the time to feed to the animation player
+    // when a new pose has to be evaluated this frame
+    pub fn tick(&mut self, delta_time: f32) -> Option<f32> {
+        self.time_since_update += delta_time;
+        let interval = self.level().update_interval;
+        if self.pose_count == 0 || self.time_since_update >= interval {
+            let elapsed = self.time_since_update;
+            self.time_since_update = 0.0;
+            return Some(elapsed);
+        }
+        None
+    }
+
+    pub fn push_pose(&mut self, pose: BoneTransformsUniform) {
+        self.previous_pose = if self.pose_count == 0 {
+            pose
+        } else {
+            self.target_pose
+        };
+        self.target_pose = pose;
+        self.pose_count += 1;
+        self.dirty = true;
+    }
+
+    // pose to upload this frame, None if the buffer already holds it
+    pub fn take_pose(&mut self, bone_count: usize) -> Option<BoneTransformsUniform> {
+        let interval = self.level().update_interval;
+        let interpolating = self.settings.interpolate && interval > 0.0 && self.pose_count > 1;
+        if interpolating {
+            let t = (self.time_since_update / interval).clamp(0.0, 1.0);
+            self.dirty = false;
+            return Some(blend_poses(
+                &self.previous_pose,
+                &self.target_pose,
+                t,
+                bone_count,
+            ));
+        }
+        if self.dirty {
+            self.dirty = false;
+            return Some(self.target_pose);
+        }
+        None
+    }
+
+    // forget the evaluated poses (e.g. after switching animation)
+    pub fn reset(&mut self) {
+        self.pose_count = 0;
+        self.time_since_update = 0.0;
+        self.dirty = false;
+    }
+}
+
+// projected height of the bounding sphere relative to the screen height
+fn screen_size(camera: &Camera, distance: f32, radius: f32) -> f32 {
+    let half_fov = cgmath::Rad::from(cgmath::Deg(camera.fov * 0.5)).0;
+    if distance <= radius {
+        return f32::MAX;
+    }
+    radius / (distance * half_fov.tan())
+}
+
+// linear blend of the skinning matrices, close enough for the small
+// intervals used by the lod levels and much cheaper than resampling
+fn blend_poses(
+    from: &BoneTransformsUniform,
+    to: &BoneTransformsUniform,
+    t: f32,
+    bone_count: usize,
+) -> BoneTransformsUniform {
+    let mut result = *to;
+    for bone in 0..bone_count.min(MAX_BONES) {
+        for col in 0..4 {
+            for row in 0..4 {
+                let a = from.transforms[bone][col][row];
+                let b = to.transforms[bone][col][row];
+                result.transforms[bone][col][row] = a + (b - a) * t;
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Transform;
+
+    fn lod(metric: LodMetric, thresholds: [f32; 3], intervals: [f32; 3]) -> AnimationLod {
+        let levels = thresholds
+            .iter()
+            .zip(intervals)
+            .map(|(threshold, update_interval)| AnimationLodLevel {
+                threshold: *threshold,
+                update_interval,
+                skip_leaf_bones: false,
+            })
+            .collect();
+        let settings = AnimationLodSettings {
+            metric,
+            levels,
+            interpolate: true,
+        };
+        AnimationLod::new(settings, &Skeleton::default())
+    }
+
+    // camera at the origin
+    fn camera() -> Camera {
+        Camera::new(Transform::identity(), 45.0, 1.0, 0.1, 100.0)
+    }
+
+    #[test]
+    fn distance_thresholds() {
+        let mut lod = lod(LodMetric::Distance, [10.0, 25.0, f32::MAX], [0.0; 3]);
+        for (distance, level) in [(5.0, 0), (10.0, 0), (10.5, 1), (25.0, 1), (80.0, 2)] {
+            lod.select_level(&camera(), Vector3::new(0.0, 0.0, -distance), 1.0);
+            assert_eq!(lod.level_index(), level, "distance {}", distance);
+        }
+    }
+
+    #[test]
+    fn screen_size_thresholds() {
+        let mut lod = lod(LodMetric::ScreenSize, [0.5, 0.1, 0.0], [0.0; 3]);
+        // tan(22.5°) ≈ 0.414, a unit sphere at distance d covers 1 / (0.414 d)
+        for (distance, level) in [(0.5, 0), (4.0, 0), (5.0, 1), (24.0, 1), (25.0, 2)] {
+            lod.select_level(&camera(), Vector3::new(0.0, 0.0, -distance), 1.0);
+            assert_eq!(lod.level_index(), level, "distance {}", distance);
+        }
+    }
+
+    // frames of delta_time that evaluate a pose, and the time they were given
+    fn run(lod: &mut AnimationLod, frames: usize, delta_time: f32) -> (usize, f32) {
+        let mut updates = 0;
+        let mut elapsed = 0.0;
+        for _ in 0..frames {
+            if let Some(time) = lod.tick(delta_time) {
+                updates += 1;
+                elapsed += time;
+                lod.push_pose(BoneTransformsUniform::new());
+            }
+        }
+        (updates, elapsed)
+    }
+
+    #[test]
+    fn tick_throttles_the_updates() {
+        // 4 frames per update, the first frame always evaluates
+        let mut throttled = lod(LodMetric::Distance, [f32::MAX; 3], [0.25; 3]);
+        let (updates, elapsed) = run(&mut throttled, 64, 0.0625);
+        assert_eq!(updates, 16);
+        assert_eq!(elapsed, 0.0625 + 15.0 * 0.25);
+
+        let mut every_frame = lod(LodMetric::Distance, [f32::MAX; 3], [0.0; 3]);
+        assert_eq!(run(&mut every_frame, 64, 0.0625).0, 64);
+
+        // a reset evaluates on the next frame
+        throttled.reset();
+        assert_eq!(run(&mut throttled, 1, 0.0625).0, 1);
+    }
+
+    #[test]
+    fn blend_between_poses() {
+        let from = BoneTransformsUniform::new();
+        let mut to = BoneTransformsUniform::new();
+        let translation: [[f32; 4]; 4] =
+            Matrix4::from_translation(Vector3::new(2.0, 4.0, 6.0)).into();
+        to.transforms[0] = translation;
+        to.transforms[1] = translation;
+
+        assert_eq!(
+            blend_poses(&from, &to, 0.0, 1).transforms[0],
+            from.transforms[0]
+        );
+        assert_eq!(blend_poses(&from, &to, 1.0, 1).transforms[0], translation);
+        let half: [[f32; 4]; 4] = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0)).into();
+        let blended = blend_poses(&from, &to, 0.5, 1);
+        assert_eq!(blended.transforms[0], half);
+        // the bones past bone_count are the target pose
+        assert_eq!(blended.transforms[1], translation);
+    }
+}
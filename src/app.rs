@@ -2,13 +2,15 @@ use cgmath::{Matrix4, SquareMatrix};
 use gltf::Mesh;
 
 use crate::{
+    animation_library::AnimationLibrary,
     camera::{self, Camera},
     input,
-    model::{AnimatedBone, Bone, BoneTransformsUniform},
+    mesh_optimizer::OptimizeOptions,
+    model::{AnimatedBone, Bone, BoneTransformsUniform, MeshVertexFormat},
     model_shader,
     renderer::Renderer,
     shader::{self, ColorUniform, Render},
-    testing::{CameraController, LoadedModel},
+    testing::{CameraController, LoadedModel, ModelAnimator},
     transform::{self, Transform},
     window::{self, WindowSize, WinitWindow},
 };
@@ -136,17 +138,54 @@ struct TestUpdate {
 
 impl TestUpdate {
     pub fn new() -> Self {
-        // glb/gltf not working
+        // json, gltf or glb
         let model_path = "res/mesh_data.json";
-        let anim_path = "res/anim_data.json";
-        let camera = CameraController::new();
+        // extra clip files, the model files bring their own animations
+        // (res/anim_data.json is loaded with res/mesh_data.json)
+        let anim_paths: [&str; 0] = [];
+        // MeshVertexFormat::Packed(..) uploads the compressed layout of vertex_packing,
+        // the vertex buffer sizes are printed
+        let vertex_format = MeshVertexFormat::Full;
+        // Some(OptimizeOptions::default()) welds the vertices and reorders them for the
+        // gpu caches before the upload, the reports are printed to stderr
+        let optimize: Option<OptimizeOptions> = None;
+        let mut camera = CameraController::new();
+        // clips shared by every loaded model
+        let mut animation_library = AnimationLibrary::new();
+        animation_library
+            .load_files(&anim_paths)
+            .expect("animation error");
         let mut models: Vec<LoadedModel> = Vec::new();
-        // load model
-        models.push(LoadedModel::new(
-            model_path,
-            anim_path,
-            Transform::identity(),
-        ));
+        // load model, gltf scenes can spawn several models
+        if model_path.ends_with(".gltf") || model_path.ends_with(".glb") {
+            let scene = crate::gltf_loader::load_gltf_scene(model_path).expect("scene error");
+            // look through the first camera of the scene, keep the window aspect ratio
+            if let Some(scene_camera) = scene.cameras.first() {
+                camera.camera = Camera {
+                    aspect_ratio: camera.camera.aspect_ratio,
+                    ..*scene_camera
+                };
+            }
+            models.extend(LoadedModel::spawn_scene(
+                scene,
+                model_path,
+                &mut animation_library,
+                Transform::identity(),
+                vertex_format,
+                optimize,
+            ));
+        } else {
+            models.push(
+                LoadedModel::new(
+                    model_path,
+                    &mut animation_library,
+                    Transform::identity(),
+                    vertex_format,
+                    optimize,
+                )
+                .unwrap_or_else(|err| panic!("model error: {}", err)),
+            );
+        }
         Self {
             last_update_time: Instant::now(),
             camera,
@@ -170,7 +209,20 @@ impl UpdateCallback for TestUpdate {
         self.camera.update(delta_time);
         for model in &mut self.models {
             model.update_camera(&self.camera.camera);
-            model.update(delta_time);
+            model.handle_input();
+        }
+        // sample animations on worker threads, then upload on this thread
+        let mut animators: Vec<&mut ModelAnimator> = self
+            .models
+            .iter_mut()
+            .map(|model| model.animator_mut())
+            .collect();
+        let poses =
+            crate::parallel::map_mut(&mut animators, |animator| animator.evaluate(delta_time));
+        for (model, pose) in self.models.iter_mut().zip(poses) {
+            if let Some(pose) = pose {
+                model.upload_pose(&pose);
+            }
         }
         if crate::input::is_key_just_released(crate::input::KeyCode::Space) {
             println!("Space just released");
@@ -199,12 +251,12 @@ fn animate(
     let mut transforms: HashMap<usize, cgmath::Matrix4<f32>> = HashMap::new();
     //let current_anim_index = 0;
     let skel = mesh.skeleton.as_ref().expect("error skeleton");
-    for bone in animation.bone_keyframes.values() {
+    for (_, bone) in animation.tracks_by_id() {
         calculate_bone_transforms(
             bone,
             &mut transforms,
             current_anim_index,
-            &animation.bone_keyframes,
+            animation,
             &(skel).bones,
         );
     }
@@ -226,7 +278,7 @@ fn calculate_bone_transforms(
     bone: &AnimatedBone,
     transforms: &mut HashMap<usize, cgmath::Matrix4<f32>>,
     current_anim_index: usize,
-    animation_bones: &HashMap<usize, AnimatedBone>,
+    animation: &model::Animation,
     bones: &HashMap<usize, Bone>,
 ) -> Matrix4<f32> {
     // transform already calculated
@@ -252,13 +304,10 @@ fn calculate_bone_transforms(
         } else {
             // calculate parent
             let parent_transform = calculate_bone_transforms(
-                animation_bones
-                    .get_key_value(&(parent_id as usize))
-                    .expect("Parent not found")
-                    .1,
+                animation.track_by_id(parent_id).expect("Parent not found"),
                 transforms,
                 current_anim_index,
-                animation_bones,
+                animation,
                 bones,
             );
 
@@ -0,0 +1,683 @@
+// asset pipeline tool, run `asset_tool help` for the commands
+use rust_renderer::{
+    binary_format::{self, BinaryWriteOptions},
+    bvh_loader::{self, BvhOptions},
+    glb_exporter::{self, GlbExportOptions},
+    gltf_loader, json_exporter,
+    json_schema::{self, AnimationFile},
+    mesh_optimizer::{self, OptimizeOptions},
+    mesh_utils::{self, NormalMode},
+    model::{AnimatedBone, Animation, MeshVertexFormat, Model, Skeleton},
+    model_loader::{self, LoadError, LoaderRegistry},
+    model_report::ModelReport,
+    obj_exporter, obj_loader,
+    vertex_packing::WeightPrecision,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage:
+  asset_tool convert <input> <output> [options]
+      formats: .obj, .gltf/.glb, .json (mesh, its <stem>_anim.json animations are loaded too),
+               .rrb (binary),
+               .bvh (animations only, with --anim), .stl and .ply (meshes only)
+      (.gltf, .stl and .ply are read only, write .glb instead)
+    --anim <file>          add the animations of a .json/.gltf/.glb/.rrb/.bvh file (repeatable)
+    --anim-out <file>      animation file of a .json output (default <output>_anim.json)
+    --normals smooth|flat  regenerate the normals (and the tangents)
+    --tangents             regenerate the tangents
+    --merge                merge the meshes sharing a material
+    --optimize             weld the vertices and reorder them and the triangles for the
+                           gpu caches, prints the acmr before and after
+    --strip-animations     don't write any animation
+    --time-scale <factor>  multiply the key times by factor (default: converts between
+                           the milliseconds of .json/.rrb and the seconds of gltf)
+    --verify               reload a .glb/.rrb output and compare it with the input
+  asset_tool inspect <file> [options]
+      prints the meshes, skeleton, animations and anomalies of a model
+    --anim <file>          also inspect the animations of a .json/.gltf/.glb/.rrb/.bvh file
+    --json <file>          also write the report as json, `-` prints only the json on stdout
+                           (the text report then goes to stderr with the loader messages)
+    --strict               fail when the report has warnings
+    --packed unorm8|unorm16
+                           bone weight precision of the packed vertex buffers the
+                           memory comparison is measured with (default unorm16)
+  asset_tool bench <animation file> [--runs <n>] [--model <file>] [--baseline]
+      load time and peak heap memory of the animations of a .json/.gltf/.glb/.rrb/.bvh file,
+      bound to the skeleton of the model when one is given
+    --baseline             load a .json file the way it was loaded before the streaming
+                           parser: the whole file is parsed first and binding copies every
+                           bound track, to compare against a run without the option
+  asset_tool help
+
+exit codes: 0 success, 1 conversion failed, 2 bad arguments";
+
+// heap usage of the tool, for the bench command
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+impl CountingAllocator {
+    fn add(size: usize) {
+        let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK_ALLOCATED.fetch_max(allocated, Ordering::Relaxed);
+    }
+
+    fn remove(size: usize) {
+        ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            Self::add(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            Self::add(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        Self::remove(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            Self::add(new_size);
+            Self::remove(layout.size());
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// bad arguments exit with 2, failed conversions with 1
+enum ToolError {
+    Usage(String),
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for ToolError {
+    fn from(err: anyhow::Error) -> Self {
+        ToolError::Failed(err)
+    }
+}
+
+impl From<LoadError> for ToolError {
+    fn from(err: LoadError) -> Self {
+        ToolError::Failed(err.into())
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("convert") => convert(&args[1..]),
+        Some("inspect") => inspect(&args[1..]),
+        Some("bench") => bench(&args[1..]),
+        Some("help") | Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(command) => Err(ToolError::Usage(format!("unknown command '{}'", command))),
+        None => Err(ToolError::Usage("missing command".to_string())),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(ToolError::Usage(message)) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            ExitCode::from(2)
+        }
+        Err(ToolError::Failed(err)) => {
+            eprintln!("error: {:#}", err);
+            ExitCode::from(1)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Obj,
+    Gltf,
+    Glb,
+    Json,
+    Binary,
+    // animation only
+    Bvh,
+    // mesh only, read only
+    Stl,
+    Ply,
+}
+
+impl Format {
+    fn from_path(path: &str) -> Result<Self, ToolError> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("obj") => Ok(Format::Obj),
+            Some("gltf") => Ok(Format::Gltf),
+            Some("glb") => Ok(Format::Glb),
+            Some("json") => Ok(Format::Json),
+            Some(binary_format::BINARY_EXTENSION) => Ok(Format::Binary),
+            Some("bvh") => Ok(Format::Bvh),
+            Some("stl") => Ok(Format::Stl),
+            Some("ply") => Ok(Format::Ply),
+            _ => Err(ToolError::Usage(format!("{}: unknown file format", path))),
+        }
+    }
+
+    // length of one unit of the animation key times, in seconds
+    fn time_unit(self) -> f32 {
+        match self {
+            Format::Gltf | Format::Glb | Format::Bvh | Format::Stl | Format::Ply => 1.0,
+            _ => 0.001,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ConvertOptions {
+    input: String,
+    output: String,
+    animation_files: Vec<String>,
+    animation_output: Option<String>,
+    normals: Option<NormalMode>,
+    tangents: bool,
+    merge: bool,
+    optimize: bool,
+    strip_animations: bool,
+    time_scale: Option<f32>,
+    verify: bool,
+}
+
+impl ConvertOptions {
+    fn parse(args: &[String]) -> Result<Self, ToolError> {
+        let mut options = ConvertOptions::default();
+        let mut positional: Vec<String> = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| ToolError::Usage(format!("{} needs a value", name)))
+            };
+            match arg.as_str() {
+                "--anim" => options.animation_files.push(value(arg)?),
+                "--anim-out" => options.animation_output = Some(value(arg)?),
+                "--normals" => {
+                    options.normals = Some(match value(arg)?.as_str() {
+                        "smooth" => NormalMode::Smooth,
+                        "flat" => NormalMode::Flat,
+                        other => {
+                            return Err(ToolError::Usage(format!(
+                                "--normals: expected smooth or flat, found '{}'",
+                                other
+                            )))
+                        }
+                    })
+                }
+                "--tangents" => options.tangents = true,
+                "--merge" => options.merge = true,
+                "--optimize" => options.optimize = true,
+                "--strip-animations" => options.strip_animations = true,
+                "--time-scale" => {
+                    let text = value(arg)?;
+                    let scale = text
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|scale| scale.is_finite() && *scale > 0.0)
+                        .ok_or_else(|| {
+                            ToolError::Usage(format!(
+                                "--time-scale: '{}' is not a positive number",
+                                text
+                            ))
+                        })?;
+                    options.time_scale = Some(scale);
+                }
+                "--verify" => options.verify = true,
+                _ if arg.starts_with("--") => {
+                    return Err(ToolError::Usage(format!("unknown option '{}'", arg)))
+                }
+                _ => positional.push(arg.clone()),
+            }
+        }
+        match <[String; 2]>::try_from(positional) {
+            Ok([input, output]) => {
+                options.input = input;
+                options.output = output;
+                Ok(options)
+            }
+            Err(positional) => Err(ToolError::Usage(format!(
+                "convert takes an input and an output file, found {} file arguments",
+                positional.len()
+            ))),
+        }
+    }
+}
+
+fn convert(args: &[String]) -> Result<(), ToolError> {
+    let options = ConvertOptions::parse(args)?;
+    let output_format = Format::from_path(&options.output)?;
+    match output_format {
+        Format::Gltf => {
+            return Err(ToolError::Usage(
+                "writing .gltf is not supported, write .glb instead".to_string(),
+            ))
+        }
+        Format::Bvh | Format::Stl | Format::Ply => {
+            return Err(ToolError::Usage(format!(
+                "{}: writing this format is not supported",
+                options.output
+            )))
+        }
+        Format::Obj | Format::Json if options.verify => {
+            return Err(ToolError::Usage(
+                "--verify needs a .glb or .rrb output".to_string(),
+            ))
+        }
+        _ => {}
+    }
+    let animation_formats = animation_formats(&options.animation_files)?;
+
+    // load, animation times are converted to the unit of the output
+    let scale_for = |time_unit: f32| {
+        options
+            .time_scale
+            .unwrap_or(time_unit / output_format.time_unit())
+    };
+    let (mut model, mut animations) = load_input(&options.input)?;
+    scale_times(&mut animations, scale_for(1.0));
+    for (path, format) in options.animation_files.iter().zip(animation_formats) {
+        if loaded_with_input(&options.input, path) {
+            continue;
+        }
+        let mut clips = load_animations(path, format)?;
+        scale_times(&mut clips, scale_for(format.time_unit()));
+        animations.extend(clips);
+    }
+    if options.strip_animations {
+        animations.clear();
+    }
+
+    if options.merge {
+        mesh_utils::merge_meshes(&mut model);
+    }
+    for mesh in &mut model.meshes {
+        if let Some(mode) = options.normals {
+            mesh_utils::generate_normals(mesh, mode);
+        }
+        if options.normals.is_some() || options.tangents {
+            mesh_utils::generate_tangents(mesh);
+        }
+    }
+    // last, flat normals split the welded vertices
+    if options.optimize {
+        for report in mesh_optimizer::optimize_model(&mut model, &OptimizeOptions::default()) {
+            println!("{}", report);
+        }
+    }
+
+    write_output(&options, output_format, &model, &animations)?;
+    let skeleton = model.meshes.iter().find_map(|mesh| mesh.skeleton.as_ref());
+    println!(
+        "wrote {}: {} meshes, {} bones, {} materials, {} animations",
+        options.output,
+        model.meshes.len(),
+        skeleton.map_or(0, |skeleton| skeleton.bones_ordered.len()),
+        model.materials.len(),
+        animations.len()
+    );
+    Ok(())
+}
+
+fn inspect(args: &[String]) -> Result<(), ToolError> {
+    let mut input: Option<String> = None;
+    let mut animation_files: Vec<String> = Vec::new();
+    let mut json_output: Option<String> = None;
+    let mut strict = false;
+    let mut packed_weights = WeightPrecision::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| ToolError::Usage(format!("{} needs a value", name)))
+        };
+        match arg.as_str() {
+            "--anim" => animation_files.push(value(arg)?),
+            "--json" => json_output = Some(value(arg)?),
+            "--strict" => strict = true,
+            "--packed" => {
+                packed_weights = match value(arg)?.as_str() {
+                    "unorm8" => WeightPrecision::Unorm8,
+                    "unorm16" => WeightPrecision::Unorm16,
+                    other => {
+                        return Err(ToolError::Usage(format!(
+                            "--packed must be unorm8 or unorm16, not '{}'",
+                            other
+                        )))
+                    }
+                }
+            }
+            _ if arg.starts_with("--") => {
+                return Err(ToolError::Usage(format!("unknown option '{}'", arg)))
+            }
+            _ if input.is_none() => input = Some(arg.clone()),
+            _ => {
+                return Err(ToolError::Usage(
+                    "inspect takes a single model file".to_string(),
+                ))
+            }
+        }
+    }
+    let input = input.ok_or_else(|| ToolError::Usage("inspect needs a model file".to_string()))?;
+    let animation_formats = animation_formats(&animation_files)?;
+
+    // the report times are in seconds
+    let (mut model, mut animations) = load_input(&input)?;
+    for mesh in &mut model.meshes {
+        mesh.vertex_format = MeshVertexFormat::Packed(packed_weights);
+    }
+    for (path, format) in animation_files.iter().zip(animation_formats) {
+        if loaded_with_input(&input, path) {
+            continue;
+        }
+        let mut clips = load_animations(path, format)?;
+        scale_times(&mut clips, format.time_unit());
+        animations.extend(clips);
+    }
+
+    let report = ModelReport::new(&input, &model, &animations, 1.0);
+    // the loaders print their messages to stderr, stdout only has the report
+    match json_output.as_deref() {
+        Some("-") => {
+            eprintln!("{}", report);
+            println!("{}", report.to_json()?);
+        }
+        Some(path) => {
+            println!("{}", report);
+            std::fs::write(path, report.to_json()? + "\n")
+                .map_err(|err| anyhow::anyhow!("{}: can't write file ({})", path, err))?;
+        }
+        None => println!("{}", report),
+    }
+    if strict && !report.warnings.is_empty() {
+        return Err(ToolError::Failed(anyhow::anyhow!(
+            "{}: {} warnings",
+            input,
+            report.warnings.len()
+        )));
+    }
+    Ok(())
+}
+
+fn bench(args: &[String]) -> Result<(), ToolError> {
+    let mut input: Option<String> = None;
+    let mut model_path: Option<String> = None;
+    let mut runs = 5;
+    let mut baseline = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--model" => {
+                model_path = Some(
+                    args.next()
+                        .cloned()
+                        .ok_or_else(|| ToolError::Usage("--model needs a value".to_string()))?,
+                )
+            }
+            "--runs" => {
+                runs = args
+                    .next()
+                    .and_then(|runs| runs.parse::<usize>().ok())
+                    .filter(|runs| *runs > 0)
+                    .ok_or_else(|| ToolError::Usage("--runs needs a positive count".to_string()))?;
+            }
+            "--baseline" => baseline = true,
+            _ if arg.starts_with("--") => {
+                return Err(ToolError::Usage(format!("unknown option '{}'", arg)))
+            }
+            _ if input.is_none() => input = Some(arg.clone()),
+            _ => {
+                return Err(ToolError::Usage(
+                    "bench takes a single animation file".to_string(),
+                ))
+            }
+        }
+    }
+    let input =
+        input.ok_or_else(|| ToolError::Usage("bench needs an animation file".to_string()))?;
+    let format = animation_formats(std::slice::from_ref(&input))?[0];
+    if baseline && format != Format::Json {
+        return Err(ToolError::Usage(
+            "--baseline needs a .json animation file".to_string(),
+        ));
+    }
+    let skeleton = match &model_path {
+        Some(path) => {
+            let (model, _) = load_input(path)?;
+            let skeleton = model.meshes.into_iter().find_map(|mesh| mesh.skeleton);
+            Some(skeleton.ok_or_else(|| anyhow::anyhow!("{}: the model has no skeleton", path))?)
+        }
+        None => None,
+    };
+
+    let mut times: Vec<Duration> = Vec::with_capacity(runs);
+    let mut peak = 0;
+    let mut retained = 0;
+    let mut clip_count = 0;
+    for _ in 0..runs {
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        PEAK_ALLOCATED.store(before, Ordering::Relaxed);
+        let start = Instant::now();
+        // the clips are dropped after the measures
+        let clips: Box<dyn std::any::Any> = if baseline {
+            let clips = load_baseline(&input, skeleton.as_ref())?;
+            clip_count = clips.len();
+            Box::new(clips)
+        } else {
+            let mut clips = load_animations(&input, format)?;
+            if let Some(skeleton) = &skeleton {
+                for clip in &mut clips {
+                    clip.bind_bone_ids(skeleton);
+                }
+            }
+            clip_count = clips.len();
+            Box::new(clips)
+        };
+        times.push(start.elapsed());
+        peak = PEAK_ALLOCATED.load(Ordering::Relaxed) - before;
+        retained = ALLOCATED.load(Ordering::Relaxed) - before;
+        drop(clips);
+    }
+    times.sort();
+    let total: Duration = times.iter().sum();
+    let file_size = std::fs::metadata(&input).map_or(0, |metadata| metadata.len());
+    let mib = |bytes: usize| bytes as f64 / (1024.0 * 1024.0);
+    println!(
+        "{}: {} clips, {:.2} MiB file, {} runs{}",
+        input,
+        clip_count,
+        mib(file_size as usize),
+        runs,
+        if baseline { ", baseline loading" } else { "" }
+    );
+    println!(
+        "load time: min {:.1} ms, median {:.1} ms, mean {:.1} ms",
+        times[0].as_secs_f64() * 1000.0,
+        times[runs / 2].as_secs_f64() * 1000.0,
+        total.as_secs_f64() * 1000.0 / runs as f64
+    );
+    println!(
+        "heap: peak {:.2} MiB while loading, {:.2} MiB for the loaded clips",
+        mib(peak),
+        mib(retained)
+    );
+    Ok(())
+}
+
+// the json clips as they were loaded before the streaming parser, for bench --baseline:
+// the whole file is parsed before the clips are converted, and binding copies the
+// tracks of the skeleton bones into a map by bone id next to the tracks by name
+fn load_baseline(
+    path: &str,
+    skeleton: Option<&Skeleton>,
+) -> anyhow::Result<Vec<(Animation, HashMap<usize, AnimatedBone>)>> {
+    let file: AnimationFile = json_schema::read_json_file(path)?;
+    let clips = file
+        .animations
+        .into_iter()
+        .map(|data| {
+            let clip = obj_loader::json_animation(data);
+            let mut by_id = HashMap::new();
+            for bone in skeleton.iter().flat_map(|skeleton| &skeleton.bones_ordered) {
+                if let Some(track) = clip.track(&bone.name) {
+                    let mut track = track.clone();
+                    track.bone_id = bone.id;
+                    by_id.insert(bone.id as usize, track);
+                }
+            }
+            (clip, by_id)
+        })
+        .collect();
+    Ok(clips)
+}
+
+fn animation_formats(paths: &[String]) -> Result<Vec<Format>, ToolError> {
+    paths
+        .iter()
+        .map(|path| match Format::from_path(path)? {
+            Format::Obj | Format::Stl | Format::Ply => Err(ToolError::Usage(format!(
+                "{}: the format has no animation",
+                path
+            ))),
+            format => Ok(format),
+        })
+        .collect()
+}
+
+// any format the loader registry detects, the clip times are in seconds
+fn load_input(path: &str) -> Result<(Model, Vec<Animation>), LoadError> {
+    LoaderRegistry::default().load(path)
+}
+
+// the animation file of a json input is read by the loader, --anim must not add its clips twice
+fn loaded_with_input(input: &str, path: &str) -> bool {
+    let is_json = LoaderRegistry::default()
+        .find(input)
+        .is_ok_and(|loader| loader.name() == "json");
+    let pair = model_loader::json_animation_path(input).and_then(|pair| pair.canonicalize().ok());
+    let loaded = is_json && pair.is_some() && Path::new(path).canonicalize().ok() == pair;
+    if loaded {
+        eprintln!("{}: already loaded with {}", path, input);
+    }
+    loaded
+}
+
+fn load_animations(path: &str, format: Format) -> anyhow::Result<Vec<Animation>> {
+    match format {
+        Format::Gltf | Format::Glb => gltf_loader::load_gltf_animations(path),
+        Format::Json => obj_loader::json_anim_clips(path),
+        Format::Binary => Ok(binary_format::read_binary(path)?.animations),
+        Format::Bvh => Ok(vec![bvh_loader::load_bvh(path, &BvhOptions::default())?.1]),
+        Format::Obj | Format::Stl | Format::Ply => Ok(Vec::new()),
+    }
+}
+
+fn scale_times(animations: &mut [Animation], scale: f32) {
+    for animation in animations {
+        animation.scale_times(scale);
+    }
+}
+
+fn write_output(
+    options: &ConvertOptions,
+    format: Format,
+    model: &Model,
+    animations: &[Animation],
+) -> anyhow::Result<()> {
+    let path = options.output.as_str();
+    let colored_meshes = model
+        .meshes
+        .iter()
+        .filter(|mesh| mesh_utils::has_vertex_colors(mesh))
+        .count();
+    if colored_meshes > 0 && !matches!(format, Format::Glb | Format::Binary) {
+        println!(
+            "{}: only glb and rrb keep vertex colors, the colors of {} meshes are not written",
+            path, colored_meshes
+        );
+    }
+    match format {
+        Format::Obj => {
+            if model.meshes.iter().any(mesh_utils::is_skinned) || !animations.is_empty() {
+                println!(
+                    "{}: obj has no skeleton, the skin and {} animations are not written",
+                    path,
+                    animations.len()
+                );
+            }
+            obj_exporter::write_obj(path, model)
+        }
+        Format::Json => {
+            if !model.materials.is_empty() {
+                println!(
+                    "{}: the json format has no material, {} materials are not written",
+                    path,
+                    model.materials.len()
+                );
+            }
+            json_exporter::write_json_model(path, model)?;
+            if !animations.is_empty() {
+                let animation_path = options.animation_output.clone().unwrap_or_else(|| {
+                    let stem = path.strip_suffix(".json").unwrap_or(path);
+                    format!("{}_anim.json", stem)
+                });
+                json_exporter::write_json_animations(&animation_path, animations)?;
+                println!("wrote {}: {} animations", animation_path, animations.len());
+            }
+            Ok(())
+        }
+        Format::Glb => {
+            // the times were already converted to seconds
+            let glb_options = GlbExportOptions { time_scale: 1.0 };
+            glb_exporter::write_glb(path, model, animations, &glb_options)?;
+            if options.verify {
+                glb_exporter::verify_glb_export(path, model, animations, &glb_options)?;
+                println!("{}: verified", path);
+            }
+            Ok(())
+        }
+        Format::Binary => {
+            binary_format::write_binary(path, model, animations, &BinaryWriteOptions::default())?;
+            if options.verify {
+                binary_format::verify_binary_file(path, model, animations)?;
+                println!("{}: verified", path);
+            }
+            Ok(())
+        }
+        Format::Gltf | Format::Bvh | Format::Stl | Format::Ply => Err(anyhow::anyhow!(
+            "{}: writing this format is not supported",
+            path
+        )),
+    }
+}
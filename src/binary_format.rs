@@ -0,0 +1,1048 @@
+// binary container for models, skeletons and animations, loads without parsing
+//
+// everything is little-endian:
+//   file header (16 bytes)
+//     magic       b"RRBF"
+//     version     u32
+//     flags       u32, FLAG_CHECKSUMS when the sections have a crc32
+//     count       u32, number of sections
+//   section table, `count` entries of 24 bytes
+//     kind        4 bytes (b"MESH", b"VERT"...)
+//     crc32       u32 of the section data, 0 without FLAG_CHECKSUMS
+//     offset      u64 from the start of the file, aligned to SECTION_ALIGNMENT
+//     length      u64
+//   section data
+//
+// VERT, INDX and COLR sections are the ModelVertex, u32 and [f32; 4] arrays as
+// uploaded to the gpu, the other sections are lists of u32/f32 values and strings
+// (u32 length + utf8)
+use crate::json_schema::check_bone_hierarchy;
+use crate::material::{AlphaMode, ImageData, Material, SamplerInfo, TextureRef};
+use crate::model::{
+    AnimatedBone, Animation, Bone, KeyRotation, KeyScale, KeyTranslation, Mesh, Model, ModelVertex,
+    Skeleton, MAX_BONES,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub const BINARY_MAGIC: [u8; 4] = *b"RRBF";
+// version 2 stores the bone id index of the animations as track names, version 3
+// added the vertex colors, older files have a different layout and are rejected
+// (convert them again from the source)
+pub const BINARY_VERSION: u32 = 3;
+pub const BINARY_EXTENSION: &str = "rrb";
+pub const FLAG_CHECKSUMS: u32 = 1;
+const HEADER_SIZE: usize = 16;
+const SECTION_ENTRY_SIZE: usize = 24;
+const SECTION_ALIGNMENT: usize = 16;
+
+// mesh metadata and the sections holding its data
+const SECTION_MESH: [u8; 4] = *b"MESH";
+const SECTION_VERTICES: [u8; 4] = *b"VERT";
+const SECTION_INDICES: [u8; 4] = *b"INDX";
+const SECTION_COLORS: [u8; 4] = *b"COLR";
+const SECTION_SKELETON: [u8; 4] = *b"SKEL";
+const SECTION_MATERIAL: [u8; 4] = *b"MATL";
+const SECTION_IMAGE: [u8; 4] = *b"IMAG";
+const SECTION_ANIMATION: [u8; 4] = *b"ANIM";
+// u32 written for a missing index
+const NONE_INDEX: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BinaryWriteOptions {
+    pub checksums: bool,
+}
+
+impl Default for BinaryWriteOptions {
+    fn default() -> Self {
+        Self { checksums: true }
+    }
+}
+
+// content of a binary file, animation tracks are indexed as they were written
+#[derive(Default)]
+pub struct BinaryAsset {
+    pub model: Model,
+    pub animations: Vec<Animation>,
+}
+
+// pod arrays as little-endian bytes, every field of the types stored this way
+// (ModelVertex, u32, [f32; 4]) is 4 bytes wide
+fn pod_to_le_bytes<T: bytemuck::Pod>(data: &[T]) -> Vec<u8> {
+    let mut bytes = bytemuck::cast_slice::<T, u8>(data).to_vec();
+    if cfg!(target_endian = "big") {
+        for word in bytes.chunks_exact_mut(4) {
+            word.reverse();
+        }
+    }
+    bytes
+}
+
+// single copy from the file bytes, which may not be aligned for T
+fn le_bytes_to_pod<T: bytemuck::Pod>(bytes: &[u8]) -> Vec<T> {
+    let mut data: Vec<T> = bytemuck::pod_collect_to_vec(bytes);
+    if cfg!(target_endian = "big") {
+        for word in bytemuck::cast_slice_mut::<T, u8>(&mut data).chunks_exact_mut(4) {
+            word.reverse();
+        }
+    }
+    data
+}
+
+#[derive(Default)]
+struct SectionWriter {
+    bytes: Vec<u8>,
+}
+
+impl SectionWriter {
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32s(&mut self, values: &[f32]) {
+        for value in values {
+            self.f32(*value);
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn index(&mut self, value: Option<usize>) {
+        self.u32(value.map_or(NONE_INDEX, |value| value as u32));
+    }
+
+    fn matrix(&mut self, matrix: &[[f32; 4]; 4]) {
+        for column in matrix {
+            self.f32s(column);
+        }
+    }
+}
+
+// bounds checked reads from a section, errors name the section
+struct SectionReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    name: String,
+}
+
+impl<'a> SectionReader<'a> {
+    fn take(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{}: unexpected end of data (reading {} bytes at {})",
+                    self.name,
+                    length,
+                    self.position
+                )
+            })?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> anyhow::Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn f32s<const N: usize>(&mut self) -> anyhow::Result<[f32; N]> {
+        let mut values = [0.0; N];
+        for value in &mut values {
+            *value = self.f32()?;
+        }
+        Ok(values)
+    }
+
+    // element count, checked against the remaining bytes before allocating
+    fn count(&mut self, element_size: usize) -> anyhow::Result<usize> {
+        let count = self.u32()? as usize;
+        if count.saturating_mul(element_size) > self.bytes.len() - self.position {
+            return Err(anyhow::anyhow!(
+                "{}: count {} does not fit in the section",
+                self.name,
+                count
+            ));
+        }
+        Ok(count)
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        let length = self.count(1)?;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| anyhow::anyhow!("{}: string is not utf-8", self.name))
+    }
+
+    fn index(&mut self) -> anyhow::Result<Option<usize>> {
+        let value = self.u32()?;
+        Ok((value != NONE_INDEX).then_some(value as usize))
+    }
+
+    fn matrix(&mut self) -> anyhow::Result<[[f32; 4]; 4]> {
+        Ok([self.f32s()?, self.f32s()?, self.f32s()?, self.f32s()?])
+    }
+}
+
+// write a model and animations, see the top of the file for the layout
+pub fn write_binary<'a>(
+    filepath: &str,
+    model: &Model,
+    animations: impl IntoIterator<Item = &'a Animation>,
+    options: &BinaryWriteOptions,
+) -> anyhow::Result<()> {
+    let bytes = binary_bytes(model, animations, options);
+    std::fs::write(filepath, bytes)
+        .map_err(|err| anyhow::anyhow!("{}: can't write file ({})", filepath, err))
+}
+
+pub fn binary_bytes<'a>(
+    model: &Model,
+    animations: impl IntoIterator<Item = &'a Animation>,
+    options: &BinaryWriteOptions,
+) -> Vec<u8> {
+    let mut sections: Vec<([u8; 4], Vec<u8>)> = Vec::new();
+    for image in &model.images {
+        sections.push((SECTION_IMAGE, image_section(image)));
+    }
+    for material in &model.materials {
+        sections.push((SECTION_MATERIAL, material_section(material)));
+    }
+    for mesh in &model.meshes {
+        // the mesh section points to the sections that follow it
+        let first = sections.len() as u32 + 1;
+        let colors = (!mesh.colors.is_empty()).then_some(first as usize + 2);
+        let skeleton = mesh
+            .skeleton
+            .as_ref()
+            .map(|_| first as usize + 2 + colors.is_some() as usize);
+        let mut section = SectionWriter::default();
+        section.string(&mesh.name);
+        section.index(mesh.material);
+        section.u32(first);
+        section.u32(first + 1);
+        section.index(skeleton);
+        section.index(colors);
+        sections.push((SECTION_MESH, section.bytes));
+        sections.push((SECTION_VERTICES, pod_to_le_bytes(&mesh.vertices)));
+        sections.push((SECTION_INDICES, pod_to_le_bytes(&mesh.indices)));
+        if colors.is_some() {
+            sections.push((SECTION_COLORS, pod_to_le_bytes(&mesh.colors)));
+        }
+        if let Some(skeleton) = &mesh.skeleton {
+            sections.push((SECTION_SKELETON, skeleton_section(skeleton)));
+        }
+    }
+    for animation in animations {
+        sections.push((SECTION_ANIMATION, animation_section(animation)));
+    }
+
+    let table_end = HEADER_SIZE + sections.len() * SECTION_ENTRY_SIZE;
+    let align = |offset: usize| offset.div_ceil(SECTION_ALIGNMENT) * SECTION_ALIGNMENT;
+    let mut bytes = Vec::with_capacity(
+        align(table_end)
+            + sections
+                .iter()
+                .map(|(_, data)| align(data.len()))
+                .sum::<usize>(),
+    );
+    bytes.extend_from_slice(&BINARY_MAGIC);
+    bytes.extend_from_slice(&BINARY_VERSION.to_le_bytes());
+    let flags = if options.checksums { FLAG_CHECKSUMS } else { 0 };
+    bytes.extend_from_slice(&flags.to_le_bytes());
+    bytes.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+    let mut offset = align(table_end);
+    for (kind, data) in &sections {
+        let checksum = if options.checksums {
+            crc32fast::hash(data)
+        } else {
+            0
+        };
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes.extend_from_slice(&(offset as u64).to_le_bytes());
+        bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        offset = align(offset + data.len());
+    }
+    for (_, data) in &sections {
+        bytes.resize(align(bytes.len()), 0);
+        bytes.extend_from_slice(data);
+    }
+    bytes
+}
+
+fn image_section(image: &ImageData) -> Vec<u8> {
+    let mut section = SectionWriter::default();
+    section.string(&image.name);
+    // empty when unknown
+    section.string(image.mime_type.as_deref().unwrap_or_default());
+    section.u32(image.bytes.len() as u32);
+    section.bytes.extend_from_slice(&image.bytes);
+    section.bytes
+}
+
+fn write_texture_ref(section: &mut SectionWriter, texture: &Option<TextureRef>) {
+    let Some(texture) = texture else {
+        section.u32(NONE_INDEX);
+        return;
+    };
+    section.u32(texture.image as u32);
+    section.u32(texture.tex_coord);
+    let filter = |mode: wgpu::FilterMode| match mode {
+        wgpu::FilterMode::Nearest => 0,
+        wgpu::FilterMode::Linear => 1,
+    };
+    let address = |mode: wgpu::AddressMode| match mode {
+        wgpu::AddressMode::ClampToEdge => 0,
+        wgpu::AddressMode::Repeat => 1,
+        wgpu::AddressMode::MirrorRepeat => 2,
+        wgpu::AddressMode::ClampToBorder => 3,
+    };
+    section.u32(filter(texture.sampler.mag_filter));
+    section.u32(filter(texture.sampler.min_filter));
+    section.u32(filter(texture.sampler.mipmap_filter));
+    section.u32(address(texture.sampler.address_mode_u));
+    section.u32(address(texture.sampler.address_mode_v));
+}
+
+fn material_section(material: &Material) -> Vec<u8> {
+    let mut section = SectionWriter::default();
+    section.string(&material.name);
+    section.f32s(&material.base_color_factor);
+    section.f32(material.metallic_factor);
+    section.f32(material.roughness_factor);
+    section.f32(material.normal_scale);
+    section.f32(material.occlusion_strength);
+    section.f32s(&material.emissive_factor);
+    section.u32(match material.alpha_mode {
+        AlphaMode::Opaque => 0,
+        AlphaMode::Mask => 1,
+        AlphaMode::Blend => 2,
+    });
+    section.f32(material.alpha_cutoff);
+    section.u32(material.double_sided as u32);
+    section.f32s(&material.specular_color_factor);
+    for texture in [
+        &material.base_color_texture,
+        &material.metallic_roughness_texture,
+        &material.normal_texture,
+        &material.occlusion_texture,
+        &material.emissive_texture,
+        &material.specular_texture,
+    ] {
+        write_texture_ref(&mut section, texture);
+    }
+    section.bytes
+}
+
+fn skeleton_section(skeleton: &Skeleton) -> Vec<u8> {
+    let mut section = SectionWriter::default();
+    section.string(&skeleton.name);
+    section.u32(skeleton.bones_ordered.len() as u32);
+    for bone in &skeleton.bones_ordered {
+        section.u32(bone.id);
+        section.string(&bone.name);
+        section.index(bone.parent_id);
+        section.u32(bone.index as u32);
+        section.matrix(&bone.inverse_bind_matrix);
+        match &bone.parent_offset {
+            Some(offset) => {
+                section.u32(1);
+                section.matrix(offset);
+            }
+            None => section.u32(0),
+        }
+    }
+    section.bytes
+}
+
+fn write_track(section: &mut SectionWriter, track: &AnimatedBone) {
+    section.u32(track.bone_id);
+    section.string(&track.bone_name);
+    section.index(track.parent_index);
+    section.u32(track.translation_keys.len() as u32);
+    for key in &track.translation_keys {
+        section.f32(key.timestamp);
+        section.f32s(&key.translation);
+    }
+    section.u32(track.rotation_keys.len() as u32);
+    for key in &track.rotation_keys {
+        section.f32(key.timestamp);
+        section.f32s(&key.rotation);
+    }
+    section.u32(track.scale_keys.len() as u32);
+    for key in &track.scale_keys {
+        section.f32(key.timestamp);
+        section.f32s(&key.scale);
+    }
+}
+
+// tracks indexed by name, then tracks indexed by bone id, sorted so the output
+// does not depend on the hash map order
+fn animation_section(animation: &Animation) -> Vec<u8> {
+    let mut section = SectionWriter::default();
+    section.string(&animation.name);
+    let mut named: Vec<&AnimatedBone> = animation.tracks().iter().collect();
+    named.sort_by(|a, b| a.bone_name.cmp(&b.bone_name));
+    section.u32(named.len() as u32);
+    for track in named {
+        write_track(&mut section, track);
+    }
+    let by_id: Vec<(usize, &AnimatedBone)> = animation.tracks_by_id().collect();
+    section.u32(by_id.len() as u32);
+    for (id, track) in by_id {
+        section.u32(id as u32);
+        section.string(&track.bone_name);
+    }
+    section.bytes
+}
+
+pub fn read_binary(filepath: &str) -> anyhow::Result<BinaryAsset> {
+    let bytes = std::fs::read(filepath)
+        .map_err(|err| anyhow::anyhow!("{}: can't read file ({})", filepath, err))?;
+    parse_binary(&bytes).map_err(|err| anyhow::anyhow!("{}: {}", filepath, err))
+}
+
+pub fn parse_binary(bytes: &[u8]) -> anyhow::Result<BinaryAsset> {
+    let mut header = SectionReader {
+        bytes,
+        position: 0,
+        name: "header".to_string(),
+    };
+    if header.take(4)? != BINARY_MAGIC {
+        return Err(anyhow::anyhow!("not a binary model file (bad magic)"));
+    }
+    let version = header.u32()?;
+    if version != BINARY_VERSION {
+        return Err(anyhow::anyhow!(
+            "version {} is not supported (expected {})",
+            version,
+            BINARY_VERSION
+        ));
+    }
+    let flags = header.u32()?;
+    let count = header.u32()? as usize;
+    header.name = "section table".to_string();
+    let mut sections: Vec<([u8; 4], &[u8])> = Vec::with_capacity(count.min(bytes.len()));
+    for index in 0..count {
+        let kind: [u8; 4] = header.take(4)?.try_into()?;
+        let checksum = header.u32()?;
+        let offset = u64::from_le_bytes(header.take(8)?.try_into()?);
+        let length = u64::from_le_bytes(header.take(8)?.try_into()?);
+        let name = section_name(index, kind);
+        let data = usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(length).ok())
+            .and_then(|(offset, length)| bytes.get(offset..offset.checked_add(length)?))
+            .ok_or_else(|| anyhow::anyhow!("{} is outside of the file", name))?;
+        if flags & FLAG_CHECKSUMS != 0 && crc32fast::hash(data) != checksum {
+            return Err(anyhow::anyhow!("{}: checksum mismatch", name));
+        }
+        sections.push((kind, data));
+    }
+    let reader = |index: usize| -> anyhow::Result<SectionReader> {
+        let (kind, data) = sections
+            .get(index)
+            .ok_or_else(|| anyhow::anyhow!("section {} does not exist", index))?;
+        Ok(SectionReader {
+            bytes: data,
+            position: 0,
+            name: section_name(index, *kind),
+        })
+    };
+    let expect_kind = |index: usize, expected: [u8; 4]| -> anyhow::Result<&[u8]> {
+        match sections.get(index) {
+            Some((kind, data)) if *kind == expected => Ok(data),
+            _ => Err(anyhow::anyhow!(
+                "section {} is not a {} section",
+                index,
+                String::from_utf8_lossy(&expected)
+            )),
+        }
+    };
+
+    let mut asset = BinaryAsset::default();
+    // unknown sections are skipped so newer writers can add some
+    for (index, (kind, _)) in sections.iter().enumerate() {
+        let mut section = reader(index)?;
+        match *kind {
+            SECTION_IMAGE => asset.model.images.push(read_image(&mut section)?),
+            SECTION_MATERIAL => asset.model.materials.push(read_material(&mut section)?),
+            SECTION_MESH => {
+                let name = section.string()?;
+                let material = section.index()?;
+                let vertices = expect_kind(section.u32()? as usize, SECTION_VERTICES)?;
+                let indices = expect_kind(section.u32()? as usize, SECTION_INDICES)?;
+                if vertices.len() % std::mem::size_of::<ModelVertex>() != 0
+                    || indices.len() % 4 != 0
+                {
+                    return Err(anyhow::anyhow!(
+                        "{}: vertex or index data has a partial element",
+                        section.name
+                    ));
+                }
+                let skeleton = match section.index()? {
+                    Some(skeleton) => {
+                        expect_kind(skeleton, SECTION_SKELETON)?;
+                        Some(read_skeleton(&mut reader(skeleton)?)?)
+                    }
+                    None => None,
+                };
+                // one color per vertex, or none
+                let colors = match section.index()? {
+                    Some(colors) => {
+                        let colors = expect_kind(colors, SECTION_COLORS)?;
+                        let vertex_count = vertices.len() / std::mem::size_of::<ModelVertex>();
+                        if colors.len() != vertex_count * std::mem::size_of::<[f32; 4]>() {
+                            return Err(anyhow::anyhow!(
+                                "{}: color count does not match the vertex count",
+                                section.name
+                            ));
+                        }
+                        le_bytes_to_pod(colors)
+                    }
+                    None => Vec::new(),
+                };
+                asset.model.meshes.push(Mesh {
+                    name,
+                    vertices: le_bytes_to_pod(vertices),
+                    indices: le_bytes_to_pod(indices),
+                    skeleton,
+                    material,
+                    colors,
+                    vertex_format: Default::default(),
+                });
+            }
+            SECTION_ANIMATION => asset.animations.push(read_animation(&mut section)?),
+            _ => {}
+        }
+    }
+    Ok(asset)
+}
+
+fn section_name(index: usize, kind: [u8; 4]) -> String {
+    format!("section {} ({})", index, String::from_utf8_lossy(&kind))
+}
+
+fn read_image(section: &mut SectionReader) -> anyhow::Result<Arc<ImageData>> {
+    let name = section.string()?;
+    let mime_type = section.string()?;
+    let length = section.count(1)?;
+    Ok(Arc::new(ImageData {
+        name,
+        mime_type: (!mime_type.is_empty()).then_some(mime_type),
+        bytes: section.take(length)?.to_vec(),
+    }))
+}
+
+fn read_texture_ref(section: &mut SectionReader) -> anyhow::Result<Option<TextureRef>> {
+    let Some(image) = section.index()? else {
+        return Ok(None);
+    };
+    let tex_coord = section.u32()?;
+    let mut filter = || -> anyhow::Result<wgpu::FilterMode> {
+        match section.u32()? {
+            0 => Ok(wgpu::FilterMode::Nearest),
+            1 => Ok(wgpu::FilterMode::Linear),
+            other => Err(anyhow::anyhow!("unknown filter mode {}", other)),
+        }
+    };
+    let (mag_filter, min_filter, mipmap_filter) = (filter()?, filter()?, filter()?);
+    let mut address = || -> anyhow::Result<wgpu::AddressMode> {
+        match section.u32()? {
+            0 => Ok(wgpu::AddressMode::ClampToEdge),
+            1 => Ok(wgpu::AddressMode::Repeat),
+            2 => Ok(wgpu::AddressMode::MirrorRepeat),
+            3 => Ok(wgpu::AddressMode::ClampToBorder),
+            other => Err(anyhow::anyhow!("unknown address mode {}", other)),
+        }
+    };
+    let (address_mode_u, address_mode_v) = (address()?, address()?);
+    Ok(Some(TextureRef {
+        image,
+        tex_coord,
+        sampler: SamplerInfo {
+            mag_filter,
+            min_filter,
+            mipmap_filter,
+            address_mode_u,
+            address_mode_v,
+        },
+    }))
+}
+
+fn read_material(section: &mut SectionReader) -> anyhow::Result<Material> {
+    let mut material = Material {
+        name: section.string()?,
+        base_color_factor: section.f32s()?,
+        metallic_factor: section.f32()?,
+        roughness_factor: section.f32()?,
+        normal_scale: section.f32()?,
+        occlusion_strength: section.f32()?,
+        emissive_factor: section.f32s()?,
+        alpha_mode: match section.u32()? {
+            0 => AlphaMode::Opaque,
+            1 => AlphaMode::Mask,
+            2 => AlphaMode::Blend,
+            other => {
+                return Err(anyhow::anyhow!(
+                    "{}: unknown alpha mode {}",
+                    section.name,
+                    other
+                ))
+            }
+        },
+        alpha_cutoff: section.f32()?,
+        double_sided: section.u32()? != 0,
+        specular_color_factor: section.f32s()?,
+        ..Default::default()
+    };
+    for texture in [
+        &mut material.base_color_texture,
+        &mut material.metallic_roughness_texture,
+        &mut material.normal_texture,
+        &mut material.occlusion_texture,
+        &mut material.emissive_texture,
+        &mut material.specular_texture,
+    ] {
+        *texture = read_texture_ref(section)
+            .map_err(|err| anyhow::anyhow!("{}: {}", section.name, err))?;
+    }
+    Ok(material)
+}
+
+fn read_skeleton(section: &mut SectionReader) -> anyhow::Result<Skeleton> {
+    let name = section.string()?;
+    let count = section.count(4)?;
+    if count > MAX_BONES {
+        return Err(anyhow::anyhow!(
+            "{}: skeleton '{}' has {} bones, at most {} are supported",
+            section.name,
+            name,
+            count,
+            MAX_BONES
+        ));
+    }
+    let mut bones_ordered: Vec<Bone> = Vec::with_capacity(count);
+    for _ in 0..count {
+        let id = section.u32()?;
+        let name = section.string()?;
+        let parent_id = section.index()?;
+        let index = section.u32()? as usize;
+        let inverse_bind_matrix = section.matrix()?;
+        let parent_offset = match section.u32()? {
+            0 => None,
+            _ => Some(section.matrix()?),
+        };
+        bones_ordered.push(Bone {
+            id,
+            name,
+            parent_id,
+            inverse_bind_matrix,
+            index,
+            parent_offset,
+        });
+    }
+    // the json checks, then the order the animation player walks the bones in
+    let hierarchy: Vec<(u32, i64)> = bones_ordered
+        .iter()
+        .map(|bone| (bone.id, bone.parent_id.map_or(-1, |parent| parent as i64)))
+        .collect();
+    check_bone_hierarchy(&hierarchy)
+        .map_err(|err| anyhow::anyhow!("{}: skeleton '{}': {}", section.name, name, err))?;
+    let mut placed = vec![false; count];
+    for (position, bone) in bones_ordered.iter().enumerate() {
+        if let Some(parent) = bone.parent_id.filter(|parent| !placed[*parent]) {
+            return Err(anyhow::anyhow!(
+                "{}: skeleton '{}': Bones[{}]: bone {} comes before its parent {}",
+                section.name,
+                name,
+                position,
+                bone.id,
+                parent
+            ));
+        }
+        placed[bone.id as usize] = true;
+    }
+    let bones: HashMap<usize, Bone> = bones_ordered
+        .iter()
+        .map(|bone| (bone.id as usize, bone.clone()))
+        .collect();
+    Ok(Skeleton {
+        name,
+        bones,
+        bones_ordered,
+    })
+}
+
+fn read_track(section: &mut SectionReader) -> anyhow::Result<AnimatedBone> {
+    let bone_id = section.u32()?;
+    let bone_name = section.string()?;
+    let parent_index = section.index()?;
+    let mut translation_keys = Vec::new();
+    for _ in 0..section.count(16)? {
+        let timestamp = section.f32()?;
+        let translation = section.f32s()?;
+        translation_keys.push(KeyTranslation {
+            timestamp,
+            translation,
+        });
+    }
+    let mut rotation_keys = Vec::new();
+    for _ in 0..section.count(20)? {
+        let timestamp = section.f32()?;
+        let rotation = section.f32s()?;
+        rotation_keys.push(KeyRotation {
+            timestamp,
+            rotation,
+        });
+    }
+    let mut scale_keys = Vec::new();
+    for _ in 0..section.count(16)? {
+        let timestamp = section.f32()?;
+        let scale = section.f32s()?;
+        scale_keys.push(KeyScale { timestamp, scale });
+    }
+    Ok(AnimatedBone {
+        bone_id,
+        bone_name,
+        parent_index,
+        translation_keys,
+        rotation_keys,
+        scale_keys,
+    })
+}
+
+fn read_animation(section: &mut SectionReader) -> anyhow::Result<Animation> {
+    let mut animation = Animation::new(&section.string()?);
+    for _ in 0..section.count(4)? {
+        animation.add_track(read_track(section)?);
+    }
+    for _ in 0..section.count(8)? {
+        let id = section.u32()? as usize;
+        let name = section.string()?;
+        // the ids index a table, a corrupt one must not size it
+        if id >= MAX_BONES {
+            return Err(anyhow::anyhow!(
+                "animation '{}': bone id {} is out of range (at most {} bones)",
+                animation.name,
+                id,
+                MAX_BONES
+            ));
+        }
+        if !animation.bind_track_id(id, &name) {
+            return Err(anyhow::anyhow!(
+                "animation '{}': bone {} has no track named '{}'",
+                animation.name,
+                id,
+                name
+            ));
+        }
+    }
+    Ok(animation)
+}
+
+// read the file back and check it holds exactly the model and animations
+pub fn verify_binary_file<'a>(
+    filepath: &str,
+    model: &Model,
+    animations: impl IntoIterator<Item = &'a Animation>,
+) -> anyhow::Result<()> {
+    let asset = read_binary(filepath)?;
+    let mismatch = |what: String| anyhow::anyhow!("{}: {} differs after reloading", filepath, what);
+    if asset.model.images != model.images {
+        return Err(mismatch("images".to_string()));
+    }
+    if asset.model.materials != model.materials {
+        return Err(mismatch("materials".to_string()));
+    }
+    if asset.model.meshes.len() != model.meshes.len() {
+        return Err(mismatch("mesh count".to_string()));
+    }
+    for (mesh, loaded) in model.meshes.iter().zip(&asset.model.meshes) {
+        let same_skeleton = match (&mesh.skeleton, &loaded.skeleton) {
+            (Some(a), Some(b)) => a.name == b.name && a.bones_ordered == b.bones_ordered,
+            (None, None) => true,
+            _ => false,
+        };
+        if mesh.name != loaded.name
+            || mesh.material != loaded.material
+            || mesh.vertices != loaded.vertices
+            || mesh.indices != loaded.indices
+            || mesh.colors != loaded.colors
+            || !same_skeleton
+        {
+            return Err(mismatch(format!("mesh '{}'", mesh.name)));
+        }
+    }
+    let animations: Vec<&Animation> = animations.into_iter().collect();
+    if animations.len() != asset.animations.len() {
+        return Err(mismatch("animation count".to_string()));
+    }
+    for (animation, loaded) in animations.iter().zip(&asset.animations) {
+        let same_named = animation.tracks().len() == loaded.tracks().len()
+            && animation.tracks().iter().all(|track| {
+                loaded
+                    .track(&track.bone_name)
+                    .is_some_and(|other| same_track(track, other))
+            });
+        let bound_ids = |animation: &Animation| -> Vec<(usize, String)> {
+            animation
+                .tracks_by_id()
+                .map(|(id, track)| (id, track.bone_name.clone()))
+                .collect()
+        };
+        if animation.name != loaded.name || !same_named || bound_ids(animation) != bound_ids(loaded)
+        {
+            return Err(mismatch(format!("animation '{}'", animation.name)));
+        }
+    }
+    Ok(())
+}
+
+fn same_track(a: &AnimatedBone, b: &AnimatedBone) -> bool {
+    a.bone_id == b.bone_id
+        && a.bone_name == b.bone_name
+        && a.parent_index == b.parent_index
+        && a.translation_keys.len() == b.translation_keys.len()
+        && a.rotation_keys.len() == b.rotation_keys.len()
+        && a.scale_keys.len() == b.scale_keys.len()
+        && a.translation_keys
+            .iter()
+            .zip(&b.translation_keys)
+            .all(|(a, b)| a.timestamp == b.timestamp && a.translation == b.translation)
+        && a.rotation_keys
+            .iter()
+            .zip(&b.rotation_keys)
+            .all(|(a, b)| a.timestamp == b.timestamp && a.rotation == b.rotation)
+        && a.scale_keys
+            .iter()
+            .zip(&b.scale_keys)
+            .all(|(a, b)| a.timestamp == b.timestamp && a.scale == b.scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj_loader::load_json_obj;
+
+    // the json assets, parsed once for every test
+    fn json_assets() -> &'static (Model, Vec<Animation>) {
+        static ASSETS: std::sync::OnceLock<(Model, Vec<Animation>)> = std::sync::OnceLock::new();
+        ASSETS.get_or_init(|| load_json_obj("res/mesh_data.json", "res/anim_data.json").unwrap())
+    }
+
+    fn json_asset_bytes() -> (&'static Model, &'static [Animation], Vec<u8>) {
+        let (model, animations) = json_assets();
+        let bytes = binary_bytes(model, animations, &BinaryWriteOptions::default());
+        (model, animations, bytes)
+    }
+
+    #[test]
+    fn json_assets_round_trip() {
+        let (model, animations, bytes) = json_asset_bytes();
+        let asset = parse_binary(&bytes).unwrap();
+
+        assert_eq!(asset.model.meshes.len(), model.meshes.len());
+        for (mesh, loaded) in model.meshes.iter().zip(&asset.model.meshes) {
+            assert_eq!(mesh.name, loaded.name);
+            assert_eq!(mesh.vertices, loaded.vertices);
+            assert_eq!(mesh.indices, loaded.indices);
+            assert_eq!(mesh.material, loaded.material);
+            match (&mesh.skeleton, &loaded.skeleton) {
+                (Some(skeleton), Some(loaded)) => {
+                    assert_eq!(skeleton.name, loaded.name);
+                    assert_eq!(skeleton.bones_ordered, loaded.bones_ordered);
+                    assert_eq!(skeleton.bones, loaded.bones);
+                }
+                (None, None) => {}
+                _ => panic!("mesh '{}': skeleton was not preserved", mesh.name),
+            }
+        }
+        assert!(model.meshes[0].skeleton.is_some());
+
+        assert!(!animations.is_empty());
+        assert_eq!(asset.animations.len(), animations.len());
+        for (animation, loaded) in animations.iter().zip(&asset.animations) {
+            assert_eq!(animation.name, loaded.name);
+            assert_eq!(animation.tracks().len(), loaded.tracks().len());
+            for track in animation.tracks() {
+                let name = &track.bone_name;
+                assert_eq!(Some(track), loaded.track(name), "{}", name);
+            }
+            assert!(animation.tracks_by_id().eq(loaded.tracks_by_id()));
+        }
+    }
+
+    #[test]
+    fn vertex_colors_round_trip() {
+        let (source, _) = json_assets();
+        let vertices = source.meshes[0].vertices.clone();
+        let colors: Vec<[f32; 4]> = (0..vertices.len())
+            .map(|index| [index as f32 / vertices.len() as f32, 0.5, 0.25, 1.0])
+            .collect();
+        let mut model = Model::default();
+        model.meshes.push(Mesh {
+            name: "colored".to_string(),
+            vertices: vertices.clone(),
+            indices: source.meshes[0].indices.clone(),
+            colors: colors.clone(),
+            ..Default::default()
+        });
+        model.meshes.push(Mesh {
+            name: "plain".to_string(),
+            vertices,
+            indices: source.meshes[0].indices.clone(),
+            ..Default::default()
+        });
+        let path = std::env::temp_dir().join(format!("colors_{}.rrb", std::process::id()));
+        let path = path.to_string_lossy();
+        write_binary(&path, &model, [], &BinaryWriteOptions::default()).unwrap();
+        let asset = read_binary(&path);
+        let verified = verify_binary_file(&path, &model, []);
+        // the same file without the colors of the input
+        model.meshes[0].colors.clear();
+        let dropped = verify_binary_file(&path, &model, []);
+        std::fs::remove_file(path.as_ref()).unwrap();
+
+        let asset = asset.unwrap();
+        assert_eq!(asset.model.meshes[0].colors, colors);
+        assert!(asset.model.meshes[1].colors.is_empty());
+        verified.unwrap();
+        let err = dropped.err().unwrap().to_string();
+        assert!(err.contains("mesh 'colored'"), "{}", err);
+    }
+
+    #[test]
+    fn truncated_file_is_an_error() {
+        let (_, _, bytes) = json_asset_bytes();
+        // every cut in the header, then cuts spread over the table and sections
+        let step = bytes.len() / 200 + 1;
+        for length in (0..HEADER_SIZE).chain((HEADER_SIZE..bytes.len()).step_by(step)) {
+            assert!(parse_binary(&bytes[..length]).is_err(), "{} bytes", length);
+        }
+    }
+
+    #[test]
+    fn bad_section_checksum_is_an_error() {
+        let (_, _, mut bytes) = json_asset_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        let err = parse_binary(&bytes).err().unwrap().to_string();
+        assert!(err.contains("checksum mismatch"), "{}", err);
+
+        // without checksums the reader can't tell, but still must not panic
+        let (model, animations) = json_assets();
+        let options = BinaryWriteOptions { checksums: false };
+        let mut bytes = binary_bytes(model, animations, &options);
+        for index in (HEADER_SIZE..bytes.len()).step_by(37) {
+            bytes[index] ^= 0x5a;
+        }
+        let _ = parse_binary(&bytes);
+    }
+
+    #[test]
+    fn old_version_is_an_error() {
+        let (_, _, mut bytes) = json_asset_bytes();
+        bytes[4..8].copy_from_slice(&1u32.to_le_bytes());
+        let err = parse_binary(&bytes).err().unwrap().to_string();
+        assert!(err.contains("version 1 is not supported"), "{}", err);
+    }
+
+    // (id, parent id) of a bone
+    type BoneLink = (u32, Option<usize>);
+
+    // a one mesh model whose skeleton has the given bones
+    fn skeleton_bytes(bones: &[BoneLink]) -> Vec<u8> {
+        let bones: Vec<Bone> = bones
+            .iter()
+            .map(|(id, parent_id)| Bone {
+                id: *id,
+                name: format!("bone_{}", id),
+                parent_id: *parent_id,
+                index: *id as usize,
+                ..Default::default()
+            })
+            .collect();
+        let mut model = Model::default();
+        model.meshes.push(Mesh {
+            name: "skinned".to_string(),
+            skeleton: Some(Skeleton {
+                name: "rig".to_string(),
+                bones: HashMap::new(),
+                bones_ordered: bones,
+            }),
+            ..Default::default()
+        });
+        binary_bytes(&model, [], &BinaryWriteOptions::default())
+    }
+
+    #[test]
+    fn corrupt_skeleton_is_an_error() {
+        parse_binary(&skeleton_bytes(&[(0, None), (1, Some(0)), (2, Some(0))])).unwrap();
+        let cases: [(&[BoneLink], &str); 5] = [
+            (
+                &[(0, None), (0, Some(0))],
+                "Bones[1].Id: id 0 is used by another bone",
+            ),
+            (
+                &[(0, None), (7, Some(0))],
+                "Bones[1].Id: id 7 is out of range (2 bones)",
+            ),
+            (
+                &[(0, None), (1, Some(2))],
+                "Bones[1].ParentId: parent 2 is out of range",
+            ),
+            (&[(0, Some(1)), (1, Some(0))], "is its own ancestor"),
+            (
+                &[(1, Some(0)), (0, None)],
+                "Bones[0]: bone 1 comes before its parent 0",
+            ),
+        ];
+        for (bones, expected) in cases {
+            let err = parse_binary(&skeleton_bytes(bones))
+                .err()
+                .unwrap()
+                .to_string();
+            assert!(err.contains("skeleton 'rig'"), "{}", err);
+            assert!(err.contains(expected), "{}", err);
+        }
+        let too_many: Vec<BoneLink> = (0..=MAX_BONES as u32).map(|id| (id, None)).collect();
+        let err = parse_binary(&skeleton_bytes(&too_many))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("at most"), "{}", err);
+    }
+
+    #[test]
+    fn bone_id_out_of_range_is_an_error() {
+        let mut animation = Animation::new("clip");
+        animation.add_track(AnimatedBone {
+            bone_name: "hips".to_string(),
+            ..Default::default()
+        });
+        assert!(animation.bind_track_id(MAX_BONES, "hips"));
+        let bytes = binary_bytes(
+            &Model::default(),
+            [&animation],
+            &BinaryWriteOptions::default(),
+        );
+        let err = parse_binary(&bytes).err().unwrap().to_string();
+        let expected = format!("bone id {} is out of range", MAX_BONES);
+        assert!(err.contains(&expected), "{}", err);
+    }
+}
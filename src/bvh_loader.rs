@@ -0,0 +1,377 @@
+// biovision hierarchy (bvh) motion capture files: the HIERARCHY section gives
+// the skeleton, the MOTION section one value per channel and frame
+use crate::model::{
+    AnimatedBone, Animation, Bone, KeyRotation, KeyScale, KeyTranslation, Skeleton, MAX_BONES,
+};
+use crate::tokens::Tokens;
+use cgmath::{Deg, Matrix4, Quaternion, Rotation3, Vector3};
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BvhOptions {
+    // applied to the offsets and positions, e.g. 0.01 for files in centimeters
+    pub scale: f32,
+    // joint name -> bone name, to match the bones of a model loaded from another
+    // format (joints not in the map keep their name)
+    pub bone_names: HashMap<String, String>,
+}
+
+impl Default for BvhOptions {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            bone_names: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Channel {
+    // axis 0, 1, 2 for x, y, z
+    Position(usize),
+    Rotation(usize),
+}
+
+#[derive(Debug)]
+struct Joint {
+    name: String,
+    parent: Option<usize>,
+    offset: [f32; 3],
+    channels: Vec<Channel>,
+}
+
+fn parse_channel(tokens: &Tokens, word: &str) -> anyhow::Result<Channel> {
+    let axis = match word
+        .get(..1)
+        .map(|axis| axis.to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("x") => 0,
+        Some("y") => 1,
+        Some("z") => 2,
+        _ => return Err(tokens.error(format!("unknown channel '{}'", word))),
+    };
+    match word[1..].to_ascii_lowercase().as_str() {
+        "position" => Ok(Channel::Position(axis)),
+        "rotation" => Ok(Channel::Rotation(axis)),
+        _ => Err(tokens.error(format!("unknown channel '{}'", word))),
+    }
+}
+
+// the block of a ROOT or JOINT, after its name
+fn parse_joint(
+    tokens: &mut Tokens,
+    joints: &mut Vec<Joint>,
+    name: &str,
+    parent: Option<usize>,
+) -> anyhow::Result<()> {
+    let index = joints.len();
+    joints.push(Joint {
+        name: name.to_string(),
+        parent,
+        offset: [0.0; 3],
+        channels: Vec::new(),
+    });
+    tokens.expect("{")?;
+    loop {
+        let word = tokens.next("OFFSET, CHANNELS, JOINT, End Site or }")?;
+        match word.to_ascii_uppercase().as_str() {
+            "OFFSET" => {
+                for axis in 0..3 {
+                    joints[index].offset[axis] = tokens.number("an offset")?;
+                }
+            }
+            "CHANNELS" => {
+                let count: usize = tokens.number("a channel count")?;
+                if count > 6 {
+                    return Err(tokens.error(format!("joint '{}' has {} channels", name, count)));
+                }
+                let mut channels = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let channel = tokens.next("a channel")?;
+                    channels.push(parse_channel(tokens, channel)?);
+                }
+                joints[index].channels = channels;
+            }
+            "JOINT" => {
+                let child = tokens.next("a joint name")?;
+                parse_joint(tokens, joints, child, Some(index))?;
+            }
+            // end sites only give the length of the last bone, they are not animated
+            "END" => {
+                tokens.expect("Site")?;
+                tokens.expect("{")?;
+                tokens.expect("OFFSET")?;
+                for _ in 0..3 {
+                    tokens.number::<f32>("an offset")?;
+                }
+                tokens.expect("}")?;
+            }
+            "}" => return Ok(()),
+            _ => {
+                return Err(tokens.error(format!(
+                    "expected OFFSET, CHANNELS, JOINT, End Site or }}, found '{}'",
+                    word
+                )))
+            }
+        }
+    }
+}
+
+// channel values of a joint: translation from the offset with the position
+// channels replacing their axis, rotation composed in channel order
+fn joint_pose(joint: &Joint, values: &[f32], scale: f32) -> ([f32; 3], Quaternion<f32>) {
+    let mut translation = joint.offset.map(|value| value * scale);
+    let mut rotation = Quaternion::new(1.0, 0.0, 0.0, 0.0);
+    for (channel, value) in joint.channels.iter().zip(values) {
+        match *channel {
+            Channel::Position(axis) => translation[axis] = value * scale,
+            Channel::Rotation(axis) => {
+                let axis_vector = match axis {
+                    0 => Vector3::unit_x(),
+                    1 => Vector3::unit_y(),
+                    _ => Vector3::unit_z(),
+                };
+                rotation = rotation * Quaternion::from_axis_angle(axis_vector, Deg(*value));
+            }
+        }
+    }
+    (translation, rotation)
+}
+
+// skeleton in rest pose (offsets only) and the motion as a clip named `name`,
+// key times are in seconds
+pub fn parse_bvh(
+    text: &str,
+    name: &str,
+    options: &BvhOptions,
+) -> anyhow::Result<(Skeleton, Animation)> {
+    let mut tokens = Tokens::new(text);
+    tokens.expect("HIERARCHY")?;
+    let mut joints: Vec<Joint> = Vec::new();
+    while tokens
+        .peek()
+        .is_some_and(|word| word.eq_ignore_ascii_case("ROOT"))
+    {
+        tokens.next("ROOT")?;
+        let root = tokens.next("a joint name")?;
+        parse_joint(&mut tokens, &mut joints, root, None)?;
+    }
+    if joints.is_empty() {
+        return Err(tokens.error("HIERARCHY has no ROOT joint".to_string()));
+    }
+    if joints.len() > MAX_BONES {
+        return Err(anyhow::anyhow!(
+            "{} joints, at most {} are supported",
+            joints.len(),
+            MAX_BONES
+        ));
+    }
+    tokens.expect("MOTION")?;
+    tokens.expect("Frames:")?;
+    let frame_count: usize = tokens.number("a frame count")?;
+    tokens.expect("Frame")?;
+    tokens.expect("Time:")?;
+    let frame_time: f32 = tokens.number("a frame time")?;
+    if frame_count == 0 {
+        return Err(tokens.error("MOTION has no frame".to_string()));
+    }
+    if !frame_time.is_finite() || frame_time <= 0.0 {
+        return Err(tokens.error(format!("invalid frame time {}", frame_time)));
+    }
+
+    let bone_name = |joint: &Joint| {
+        options
+            .bone_names
+            .get(&joint.name)
+            .cloned()
+            .unwrap_or_else(|| joint.name.clone())
+    };
+    let mut tracks: Vec<AnimatedBone> = joints
+        .iter()
+        .enumerate()
+        .map(|(id, joint)| AnimatedBone {
+            bone_id: id as u32,
+            bone_name: bone_name(joint),
+            parent_index: joint.parent,
+            translation_keys: Vec::with_capacity(frame_count),
+            rotation_keys: Vec::with_capacity(frame_count),
+            scale_keys: Vec::with_capacity(frame_count),
+        })
+        .collect();
+    let mut values: Vec<f32> = Vec::with_capacity(6);
+    for frame in 0..frame_count {
+        let timestamp = frame as f32 * frame_time;
+        for (joint, track) in joints.iter().zip(&mut tracks) {
+            values.clear();
+            for _ in 0..joint.channels.len() {
+                values.push(tokens.number("a channel value")?);
+            }
+            let (translation, rotation) = joint_pose(joint, &values, options.scale);
+            track.translation_keys.push(KeyTranslation {
+                timestamp,
+                translation,
+            });
+            track.rotation_keys.push(KeyRotation {
+                timestamp,
+                rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+            });
+            track.scale_keys.push(KeyScale {
+                timestamp,
+                scale: [1.0, 1.0, 1.0],
+            });
+        }
+    }
+    if let Some(word) = tokens.peek() {
+        tokens.next("the end of the file")?;
+        return Err(tokens.error(format!(
+            "unexpected '{}' after the {} frames",
+            word, frame_count
+        )));
+    }
+
+    // the joints are listed parents first, their order gives the bone ids
+    let mut globals: Vec<Matrix4<f32>> = Vec::with_capacity(joints.len());
+    let mut bones: Vec<Bone> = Vec::with_capacity(joints.len());
+    for (id, joint) in joints.iter().enumerate() {
+        let local = Matrix4::from_translation(Vector3::from(joint.offset) * options.scale);
+        let global = match joint.parent {
+            Some(parent) => globals[parent] * local,
+            None => local,
+        };
+        globals.push(global);
+        let rest_position = global.w.truncate();
+        bones.push(Bone {
+            id: id as u32,
+            name: bone_name(joint),
+            parent_id: joint.parent,
+            inverse_bind_matrix: Matrix4::from_translation(-rest_position).into(),
+            index: id,
+            parent_offset: None,
+        });
+    }
+    let skeleton = Skeleton {
+        name: name.to_string(),
+        bones: bones.iter().cloned().enumerate().collect(),
+        bones_ordered: bones,
+    };
+    let mut animation = Animation::new(name);
+    for track in tracks {
+        animation.add_track(track);
+    }
+    animation.bind_bone_ids(&skeleton);
+    Ok((skeleton, animation))
+}
+
+// the clip is named after the file
+pub fn load_bvh(path: &str, options: &BvhOptions) -> anyhow::Result<(Skeleton, Animation)> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("{}: can't read file ({})", path, err))?;
+    let name = Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("bvh");
+    parse_bvh(&text, name, options).map_err(|err| anyhow::anyhow!("{}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{InnerSpace, Rotation};
+
+    // a 6 channel root with ZXY rotations and a 3 channel child with XYZ rotations
+    const TWO_JOINTS: &str = "HIERARCHY
+ROOT hips
+{
+    OFFSET 1 2 3
+    CHANNELS 6 Xposition Yposition Zposition Zrotation Xrotation Yrotation
+    JOINT knee
+    {
+        OFFSET 0 -4 0
+        CHANNELS 3 Xrotation Yrotation Zrotation
+        End Site
+        {
+            OFFSET 0 -4 0
+        }
+    }
+}
+MOTION
+Frames: 2
+Frame Time: 0.5
+1 2 3 0 0 0 0 0 0
+5 6 7 90 90 0 90 0 90
+";
+
+    fn rotate(rotation: [f32; 4], vector: [f32; 3]) -> Vector3<f32> {
+        Quaternion::from(rotation).rotate_vector(Vector3::from(vector))
+    }
+
+    fn assert_near(a: Vector3<f32>, b: [f32; 3]) {
+        assert!(
+            (a - Vector3::from(b)).magnitude() < 1e-5,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn two_joint_hierarchy() {
+        let (skeleton, animation) = parse_bvh(TWO_JOINTS, "walk", &BvhOptions::default()).unwrap();
+        let names: Vec<&str> = skeleton
+            .bones_ordered
+            .iter()
+            .map(|bone| bone.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["hips", "knee"]);
+        assert_eq!(skeleton.bones_ordered[0].parent_id, None);
+        assert_eq!(skeleton.bones_ordered[1].parent_id, Some(0));
+        // the rest pose adds up the offsets, the inverse bind matrices undo it
+        let rest = |bone: usize| {
+            -cgmath::Vector4::from(skeleton.bones_ordered[bone].inverse_bind_matrix[3]).truncate()
+        };
+        assert_near(rest(0), [1.0, 2.0, 3.0]);
+        assert_near(rest(1), [1.0, -2.0, 3.0]);
+
+        let hips = animation.track("hips").unwrap();
+        let knee = animation.track("knee").unwrap();
+        assert_eq!(hips.translation_keys.len(), 2);
+        assert_eq!(hips.translation_keys[1].timestamp, 0.5);
+        // the position channels replace the offset, the knee keeps its offset
+        assert_eq!(hips.translation_keys[1].translation, [5.0, 6.0, 7.0]);
+        assert_eq!(knee.translation_keys[1].translation, [0.0, -4.0, 0.0]);
+        assert_near(
+            rotate(hips.rotation_keys[0].rotation, [1.0, 0.0, 0.0]),
+            [1.0, 0.0, 0.0],
+        );
+        // hips: Z then X in channel order, R = Rz(90) · Rx(90)
+        let hips_rotation = hips.rotation_keys[1].rotation;
+        assert_near(rotate(hips_rotation, [1.0, 0.0, 0.0]), [0.0, 1.0, 0.0]);
+        assert_near(rotate(hips_rotation, [0.0, 1.0, 0.0]), [0.0, 0.0, 1.0]);
+        // knee: X then Z, R = Rx(90) · Rz(90)
+        let knee_rotation = knee.rotation_keys[1].rotation;
+        assert_near(rotate(knee_rotation, [1.0, 0.0, 0.0]), [0.0, 0.0, 1.0]);
+        assert_near(rotate(knee_rotation, [0.0, 0.0, 1.0]), [0.0, -1.0, 0.0]);
+    }
+
+    #[test]
+    fn scale_and_bone_names() {
+        let options = BvhOptions {
+            scale: 0.5,
+            bone_names: [("knee".to_string(), "leg_lower".to_string())].into(),
+        };
+        let (skeleton, animation) = parse_bvh(TWO_JOINTS, "walk", &options).unwrap();
+        assert_eq!(skeleton.bones_ordered[1].name, "leg_lower");
+        let hips = animation.track("hips").unwrap();
+        assert_eq!(hips.translation_keys[1].translation, [2.5, 3.0, 3.5]);
+        let knee = animation.track("leg_lower").unwrap();
+        assert_eq!(knee.translation_keys[0].translation, [0.0, -2.0, 0.0]);
+    }
+
+    #[test]
+    fn missing_channel_values_are_an_error() {
+        let text = TWO_JOINTS.replace("5 6 7 90 90 0 90 0 90\n", "5 6 7 90 90 0 90\n");
+        assert!(parse_bvh(&text, "walk", &BvhOptions::default()).is_err());
+    }
+}
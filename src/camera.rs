@@ -1,4 +1,5 @@
 use crate::transform::Transform;
+use cgmath::Rotation;
 use cgmath::SquareMatrix;
 use cgmath::{Matrix4, PerspectiveFov, Quaternion, Rad, Vector3};
 // --- Camera ---
@@ -9,6 +10,14 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 0.5,
     0.0, 0.0, 0.0, 1.0,
 );
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    // uses Camera::fov and Camera::aspect_ratio
+    Perspective,
+    // half width and half height of the view volume
+    Orthographic { x_mag: f32, y_mag: f32 },
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Camera {
     pub transform: Transform,
@@ -16,15 +25,9 @@ pub struct Camera {
     pub aspect_ratio: f32,
     pub near: f32,
     pub far: f32,
+    pub projection: Projection,
 }
 
-const DEFAULT_LEFT: f32 = -1.0;
-const DEFAULT_RIGHT: f32 = 1.0;
-const DEFAULT_BOTTOM: f32 = -1.0;
-const DEFAULT_TOP: f32 = 1.0;
-const DEFAULT_NEAR: f32 = -1.0;
-const DEFAULT_FAR: f32 = 1.0;
-
 impl Camera {
     pub fn new(transform: Transform, fov: f32, aspect_ratio: f32, near: f32, far: f32) -> Self {
         Camera {
@@ -33,6 +36,7 @@ impl Camera {
             aspect_ratio,
             near,
             far,
+            projection: Projection::Perspective,
         }
     }
     pub fn default(aspect_ratio: f32) -> Self {
@@ -44,9 +48,11 @@ impl Camera {
             near: 0.1,
             far: 100.0,
             aspect_ratio: aspect_ratio,
+            projection: Projection::Perspective,
         }
     }
 
+    // inverse of the camera world transform (scale ignored): R(q)⁻¹ · T(-p)
     pub fn view_matrix(&self) -> [[f32; 4]; 4] {
         let translation_matrix = Matrix4::from_translation(Vector3::new(
             -self.transform.position[0],
@@ -54,14 +60,15 @@ impl Camera {
             -self.transform.position[2],
         ));
 
-        let rotation_matrix = Matrix4::from(Quaternion::new(
+        let rotation = Quaternion::new(
             self.transform.rotation[3],
             self.transform.rotation[0],
             self.transform.rotation[1],
             self.transform.rotation[2],
-        ));
+        );
+        let inverse_rotation_matrix = Matrix4::from(rotation.invert());
 
-        let transformation_matrix = translation_matrix * rotation_matrix;
+        let transformation_matrix = inverse_rotation_matrix * translation_matrix;
         let array: [[f32; 4]; 4] = transformation_matrix.into();
         array
     }
@@ -81,23 +88,17 @@ impl Camera {
 
     fn build_view_projection_matrix(&self) -> Matrix4<f32> {
         let view_matrix = cgmath::Matrix4::from(self.view_matrix());
-        let perspective = cgmath::perspective(
-            cgmath::Deg(self.fov),
-            self.aspect_ratio,
-            self.near,
-            self.far,
-        );
-
-        let ortho = cgmath::ortho(
-            DEFAULT_LEFT,
-            DEFAULT_RIGHT,
-            DEFAULT_BOTTOM,
-            DEFAULT_TOP,
-            DEFAULT_NEAR,
-            DEFAULT_FAR,
-        );
-
-        let projection_matrix: Matrix4<f32> = perspective;
+        let projection_matrix: Matrix4<f32> = match self.projection {
+            Projection::Perspective => cgmath::perspective(
+                cgmath::Deg(self.fov),
+                self.aspect_ratio,
+                self.near,
+                self.far,
+            ),
+            Projection::Orthographic { x_mag, y_mag } => {
+                cgmath::ortho(-x_mag, x_mag, -y_mag, y_mag, self.near, self.far)
+            }
+        };
 
         OPENGL_TO_WGPU_MATRIX * projection_matrix * view_matrix
     }
@@ -210,3 +211,95 @@ impl ModelMatrixBufferHandler {
         queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[matrix]));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{InnerSpace, Vector4};
+
+    // a camera at (1, 2, 3) turned 90° around y, looking down -x
+    const ROTATED_CAMERA_GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{
+            "camera": 0,
+            "translation": [1.0, 2.0, 3.0],
+            "rotation": [0.0, 0.70710677, 0.0, 0.70710677]
+        }],
+        "cameras": [{
+            "type": "perspective",
+            "perspective": { "yfov": 0.8, "znear": 0.1, "zfar": 100.0 }
+        }]
+    }"#;
+
+    fn view_space(camera: &Camera, point: [f32; 3]) -> Vector3<f32> {
+        let view = Matrix4::from(camera.view_matrix());
+        (view * Vector4::new(point[0], point[1], point[2], 1.0)).truncate()
+    }
+
+    #[test]
+    fn rotated_gltf_camera_view_direction() {
+        let path = std::env::temp_dir().join(format!("rotated_camera_{}.gltf", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, ROTATED_CAMERA_GLTF).unwrap();
+        let scene = crate::gltf_loader::load_gltf_scene(path);
+        std::fs::remove_file(path).unwrap();
+        let scene = scene.unwrap();
+        assert_eq!(scene.cameras.len(), 1);
+        let camera = &scene.cameras[0];
+
+        let close = |a: Vector3<f32>, b: [f32; 3]| (a - Vector3::from(b)).magnitude() < 1e-4;
+        // the eye is the view space origin
+        assert!(close(view_space(camera, [1.0, 2.0, 3.0]), [0.0, 0.0, 0.0]));
+        // 5 units down world -x is 5 units in front of the camera
+        let ahead = view_space(camera, [-4.0, 2.0, 3.0]);
+        assert!(close(ahead, [0.0, 0.0, -5.0]), "{:?}", ahead);
+        // world +y stays up, world -z is on the camera's right
+        assert!(close(view_space(camera, [1.0, 3.0, 3.0]), [0.0, 1.0, 0.0]));
+        let right = view_space(camera, [1.0, 2.0, 2.0]);
+        assert!(close(right, [1.0, 0.0, 0.0]), "{:?}", right);
+
+        // the view matrix undoes the camera world transform
+        let world = camera.transform.matrix();
+        let round_trip = Matrix4::from(camera.view_matrix()) * world;
+        let identity: [[f32; 4]; 4] = Matrix4::identity().into();
+        let round_trip: [[f32; 4]; 4] = round_trip.into();
+        for (row, expected) in round_trip.iter().zip(&identity) {
+            for (value, expected) in row.iter().zip(expected) {
+                assert!((value - expected).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn orbit_turns_the_view_around_the_target() {
+        // the viewer used to rotate the view matrix T(-p) · R, turning the model in
+        // front of a fixed camera: orbiting the camera gives the same view
+        let mut camera = Camera::default(1.0);
+        let eye = camera.transform.position;
+        let mut model_rotation = Quaternion::new(1.0, 0.0, 0.0, 0.0);
+        for (axis, angle) in [
+            (Vector3::unit_y(), 0.3),
+            (Vector3::unit_x(), -0.5),
+            (Vector3::unit_y(), -1.2),
+        ] {
+            camera
+                .transform
+                .orbit(Vector3::new(0.0, 0.0, 0.0), axis, angle);
+            let turn: Quaternion<f32> = cgmath::Rotation3::from_axis_angle(axis, Rad(-angle));
+            model_rotation = turn * model_rotation;
+        }
+        let expected: [[f32; 4]; 4] =
+            (Matrix4::from_translation(-eye) * Matrix4::from(model_rotation)).into();
+        let view = camera.view_matrix();
+        for (row, expected) in view.iter().zip(&expected) {
+            for (value, expected) in row.iter().zip(expected) {
+                assert!((value - expected).abs() < 1e-5, "{:?}", view);
+            }
+        }
+        // the target stays at the same distance
+        let distance = view_space(&camera, [0.0, 0.0, 0.0]).magnitude();
+        assert!((distance - eye.magnitude()).abs() < 1e-5);
+    }
+}
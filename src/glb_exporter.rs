@@ -0,0 +1,1174 @@
+use crate::json_exporter::json_f32;
+use crate::material::{AlphaMode, SamplerInfo, TextureRef};
+use crate::mesh_utils::{has_valid_tangent, has_vertex_colors, is_skinned};
+use crate::model::{
+    AnimatedBone, Animation, Bone, Mesh, Model, ModelVertex, Skeleton, MAX_BONE_INFLUENCES,
+};
+use crate::transform::Transform;
+use cgmath::{InnerSpace, Matrix4, SquareMatrix};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+const CHUNK_BIN: u32 = 0x004E_4942;
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const COMPONENT_U16: u32 = 5123;
+const COMPONENT_U32: u32 = 5125;
+const COMPONENT_F32: u32 = 5126;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlbExportOptions {
+    // keyframe timestamps are multiplied by this to get seconds
+    // (0.001 for the json animations, which are in milliseconds)
+    pub time_scale: f32,
+}
+
+impl Default for GlbExportOptions {
+    fn default() -> Self {
+        Self { time_scale: 1.0 }
+    }
+}
+
+// binary chunk and the accessors/buffer views pointing into it
+#[derive(Default)]
+struct GlbBuffer {
+    bin: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl GlbBuffer {
+    fn push_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        // accessors need their data aligned to the component size
+        while !self.bin.len().is_multiple_of(4) {
+            self.bin.push(0);
+        }
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.bin.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.bin.extend_from_slice(bytes);
+        self.buffer_views.push(view);
+        self.buffer_views.len() - 1
+    }
+
+    fn push_accessor(
+        &mut self,
+        bytes: &[u8],
+        target: Option<u32>,
+        component_type: u32,
+        count: usize,
+        accessor_type: &str,
+    ) -> usize {
+        let view = self.push_view(bytes, target);
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": component_type,
+            "count": count,
+            "type": accessor_type,
+        }));
+        self.accessors.len() - 1
+    }
+
+    // `N` floats per element, with min/max when `bounds` is set
+    fn push_f32<const N: usize>(
+        &mut self,
+        data: &[[f32; N]],
+        target: Option<u32>,
+        accessor_type: &str,
+        bounds: bool,
+    ) -> usize {
+        let bytes: Vec<u8> = data
+            .iter()
+            .flat_map(|element| element.iter().flat_map(|value| value.to_le_bytes()))
+            .collect();
+        let accessor = self.push_accessor(&bytes, target, COMPONENT_F32, data.len(), accessor_type);
+        if bounds && !data.is_empty() {
+            let mut min = data[0];
+            let mut max = data[0];
+            for element in data {
+                for i in 0..N {
+                    min[i] = min[i].min(element[i]);
+                    max[i] = max[i].max(element[i]);
+                }
+            }
+            self.accessors[accessor]["min"] = Value::Array(min.map(json_f32).to_vec());
+            self.accessors[accessor]["max"] = Value::Array(max.map(json_f32).to_vec());
+        }
+        accessor
+    }
+
+    fn push_u16x4(&mut self, data: &[[u16; 4]], target: Option<u32>) -> usize {
+        let bytes: Vec<u8> = data
+            .iter()
+            .flat_map(|element| element.iter().flat_map(|value| value.to_le_bytes()))
+            .collect();
+        self.push_accessor(&bytes, target, COMPONENT_U16, data.len(), "VEC4")
+    }
+
+    fn push_indices(&mut self, indices: &[u32]) -> usize {
+        let bytes: Vec<u8> = indices
+            .iter()
+            .flat_map(|index| index.to_le_bytes())
+            .collect();
+        self.push_accessor(
+            &bytes,
+            Some(ELEMENT_ARRAY_BUFFER),
+            COMPONENT_U32,
+            indices.len(),
+            "SCALAR",
+        )
+    }
+}
+
+// write a model, its skeleton and animations as a binary gltf file
+pub fn write_glb<'a>(
+    filepath: &str,
+    model: &Model,
+    animations: impl IntoIterator<Item = &'a Animation>,
+    options: &GlbExportOptions,
+) -> anyhow::Result<()> {
+    let bytes = glb_bytes(model, animations, options)?;
+    std::fs::write(filepath, bytes)
+        .map_err(|err| anyhow::anyhow!("{}: can't write file ({})", filepath, err))
+}
+
+pub fn glb_bytes<'a>(
+    model: &Model,
+    animations: impl IntoIterator<Item = &'a Animation>,
+    options: &GlbExportOptions,
+) -> anyhow::Result<Vec<u8>> {
+    let mut buffer = GlbBuffer::default();
+    let mut nodes: Vec<Value> = Vec::new();
+    let mut scene_nodes: Vec<usize> = Vec::new();
+
+    // skeleton nodes, k: bone id, v: node index
+    let skeleton = export_skeleton(model)?;
+    let mut skins: Vec<Value> = Vec::new();
+    let mut bone_nodes: HashMap<usize, usize> = HashMap::new();
+    if let Some(skeleton) = skeleton {
+        bone_nodes = push_skeleton_nodes(skeleton, &mut nodes, &mut scene_nodes)?;
+        let inverse_bind_matrices: Vec<[f32; 16]> = skeleton
+            .bones_ordered
+            .iter()
+            .map(|bone| flatten_matrix(bone.inverse_bind_matrix))
+            .collect();
+        let joints: Vec<usize> = (0..skeleton.bones_ordered.len())
+            .map(|id| bone_nodes[&id])
+            .collect();
+        let root = skeleton
+            .bones_ordered
+            .iter()
+            .find(|bone| bone.parent_id.is_none())
+            .map(|bone| bone_nodes[&(bone.id as usize)]);
+        let mut skin = json!({
+            "name": skeleton.name,
+            "joints": joints,
+            "inverseBindMatrices": buffer.push_f32(&inverse_bind_matrices, None, "MAT4", false),
+        });
+        if let Some(root) = root {
+            skin["skeleton"] = json!(root);
+        }
+        skins.push(skin);
+    }
+
+    // one node per mesh, skinned meshes use the skeleton of the model
+    let mut meshes: Vec<Value> = Vec::new();
+    for mesh in &model.meshes {
+        if !has_geometry(mesh) {
+            eprintln!("mesh '{}' has no triangles, not exported", mesh.name);
+            continue;
+        }
+        let skinned = skeleton.is_some() && is_skinned(mesh);
+        meshes.push(export_mesh(
+            &mut buffer,
+            mesh,
+            skinned,
+            model.materials.len(),
+        ));
+        let mut node = json!({
+            "name": mesh.name,
+            "mesh": meshes.len() - 1,
+        });
+        if skinned {
+            node["skin"] = json!(0);
+        }
+        nodes.push(node);
+        scene_nodes.push(nodes.len() - 1);
+    }
+
+    if meshes.is_empty() {
+        return Err(anyhow::anyhow!("no mesh has triangles, nothing to export"));
+    }
+
+    let (materials, textures, samplers, images) = export_materials(&mut buffer, model)?;
+
+    let mut gltf_animations: Vec<Value> = Vec::new();
+    if let Some(skeleton) = skeleton {
+        for animation in animations {
+            match export_animation(&mut buffer, animation, skeleton, &bone_nodes, options)? {
+                Some(gltf_animation) => gltf_animations.push(gltf_animation),
+                None => eprintln!(
+                    "animation '{}' has no track for skeleton '{}', not exported",
+                    animation.name, skeleton.name
+                ),
+            }
+        }
+    }
+
+    let mut document = json!({
+        "asset": { "version": "2.0", "generator": "rust_renderer glb exporter" },
+        "scene": 0,
+        "scenes": [{ "nodes": scene_nodes }],
+    });
+    // gltf arrays can't be empty
+    let optional = [
+        ("nodes", nodes),
+        ("meshes", meshes),
+        ("skins", skins),
+        ("animations", gltf_animations),
+        ("materials", materials),
+        ("textures", textures),
+        ("samplers", samplers),
+        ("images", images),
+        ("bufferViews", buffer.buffer_views),
+        ("accessors", buffer.accessors),
+    ];
+    for (key, values) in optional {
+        if !values.is_empty() {
+            document[key] = Value::Array(values);
+        }
+    }
+    while !buffer.bin.len().is_multiple_of(4) {
+        buffer.bin.push(0);
+    }
+    if !buffer.bin.is_empty() {
+        document["buffers"] = json!([{ "byteLength": buffer.bin.len() }]);
+    }
+    Ok(glb_container(&serde_json::to_vec(&document)?, &buffer.bin))
+}
+
+fn glb_container(json: &[u8], bin: &[u8]) -> Vec<u8> {
+    let mut json = json.to_vec();
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+    let bin_chunk_size = if bin.is_empty() { 0 } else { 8 + bin.len() };
+    let total = 12 + 8 + json.len() + bin_chunk_size;
+    let mut bytes = Vec::with_capacity(total);
+    for value in [GLB_MAGIC, GLB_VERSION, total as u32] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&CHUNK_JSON.to_le_bytes());
+    bytes.extend_from_slice(&json);
+    if !bin.is_empty() {
+        bytes.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&CHUNK_BIN.to_le_bytes());
+        bytes.extend_from_slice(bin);
+    }
+    bytes
+}
+
+// the skeleton used by the skinned meshes (the json format stores it only
+// on the first mesh), a gltf file with several skins is not supported
+fn export_skeleton(model: &Model) -> anyhow::Result<Option<&Skeleton>> {
+    let mut skeletons = model
+        .meshes
+        .iter()
+        .filter_map(|mesh| mesh.skeleton.as_ref());
+    let first = skeletons.next();
+    if let Some(first) = first {
+        let names = |skeleton: &Skeleton| -> Vec<String> {
+            skeleton
+                .bones_ordered
+                .iter()
+                .map(|bone| bone.name.clone())
+                .collect()
+        };
+        if let Some(other) = skeletons.find(|other| names(other) != names(first)) {
+            return Err(anyhow::anyhow!(
+                "meshes use different skeletons ('{}' and '{}'), only one skin can be exported",
+                first.name,
+                other.name
+            ));
+        }
+    }
+    Ok(first)
+}
+
+// bone nodes in bone id order, bones with a parent_offset get an extra
+// parent node holding it so the importer folds it back
+fn push_skeleton_nodes(
+    skeleton: &Skeleton,
+    nodes: &mut Vec<Value>,
+    scene_nodes: &mut Vec<usize>,
+) -> anyhow::Result<HashMap<usize, usize>> {
+    let mut bone_nodes: HashMap<usize, usize> = HashMap::new();
+    // node attached to the parent: the offset node or the bone node
+    let mut top_nodes: HashMap<usize, usize> = HashMap::new();
+    for bone in &skeleton.bones_ordered {
+        let mut local = skeleton.bind_local_matrix(bone);
+        if let Some(offset) = bone.parent_offset {
+            let offset = Matrix4::from(offset);
+            local = offset.invert().unwrap_or_else(Matrix4::identity) * local;
+            nodes.push(json!({
+                "name": format!("{} offset", bone.name),
+                "matrix": flatten_matrix(offset.into()).map(json_f32),
+            }));
+            top_nodes.insert(bone.id as usize, nodes.len() - 1);
+        }
+        let mut node = trs_node(&bone.name, Transform::from_matrix(local));
+        node["children"] = json!([]);
+        nodes.push(node);
+        let node_index = nodes.len() - 1;
+        bone_nodes.insert(bone.id as usize, node_index);
+        if let Some(&offset_node) = top_nodes.get(&(bone.id as usize)) {
+            nodes[offset_node]["children"] = json!([node_index]);
+        } else {
+            top_nodes.insert(bone.id as usize, node_index);
+        }
+    }
+    for bone in &skeleton.bones_ordered {
+        let top = top_nodes[&(bone.id as usize)];
+        match bone.parent_id {
+            Some(parent) => {
+                let parent_node = *bone_nodes.get(&parent).ok_or_else(|| {
+                    anyhow::anyhow!("bone '{}' has a missing parent {}", bone.name, parent)
+                })?;
+                nodes[parent_node]["children"]
+                    .as_array_mut()
+                    .expect("bone nodes have children")
+                    .push(json!(top));
+            }
+            None => scene_nodes.push(top),
+        }
+    }
+    // gltf does not allow empty children arrays
+    for node in nodes.iter_mut() {
+        if node["children"]
+            .as_array()
+            .is_some_and(|children| children.is_empty())
+        {
+            node.as_object_mut().expect("node").remove("children");
+        }
+    }
+    Ok(bone_nodes)
+}
+
+fn trs_node(name: &str, transform: Transform) -> Value {
+    let rotation = transform.rotation.normalize();
+    json!({
+        "name": name,
+        "translation": ([transform.position.x, transform.position.y, transform.position.z].map(json_f32)),
+        "rotation": ([rotation.v.x, rotation.v.y, rotation.v.z, rotation.s].map(json_f32)),
+        "scale": ([transform.scale.x, transform.scale.y, transform.scale.z].map(json_f32)),
+    })
+}
+
+fn flatten_matrix(matrix: [[f32; 4]; 4]) -> [f32; 16] {
+    let mut flat = [0.0; 16];
+    for (column, values) in matrix.iter().enumerate() {
+        flat[column * 4..column * 4 + 4].copy_from_slice(values);
+    }
+    flat
+}
+
+// meshes without vertices or indices have nothing to draw and can't be
+// written (gltf accessors need at least one element)
+fn has_geometry(mesh: &Mesh) -> bool {
+    !mesh.vertices.is_empty() && !mesh.indices.is_empty()
+}
+
+fn export_mesh(buffer: &mut GlbBuffer, mesh: &Mesh, skinned: bool, material_count: usize) -> Value {
+    let positions: Vec<[f32; 3]> = mesh.vertices.iter().map(|v| v.position).collect();
+    let normals: Vec<[f32; 3]> = mesh.vertices.iter().map(|v| v.normal).collect();
+    let tex_coords: Vec<[f32; 2]> = mesh.vertices.iter().map(|v| v.tex_coords).collect();
+    let mut attributes = json!({
+        "POSITION": buffer.push_f32(&positions, Some(ARRAY_BUFFER), "VEC3", true),
+        "NORMAL": buffer.push_f32(&normals, Some(ARRAY_BUFFER), "VEC3", false),
+        "TEXCOORD_0": buffer.push_f32(&tex_coords, Some(ARRAY_BUFFER), "VEC2", false),
+    });
+    if mesh.vertices.iter().any(|v| v.tex_coords_1 != [0.0, 0.0]) {
+        let tex_coords_1: Vec<[f32; 2]> = mesh.vertices.iter().map(|v| v.tex_coords_1).collect();
+        attributes["TEXCOORD_1"] =
+            json!(buffer.push_f32(&tex_coords_1, Some(ARRAY_BUFFER), "VEC2", false));
+    }
+    // gltf tangents must be unit vectors with a +1/-1 handedness
+    let valid_tangents = !mesh.vertices.is_empty() && mesh.vertices.iter().all(has_valid_tangent);
+    if valid_tangents {
+        let tangents: Vec<[f32; 4]> = mesh.vertices.iter().map(|v| v.tangent).collect();
+        attributes["TANGENT"] =
+            json!(buffer.push_f32(&tangents, Some(ARRAY_BUFFER), "VEC4", false));
+    }
+    if has_vertex_colors(mesh) {
+        attributes["COLOR_0"] =
+            json!(buffer.push_f32(&mesh.colors, Some(ARRAY_BUFFER), "VEC4", false));
+    }
+    if skinned {
+        let influences: Vec<[(f32, f32); MAX_BONE_INFLUENCES]> =
+            mesh.vertices.iter().map(|v| v.bone_influences()).collect();
+        let needs_second_set = influences
+            .iter()
+            .any(|influences| influences[4..].iter().any(|(_, weight)| *weight > 0.0));
+        let sets = if needs_second_set { 2 } else { 1 };
+        for set in 0..sets {
+            let mut joints: Vec<[u16; 4]> = Vec::new();
+            let mut weights: Vec<[f32; 4]> = Vec::new();
+            for influences in &influences {
+                let mut joint = [0u16; 4];
+                let mut weight = [0.0f32; 4];
+                for slot in 0..4 {
+                    let (id, w) = influences[set * 4 + slot];
+                    if w > 0.0 && id >= 0.0 {
+                        joint[slot] = id as u16;
+                        weight[slot] = w;
+                    }
+                }
+                joints.push(joint);
+                weights.push(weight);
+            }
+            attributes[format!("JOINTS_{set}")] =
+                json!(buffer.push_u16x4(&joints, Some(ARRAY_BUFFER)));
+            attributes[format!("WEIGHTS_{set}")] =
+                json!(buffer.push_f32(&weights, Some(ARRAY_BUFFER), "VEC4", false));
+        }
+    }
+    let mut primitive = json!({
+        "attributes": attributes,
+        "indices": buffer.push_indices(&mesh.indices),
+    });
+    if let Some(material) = mesh.material.filter(|material| *material < material_count) {
+        primitive["material"] = json!(material);
+    }
+    json!({
+        "name": mesh.name,
+        "primitives": [primitive],
+    })
+}
+
+// materials, textures (one per texture reference), samplers and embedded images
+#[allow(clippy::type_complexity)]
+fn export_materials(
+    buffer: &mut GlbBuffer,
+    model: &Model,
+) -> anyhow::Result<(Vec<Value>, Vec<Value>, Vec<Value>, Vec<Value>)> {
+    let mut images: Vec<Value> = Vec::new();
+    for image in &model.images {
+        let mime_type = image
+            .guessed_mime_type()
+            .ok_or_else(|| anyhow::anyhow!("image '{}' is neither png nor jpeg", image.name))?;
+        images.push(json!({
+            "name": image.name,
+            "bufferView": buffer.push_view(&image.bytes, None),
+            "mimeType": mime_type,
+        }));
+    }
+    let mut textures: Vec<Value> = Vec::new();
+    let mut samplers: Vec<Value> = Vec::new();
+    let mut texture_info = |texture_ref: &TextureRef| -> anyhow::Result<Value> {
+        if texture_ref.image >= model.images.len() {
+            return Err(anyhow::anyhow!(
+                "texture uses missing image {}",
+                texture_ref.image
+            ));
+        }
+        samplers.push(export_sampler(&texture_ref.sampler));
+        textures.push(json!({ "source": texture_ref.image, "sampler": samplers.len() - 1 }));
+        Ok(json!({ "index": textures.len() - 1, "texCoord": texture_ref.tex_coord }))
+    };
+    let mut materials: Vec<Value> = Vec::new();
+    for material in &model.materials {
+        let mut pbr = json!({
+            "baseColorFactor": material.base_color_factor.map(json_f32),
+            "metallicFactor": json_f32(material.metallic_factor),
+            "roughnessFactor": json_f32(material.roughness_factor),
+        });
+        if let Some(texture) = &material.base_color_texture {
+            pbr["baseColorTexture"] = texture_info(texture)?;
+        }
+        if let Some(texture) = &material.metallic_roughness_texture {
+            pbr["metallicRoughnessTexture"] = texture_info(texture)?;
+        }
+        let mut gltf_material = json!({
+            "name": material.name,
+            "pbrMetallicRoughness": pbr,
+            "emissiveFactor": material.emissive_factor.map(json_f32),
+            "alphaMode": match material.alpha_mode {
+                AlphaMode::Opaque => "OPAQUE",
+                AlphaMode::Mask => "MASK",
+                AlphaMode::Blend => "BLEND",
+            },
+            "doubleSided": material.double_sided,
+        });
+        if material.alpha_mode == AlphaMode::Mask {
+            gltf_material["alphaCutoff"] = json_f32(material.alpha_cutoff);
+        }
+        if let Some(texture) = &material.normal_texture {
+            let mut info = texture_info(texture)?;
+            info["scale"] = json_f32(material.normal_scale);
+            gltf_material["normalTexture"] = info;
+        }
+        if let Some(texture) = &material.occlusion_texture {
+            let mut info = texture_info(texture)?;
+            info["strength"] = json_f32(material.occlusion_strength);
+            gltf_material["occlusionTexture"] = info;
+        }
+        if let Some(texture) = &material.emissive_texture {
+            gltf_material["emissiveTexture"] = texture_info(texture)?;
+        }
+        materials.push(gltf_material);
+    }
+    Ok((materials, textures, samplers, images))
+}
+
+fn export_sampler(sampler: &SamplerInfo) -> Value {
+    let wrap = |mode: wgpu::AddressMode| match mode {
+        wgpu::AddressMode::ClampToEdge | wgpu::AddressMode::ClampToBorder => 33071,
+        wgpu::AddressMode::MirrorRepeat => 33648,
+        wgpu::AddressMode::Repeat => 10497,
+    };
+    let mag_filter = match sampler.mag_filter {
+        wgpu::FilterMode::Nearest => 9728,
+        wgpu::FilterMode::Linear => 9729,
+    };
+    let min_filter = match (sampler.min_filter, sampler.mipmap_filter) {
+        (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest) => 9984,
+        (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest) => 9985,
+        (wgpu::FilterMode::Nearest, wgpu::FilterMode::Linear) => 9986,
+        (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear) => 9987,
+    };
+    json!({
+        "magFilter": mag_filter,
+        "minFilter": min_filter,
+        "wrapS": wrap(sampler.address_mode_u),
+        "wrapT": wrap(sampler.address_mode_v),
+    })
+}
+
+// the track of a skeleton bone, by name or by the bone id it was bound to
+pub fn bone_track<'a>(animation: &'a Animation, name: &str, id: usize) -> Option<&'a AnimatedBone> {
+    animation.track(name).or_else(|| animation.track_by_id(id))
+}
+
+// None when no track of the clip animates a bone of the skeleton
+fn export_animation(
+    buffer: &mut GlbBuffer,
+    animation: &Animation,
+    skeleton: &Skeleton,
+    bone_nodes: &HashMap<usize, usize>,
+    options: &GlbExportOptions,
+) -> anyhow::Result<Option<Value>> {
+    let mut samplers: Vec<Value> = Vec::new();
+    let mut channels: Vec<Value> = Vec::new();
+    for bone in &skeleton.bones_ordered {
+        let Some(track) = bone_track(animation, &bone.name, bone.id as usize) else {
+            continue;
+        };
+        let node = bone_nodes[&(bone.id as usize)];
+        let paths: [(&str, Vec<f32>); 3] = [
+            (
+                "translation",
+                track
+                    .translation_keys
+                    .iter()
+                    .map(|key| key.timestamp)
+                    .collect(),
+            ),
+            (
+                "rotation",
+                track
+                    .rotation_keys
+                    .iter()
+                    .map(|key| key.timestamp)
+                    .collect(),
+            ),
+            (
+                "scale",
+                track.scale_keys.iter().map(|key| key.timestamp).collect(),
+            ),
+        ];
+        for (path, times) in paths {
+            if times.is_empty() {
+                continue;
+            }
+            let times: Vec<[f32; 1]> = times
+                .iter()
+                .map(|time| [time * options.time_scale])
+                .collect();
+            if times.windows(2).any(|pair| pair[1][0] <= pair[0][0]) {
+                return Err(anyhow::anyhow!(
+                    "animation '{}', bone '{}': {} keys are not sorted by time",
+                    animation.name,
+                    bone.name,
+                    path
+                ));
+            }
+            let input = buffer.push_f32(&times, None, "SCALAR", true);
+            let output = match path {
+                "translation" => {
+                    let values: Vec<[f32; 3]> = track
+                        .translation_keys
+                        .iter()
+                        .map(|key| key.translation)
+                        .collect();
+                    buffer.push_f32(&values, None, "VEC3", false)
+                }
+                "rotation" => {
+                    let values: Vec<[f32; 4]> = track
+                        .rotation_keys
+                        .iter()
+                        .map(|key| normalize_quaternion(key.rotation))
+                        .collect();
+                    buffer.push_f32(&values, None, "VEC4", false)
+                }
+                _ => {
+                    let values: Vec<[f32; 3]> =
+                        track.scale_keys.iter().map(|key| key.scale).collect();
+                    buffer.push_f32(&values, None, "VEC3", false)
+                }
+            };
+            samplers.push(json!({ "input": input, "output": output, "interpolation": "LINEAR" }));
+            channels.push(json!({
+                "sampler": samplers.len() - 1,
+                "target": { "node": node, "path": path },
+            }));
+        }
+    }
+    if channels.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(json!({
+        "name": animation.name,
+        "samplers": samplers,
+        "channels": channels,
+    })))
+}
+
+fn normalize_quaternion(rotation: [f32; 4]) -> [f32; 4] {
+    let length = rotation
+        .iter()
+        .map(|value| value * value)
+        .sum::<f32>()
+        .sqrt();
+    if length > 0.0 {
+        rotation.map(|value| value / length)
+    } else {
+        [0.0, 0.0, 0.0, 1.0]
+    }
+}
+
+// write the file, load it back with gltf_loader::load_gltf and check that the
+// meshes, skin and exported clips survived the round trip
+pub fn export_glb_verified<'a>(
+    filepath: &str,
+    model: &Model,
+    animations: impl IntoIterator<Item = &'a Animation> + Clone,
+    options: &GlbExportOptions,
+) -> anyhow::Result<()> {
+    write_glb(filepath, model, animations.clone(), options)?;
+    verify_glb_export(filepath, model, animations, options).map_err(|err| {
+        anyhow::anyhow!(
+            "{}: exported file does not match the model ({})",
+            filepath,
+            err
+        )
+    })
+}
+
+pub fn verify_glb_export<'a>(
+    filepath: &str,
+    model: &Model,
+    animations: impl IntoIterator<Item = &'a Animation>,
+    options: &GlbExportOptions,
+) -> anyhow::Result<()> {
+    let (loaded, loaded_animations) = crate::gltf_loader::load_gltf(filepath)?;
+    let skeleton = export_skeleton(model)?;
+
+    // the loader puts the skinned meshes first, empty meshes are not written
+    let written = || model.meshes.iter().filter(|mesh| has_geometry(mesh));
+    let mut expected: Vec<&Mesh> = written()
+        .filter(|mesh| skeleton.is_some() && is_skinned(mesh))
+        .collect();
+    expected.extend(written().filter(|mesh| skeleton.is_none() || !is_skinned(mesh)));
+    if expected.len() != loaded.meshes.len() {
+        return Err(anyhow::anyhow!(
+            "{} meshes written, {} loaded",
+            expected.len(),
+            loaded.meshes.len()
+        ));
+    }
+    let loaded_skeleton = loaded.meshes.iter().find_map(|mesh| mesh.skeleton.as_ref());
+    if let (Some(skeleton), Some(loaded_skeleton)) = (skeleton, loaded_skeleton) {
+        compare_skeletons(skeleton, loaded_skeleton)?;
+    } else if skeleton.is_some() != loaded_skeleton.is_some() {
+        return Err(anyhow::anyhow!("skeleton was not preserved"));
+    }
+    for (mesh, loaded_mesh) in expected.iter().zip(&loaded.meshes) {
+        compare_meshes(mesh, loaded_mesh, skeleton, loaded_skeleton)?;
+    }
+
+    let Some(skeleton) = skeleton else {
+        return Ok(());
+    };
+    let exported: Vec<&Animation> = animations
+        .into_iter()
+        .filter(|animation| {
+            skeleton
+                .bones_ordered
+                .iter()
+                .any(|bone| bone_track(animation, &bone.name, bone.id as usize).is_some())
+        })
+        .collect();
+    if exported.len() != loaded_animations.len() {
+        return Err(anyhow::anyhow!(
+            "{} animations written, {} loaded",
+            exported.len(),
+            loaded_animations.len()
+        ));
+    }
+    for (animation, loaded_animation) in exported.iter().zip(&loaded_animations) {
+        compare_animations(animation, loaded_animation, skeleton, options)?;
+    }
+    Ok(())
+}
+
+const EPSILON: f32 = 1e-4;
+
+fn close(a: &[f32], b: &[f32]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| (a - b).abs() <= EPSILON * a.abs().max(b.abs()).max(1.0))
+}
+
+fn close_matrix(a: [[f32; 4]; 4], b: [[f32; 4]; 4]) -> bool {
+    close(&flatten_matrix(a), &flatten_matrix(b))
+}
+
+fn compare_skeletons(skeleton: &Skeleton, loaded: &Skeleton) -> anyhow::Result<()> {
+    if skeleton.bones_ordered.len() != loaded.bones_ordered.len() {
+        return Err(anyhow::anyhow!(
+            "skeleton '{}': {} bones written, {} loaded",
+            skeleton.name,
+            skeleton.bones_ordered.len(),
+            loaded.bones_ordered.len()
+        ));
+    }
+    let parent_name = |skeleton: &Skeleton, bone: &Bone| -> Option<String> {
+        bone.parent_id
+            .and_then(|parent| skeleton.bones_ordered.get(parent))
+            .map(|parent| parent.name.clone())
+    };
+    for bone in &skeleton.bones_ordered {
+        let loaded_bone = loaded
+            .bones_ordered
+            .iter()
+            .find(|other| other.name == bone.name)
+            .ok_or_else(|| anyhow::anyhow!("bone '{}' is missing", bone.name))?;
+        if parent_name(skeleton, bone) != parent_name(loaded, loaded_bone) {
+            return Err(anyhow::anyhow!(
+                "bone '{}' has a different parent",
+                bone.name
+            ));
+        }
+        if !close_matrix(bone.inverse_bind_matrix, loaded_bone.inverse_bind_matrix) {
+            return Err(anyhow::anyhow!(
+                "bone '{}' has a different inverse bind matrix",
+                bone.name
+            ));
+        }
+        let offsets_match = match (bone.parent_offset, loaded_bone.parent_offset) {
+            (None, None) => true,
+            (Some(a), Some(b)) => close_matrix(a, b),
+            _ => false,
+        };
+        if !offsets_match {
+            return Err(anyhow::anyhow!(
+                "bone '{}' has a different parent offset",
+                bone.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+// weighted influences as (bone name, weight), sorted by name
+fn named_influences(vertex: &ModelVertex, skeleton: Option<&Skeleton>) -> Vec<(String, f32)> {
+    let mut influences: Vec<(String, f32)> = vertex
+        .bone_influences()
+        .iter()
+        .filter(|(id, weight)| *weight > 0.0 && *id >= 0.0)
+        .map(|(id, weight)| {
+            let name = skeleton
+                .and_then(|skeleton| skeleton.bones_ordered.get(*id as usize))
+                .map(|bone| bone.name.clone())
+                .unwrap_or_else(|| format!("#{}", id));
+            (name, *weight)
+        })
+        .collect();
+    influences.sort_by(|a, b| a.0.cmp(&b.0));
+    influences
+}
+
+fn compare_meshes(
+    mesh: &Mesh,
+    loaded: &Mesh,
+    skeleton: Option<&Skeleton>,
+    loaded_skeleton: Option<&Skeleton>,
+) -> anyhow::Result<()> {
+    if mesh.name != loaded.name {
+        return Err(anyhow::anyhow!(
+            "mesh '{}' was loaded as '{}'",
+            mesh.name,
+            loaded.name
+        ));
+    }
+    if mesh.indices != loaded.indices {
+        return Err(anyhow::anyhow!("mesh '{}': indices differ", mesh.name));
+    }
+    if mesh.vertices.len() != loaded.vertices.len() {
+        return Err(anyhow::anyhow!(
+            "mesh '{}': {} vertices written, {} loaded",
+            mesh.name,
+            mesh.vertices.len(),
+            loaded.vertices.len()
+        ));
+    }
+    if mesh.material.is_some() && mesh.material != loaded.material {
+        return Err(anyhow::anyhow!("mesh '{}': material differs", mesh.name));
+    }
+    if has_vertex_colors(mesh) != has_vertex_colors(loaded)
+        || !close(mesh.colors.as_flattened(), loaded.colors.as_flattened())
+    {
+        return Err(anyhow::anyhow!(
+            "mesh '{}': vertex colors differ",
+            mesh.name
+        ));
+    }
+    for (index, (vertex, loaded_vertex)) in mesh.vertices.iter().zip(&loaded.vertices).enumerate() {
+        let attributes = [
+            ("position", close(&vertex.position, &loaded_vertex.position)),
+            ("normal", close(&vertex.normal, &loaded_vertex.normal)),
+            ("uv", close(&vertex.tex_coords, &loaded_vertex.tex_coords)),
+            (
+                "uv1",
+                close(&vertex.tex_coords_1, &loaded_vertex.tex_coords_1),
+            ),
+        ];
+        if let Some((attribute, _)) = attributes.iter().find(|(_, same)| !same) {
+            return Err(anyhow::anyhow!(
+                "mesh '{}', vertex {}: {} differs",
+                mesh.name,
+                index,
+                attribute
+            ));
+        }
+        let influences = named_influences(vertex, skeleton);
+        let loaded_influences = named_influences(loaded_vertex, loaded_skeleton);
+        let same_influences = influences.len() == loaded_influences.len()
+            && influences
+                .iter()
+                .zip(&loaded_influences)
+                .all(|(a, b)| a.0 == b.0 && close(&[a.1], &[b.1]));
+        if !same_influences {
+            return Err(anyhow::anyhow!(
+                "mesh '{}', vertex {}: bone influences differ",
+                mesh.name,
+                index
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn compare_animations(
+    animation: &Animation,
+    loaded: &Animation,
+    skeleton: &Skeleton,
+    options: &GlbExportOptions,
+) -> anyhow::Result<()> {
+    if animation.name != loaded.name {
+        return Err(anyhow::anyhow!(
+            "animation '{}' was loaded as '{}'",
+            animation.name,
+            loaded.name
+        ));
+    }
+    for bone in &skeleton.bones_ordered {
+        let Some(track) = bone_track(animation, &bone.name, bone.id as usize) else {
+            continue;
+        };
+        let loaded_track = loaded.track(&bone.name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "animation '{}': track '{}' is missing",
+                animation.name,
+                bone.name
+            )
+        })?;
+        let scaled = |time: f32| time * options.time_scale;
+        let rotation = |rotation: [f32; 4]| {
+            // q and -q are the same rotation
+            let rotation = normalize_quaternion(rotation);
+            if rotation[3] < 0.0 {
+                rotation.map(|value| -value)
+            } else {
+                rotation
+            }
+        };
+        let paths = [
+            (
+                "translation",
+                track
+                    .translation_keys
+                    .iter()
+                    .map(|key| [&[scaled(key.timestamp)], &key.translation[..]].concat())
+                    .collect::<Vec<Vec<f32>>>(),
+                loaded_track
+                    .translation_keys
+                    .iter()
+                    .map(|key| [&[key.timestamp], &key.translation[..]].concat())
+                    .collect::<Vec<Vec<f32>>>(),
+            ),
+            (
+                "rotation",
+                track
+                    .rotation_keys
+                    .iter()
+                    .map(|key| [&[scaled(key.timestamp)], &rotation(key.rotation)[..]].concat())
+                    .collect(),
+                loaded_track
+                    .rotation_keys
+                    .iter()
+                    .map(|key| [&[key.timestamp], &rotation(key.rotation)[..]].concat())
+                    .collect(),
+            ),
+            (
+                "scale",
+                track
+                    .scale_keys
+                    .iter()
+                    .map(|key| [&[scaled(key.timestamp)], &key.scale[..]].concat())
+                    .collect(),
+                loaded_track
+                    .scale_keys
+                    .iter()
+                    .map(|key| [&[key.timestamp], &key.scale[..]].concat())
+                    .collect(),
+            ),
+        ];
+        for (path, keys, loaded_keys) in paths {
+            let same = keys.len() == loaded_keys.len()
+                && keys.iter().zip(&loaded_keys).all(|(a, b)| close(a, b));
+            if !same {
+                return Err(anyhow::anyhow!(
+                    "animation '{}', bone '{}': {} keys differ",
+                    animation.name,
+                    bone.name,
+                    path
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{KeyRotation, KeyScale, KeyTranslation};
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}_{}.glb", name, std::process::id()))
+    }
+
+    fn triangle(name: &str) -> Mesh {
+        let vertex = |position: [f32; 3]| ModelVertex {
+            position,
+            normal: [0.0, 0.0, 1.0],
+            tex_coords: [position[0], position[1]],
+            ..Default::default()
+        };
+        Mesh {
+            name: name.to_string(),
+            vertices: vec![
+                vertex([0.0, 0.0, 0.0]),
+                vertex([1.0, 0.0, 0.0]),
+                vertex([0.0, 1.0, 0.0]),
+            ],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        }
+    }
+
+    // a chain of 8 bones, one unit apart along y
+    fn chain_skeleton() -> Skeleton {
+        let mut skeleton = Skeleton {
+            name: "chain".to_string(),
+            ..Default::default()
+        };
+        for id in 0..8usize {
+            let bone = Bone {
+                id: id as u32,
+                name: format!("bone_{}", id),
+                parent_id: id.checked_sub(1),
+                inverse_bind_matrix: Matrix4::from_translation(cgmath::Vector3::new(
+                    0.0,
+                    -(id as f32),
+                    0.0,
+                ))
+                .into(),
+                index: id,
+                parent_offset: None,
+            };
+            skeleton.bones.insert(id, bone.clone());
+            skeleton.bones_ordered.push(bone);
+        }
+        skeleton
+    }
+
+    // a triangle influenced by every bone of the chain
+    fn skinned_model() -> Model {
+        let mut mesh = triangle("skinned");
+        let weights = [0.3, 0.2, 0.15, 0.1, 0.1, 0.05, 0.05, 0.05];
+        for (index, vertex) in mesh.vertices.iter_mut().enumerate() {
+            let influences: Vec<(f32, f32)> = (0..8)
+                .map(|slot| (((slot + index) % 8) as f32, weights[slot]))
+                .collect();
+            vertex.set_bone_influences(&influences);
+        }
+        mesh.skeleton = Some(chain_skeleton());
+        Model {
+            meshes: vec![mesh],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn empty_meshes_are_skipped() {
+        let path = temp_path("empty_meshes");
+        let path = path.to_str().unwrap();
+        let model = Model {
+            meshes: vec![
+                Mesh {
+                    name: "empty".to_string(),
+                    ..Default::default()
+                },
+                triangle("triangle"),
+                Mesh {
+                    name: "no indices".to_string(),
+                    indices: Vec::new(),
+                    ..triangle("no indices")
+                },
+            ],
+            ..Default::default()
+        };
+        let exported = export_glb_verified(path, &model, &[], &GlbExportOptions::default());
+        let loaded = crate::gltf_loader::load_gltf(path);
+        std::fs::remove_file(path).unwrap();
+        exported.unwrap();
+        let (loaded, _) = loaded.unwrap();
+        assert_eq!(loaded.meshes.len(), 1);
+        assert_eq!(loaded.meshes[0].indices, [0, 1, 2]);
+
+        // nothing left to write is an error, not an unreadable file
+        let empty = Model {
+            meshes: vec![Mesh::default()],
+            ..Default::default()
+        };
+        assert!(glb_bytes(&empty, &[], &GlbExportOptions::default()).is_err());
+    }
+
+    #[test]
+    fn skinned_mesh_with_eight_influences() {
+        let path = temp_path("eight_influences");
+        let path = path.to_str().unwrap();
+        let model = skinned_model();
+        let exported = export_glb_verified(path, &model, &[], &GlbExportOptions::default());
+        let loaded = crate::gltf_loader::load_gltf(path);
+        std::fs::remove_file(path).unwrap();
+        exported.unwrap();
+        let (loaded, _) = loaded.unwrap();
+        let mesh = &loaded.meshes[0];
+        let skeleton = mesh.skeleton.as_ref().unwrap();
+        assert_eq!(skeleton.bones_ordered.len(), 8);
+        for vertex in &mesh.vertices {
+            let used = vertex
+                .bone_influences()
+                .iter()
+                .filter(|(_, weight)| *weight > 0.0)
+                .count();
+            assert_eq!(used, 8);
+        }
+    }
+
+    #[test]
+    fn animations_round_trip() {
+        let path = temp_path("animations");
+        let path = path.to_str().unwrap();
+        let model = skinned_model();
+        let skeleton = model.meshes[0].skeleton.as_ref().unwrap();
+        let mut animation = Animation::new("wave");
+        for bone in &skeleton.bones_ordered[1..4] {
+            let track = AnimatedBone {
+                bone_name: bone.name.clone(),
+                translation_keys: vec![
+                    KeyTranslation {
+                        timestamp: 0.0,
+                        translation: [0.0, 1.0, 0.0],
+                    },
+                    KeyTranslation {
+                        timestamp: 0.5,
+                        translation: [0.5, 1.0, 0.0],
+                    },
+                ],
+                rotation_keys: vec![
+                    KeyRotation {
+                        timestamp: 0.0,
+                        rotation: [0.0, 0.0, 0.0, 1.0],
+                    },
+                    KeyRotation {
+                        timestamp: 1.0,
+                        rotation: [0.0, 0.0, 0.38268343, 0.9238795],
+                    },
+                ],
+                scale_keys: vec![KeyScale {
+                    timestamp: 0.25,
+                    scale: [1.0, 2.0, 1.0],
+                }],
+                ..Default::default()
+            };
+            animation.add_track(track);
+        }
+        animation.bind_bone_ids(skeleton);
+        // a clip without track for the skeleton is left out
+        let unrelated = Animation::new("unrelated");
+        let animations = [animation, unrelated];
+
+        let options = GlbExportOptions::default();
+        let exported = export_glb_verified(path, &model, &animations, &options);
+        let loaded = crate::gltf_loader::load_gltf(path);
+        std::fs::remove_file(path).unwrap();
+        exported.unwrap();
+        let (_, loaded_animations) = loaded.unwrap();
+        assert_eq!(loaded_animations.len(), 1);
+        let loaded = &loaded_animations[0];
+        assert_eq!(loaded.name, "wave");
+        for bone in &skeleton.bones_ordered[1..4] {
+            let track = animations[0].track(&bone.name).unwrap();
+            let loaded_track = loaded.track(&bone.name).unwrap();
+            assert_eq!(track.translation_keys, loaded_track.translation_keys);
+            assert_eq!(track.scale_keys, loaded_track.scale_keys);
+            assert_eq!(track.rotation_keys.len(), loaded_track.rotation_keys.len());
+        }
+    }
+}
@@ -1,27 +1,41 @@
 use cgmath::SquareMatrix;
 
 use crate::{
-    camera,
+    animation_library::{log_binding_reports, ClipBindingReport},
+    camera::{self, Camera},
+    light::{Light, LightKind},
+    material::{AlphaMode, ImageData, Material, SamplerInfo, TextureRef},
+    mesh_utils::{generate_normals, generate_tangents, NormalMode},
     model::{
         AnimatedBone, Animation, Bone, KeyRotation, KeyScale, KeyTranslation, Mesh, Model,
-        ModelVertex, Skeleton,
+        ModelVertex, Skeleton, MAX_BONES,
     },
+    scene::{Scene, SceneNode},
+    skin_validation::{log_reports, validate_model_skins, SkinValidationOptions},
+    transform::Transform,
 };
-use std::{
-    collections::{HashMap, HashSet},
-    ops::Index,
-};
+use std::{collections::HashMap, sync::Arc};
 
 pub fn process_node(node: &gltf::Node) {
-    println!("processing node: {:#?}", node.name());
+    eprintln!("processing node: {:#?}", node.name());
 }
 
-pub fn process_mesh(mesh: &gltf::Mesh, buffer_data: &Vec<Vec<u8>>, node: &gltf::Node) -> Model {
-    println!("processing mesh: {:#?}", mesh.name());
+pub fn process_mesh(
+    mesh: &gltf::Mesh,
+    document: &gltf::Document,
+    buffer_data: &[Vec<u8>],
+    node: &gltf::Node,
+) -> anyhow::Result<Model> {
+    eprintln!("processing mesh: {:#?}", mesh.name());
     let primitives = mesh.primitives();
+    // the skin belongs to the node, all the primitives share it
+    let node_skeleton = match node.skin() {
+        Some(skin) => Some(process_skin(&skin, document, buffer_data)?),
+        None => None,
+    };
     //let skin = mesh.
 
-    println!("primitives count {}", primitives.len());
+    eprintln!("primitives count {}", primitives.len());
     let mut meshes = Vec::new();
     primitives.for_each(|primitive| {
         let mut vertices: Vec<ModelVertex> = Vec::new();
@@ -29,8 +43,9 @@ pub fn process_mesh(mesh: &gltf::Mesh, buffer_data: &Vec<Vec<u8>>, node: &gltf::
         let mut normals = Vec::new();
         let mut tex_coords_0 = Vec::new();
         let mut tangents = Vec::new();
-        let mut joints = Vec::new();
-        let mut weights = Vec::new();
+        let mut colors: Vec<[f32; 4]> = Vec::new();
+        let mut joints: [Vec<[f32; 4]>; 2] = [Vec::new(), Vec::new()];
+        let mut weights: [Vec<[f32; 4]>; 2] = [Vec::new(), Vec::new()];
         let mut indices = Vec::new();
         let mut vertex_count = 0;
         let mut skeleton: Option<Skeleton> = None;
@@ -55,186 +70,271 @@ pub fn process_mesh(mesh: &gltf::Mesh, buffer_data: &Vec<Vec<u8>>, node: &gltf::
                 tex_coords_0.push(tex_coord);
             })
         }
+        // second uv set, used by the textures with texCoord 1
+        let tex_coords_1: Vec<[f32; 2]> = reader
+            .read_tex_coords(1)
+            .map(|v| v.into_f32().collect())
+            .unwrap_or_default();
         // read tangents
         if let Some(tangent_attribute) = reader.read_tangents() {
             tangent_attribute.for_each(|tangent| {
                 tangents.push(tangent);
             })
         }
+        // read vertex colors (COLOR_0 is linear)
+        if let Some(color_attribute) = reader.read_colors(0) {
+            colors.extend(color_attribute.into_rgba_f32());
+        }
         // if it has a skeleton
-        if let Some(skin) = node.skin() {
-            skeleton = Some(process_skin(&skin, buffer_data));
-            // get bones ids
-            if let Some(joint_attribute) = reader.read_joints(0).map(|v| v.into_u16()) {
-                // Iterate over joint attributes
-                joint_attribute.for_each(|joint| {
-                    let mut f_array = [0.0, 0.0, 0.0, 0.0];
-                    let mut index = 0;
-                    for j in joint {
-                        let mut joint_id: usize = j as usize;
-                        // find new bone id from gltf joint index
-                        for bone in &skeleton.as_ref().expect("").bones_ordered {
-                            if bone.index == j as usize {
-                                joint_id = bone.id as usize;
-                                break;
+        if let Some(node_skeleton) = &node_skeleton {
+            skeleton = Some(node_skeleton.clone());
+            // get bones ids and weights, JOINTS_1/WEIGHTS_1 hold influences 5-8
+            for set in 0..2 {
+                if let Some(joint_attribute) = reader.read_joints(set).map(|v| v.into_u16()) {
+                    // Iterate over joint attributes
+                    joint_attribute.for_each(|joint| {
+                        let mut f_array = [0.0, 0.0, 0.0, 0.0];
+                        for (index, j) in joint.into_iter().enumerate() {
+                            let mut joint_id: usize = j as usize;
+                            // find new bone id from gltf joint index
+                            for bone in &skeleton.as_ref().expect("").bones_ordered {
+                                if bone.index == j as usize {
+                                    joint_id = bone.id as usize;
+                                    break;
+                                }
                             }
+                            f_array[index] = joint_id as f32;
                         }
-                        f_array[index] = joint_id as f32;
-                        index += 1;
-                    }
-                    joints.push(f_array);
-                });
-            }
-            // get bones weights
-            if let Some(weight_attribute) = reader.read_weights(0).map(|v| v.into_f32()) {
-                // Iterate over joint attributes
-                weight_attribute.for_each(|weight| {
-                    weights.push(weight);
-                });
+                        joints[set as usize].push(f_array);
+                    });
+                }
+                if let Some(weight_attribute) = reader.read_weights(set).map(|v| v.into_f32()) {
+                    weight_attribute.for_each(|weight| {
+                        weights[set as usize].push(weight);
+                    });
+                }
             }
             // add vertices
         }
         for i in 0..vertex_count {
             let pos = positions[i];
-            let tex_coords = tex_coords_0[i];
-            let normals = normals[i];
-            let mut tangent = [0.0, 0.0, 0.0, 0.0];
-            let mut joint = [0.0, 0.0, 0.0, 0.0];
-            let mut weight = [0.0, 0.0, 0.0, 0.0];
-            if tangents.len() > 0 {
-                tangent = tangents[i];
-            }
-            if joints.len() > 0 {
-                joint = joints[i];
-            }
-            if weights.len() > 0 {
-                weight = weights[i];
+            // missing attributes are generated below
+            let tex_coords = tex_coords_0.get(i).copied().unwrap_or_default();
+            let tex_coords_1 = tex_coords_1.get(i).copied().unwrap_or_default();
+            let normals = normals.get(i).copied().unwrap_or_default();
+            let tangent = tangents.get(i).copied().unwrap_or_default();
+            let mut joint = [[0.0, 0.0, 0.0, 0.0]; 2];
+            let mut weight = [[0.0, 0.0, 0.0, 0.0]; 2];
+            for set in 0..2 {
+                if let (Some(j), Some(w)) = (joints[set].get(i), weights[set].get(i)) {
+                    joint[set] = *j;
+                    weight[set] = *w;
+                }
             }
             vertices.push(ModelVertex {
                 position: pos,
                 tex_coords: tex_coords,
                 normal: normals,
                 tangent: tangent,
-                bone_ids: joint,
-                bone_weights: weight,
+                bone_ids: joint[0],
+                bone_weights: weight[0],
+                bone_ids_1: joint[1],
+                bone_weights_1: weight[1],
+                tex_coords_1,
             })
         }
         if let Some(indices_raw) = reader.read_indices() {
             indices.append(&mut indices_raw.into_u32().collect::<Vec<u32>>());
+        } else {
+            // non indexed primitive
+            indices = (0..vertex_count as u32).collect();
+        }
+        if colors.len() != vertex_count {
+            colors.clear();
         }
-        meshes.push(Mesh {
+        let mut mesh = Mesh {
             name: mesh.name().unwrap_or_else(|| "unnamed mesh").to_string(),
             vertices: vertices,
             indices: indices,
             skeleton: skeleton,
-        })
+            // the document materials are stored in the same order
+            material: primitive.material().index(),
+            colors,
+            vertex_format: Default::default(),
+        };
+        // the gltf specification asks for flat normals and mikktspace tangents
+        // (which are ignored without normals) when they are not provided
+        let has_normals = normals.len() == vertex_count;
+        if !has_normals {
+            eprintln!(
+                "mesh '{}' has no normals, generating flat normals",
+                mesh.name
+            );
+            generate_normals(&mut mesh, NormalMode::Flat);
+        }
+        if !has_normals || tangents.len() != vertex_count {
+            generate_tangents(&mut mesh);
+        }
+        meshes.push(mesh)
     });
-    return Model { meshes };
+    let mut model = Model {
+        meshes,
+        ..Default::default()
+    };
+    // fix bone weights and ids before they reach the gpu
+    model.skin_reports = validate_model_skins(&mut model, &SkinValidationOptions::default());
+    log_reports(&model.skin_reports);
+    Ok(model)
 }
 
-pub fn process_skin(skin: &gltf::Skin, buffer_data: &Vec<Vec<u8>>) -> Skeleton {
-    // let skel = skin.skeleton();
-    let joints = skin.joints();
-    let mut bones = HashMap::new();
-    let name: String = skin.name().unwrap_or_else(|| "skeleton").to_string();
-    let reader = skin.reader(|buffer| Some(&buffer_data[buffer.index()]));
-    let mut matrices = Vec::new();
-    if let Some(inverse_matrices_attribute) = reader.read_inverse_bind_matrices() {
-        inverse_matrices_attribute.for_each(|mat| {
-            if cgmath::Matrix4::from(mat.clone()) == cgmath::Matrix4::identity() {
-                println!("Identity");
-            }
-            let mut m = cgmath::Matrix4::from(mat);
-            // let mut m = m.invert().expect("");
-            // m.transpose_self();
-            let mat = m.into();
-            matrices.push(mat);
-        })
-    }
-    let mut count = 0;
-    let mut children: HashMap<usize, usize> = HashMap::new();
-    joints.for_each(|joint| {
-        //println!("processing joint: {:#?}, index {}", &joint.name(), &joint.index());
-        for child in joint.children() {
-            // k: bone index, v: parent index
-            children.insert(child.index(), joint.index());
-        }
-        let parent_id = children.get_key_value(&joint.index());
-        let mut parent_index = None;
-        if let Some(parent_id) = parent_id {
-            parent_index = Some(parent_id.1.clone());
-        }
+// local transform of a gltf node
+fn node_local_matrix(node: &gltf::Node) -> cgmath::Matrix4<f32> {
+    cgmath::Matrix4::from(node.transform().matrix())
+}
 
-        bones.insert(
-            joint.index(),
-            Bone {
-                id: joint.index() as u32,
-                name: joint.name().unwrap_or_else(|| "unnamed bone").to_string(),
-                inverse_bind_matrix: matrices[count],
-                parent_id: parent_index,
-                index: joint.index(),
-            },
-        );
-        count += 1;
-    });
-    // reset bone parenting
-    for bone in bones.values_mut() {
-        if let Some(parent_id) = children.get(&(bone.id as usize)) {
-            bone.parent_id = Some(*parent_id);
-        } else {
-            bone.parent_id = None;
+// k: node index, v: parent node index, for every node of the document
+fn node_parents(document: &gltf::Document) -> anyhow::Result<HashMap<usize, usize>> {
+    let mut parents: HashMap<usize, usize> = HashMap::new();
+    for node in document.nodes() {
+        for child in node.children() {
+            if let Some(other) = parents.insert(child.index(), node.index()) {
+                return Err(anyhow::anyhow!(
+                    "node {} has more than one parent (nodes {} and {})",
+                    child.index(),
+                    other,
+                    node.index()
+                ));
+            }
         }
     }
-    // Order the bones
-    let mut ordered_bones: Vec<Bone> = Vec::new();
+    Ok(parents)
+}
 
-    // insert roots
-    for bone in bones.values() {
-        if bone.parent_id.is_none() {
-            ordered_bones.push(bone.clone());
+pub fn process_skin(
+    skin: &gltf::Skin,
+    document: &gltf::Document,
+    buffer_data: &[Vec<u8>],
+) -> anyhow::Result<Skeleton> {
+    let name: String = skin.name().unwrap_or("skeleton").to_string();
+    let joints: Vec<gltf::Node> = skin.joints().collect();
+    if joints.is_empty() {
+        return Err(anyhow::anyhow!("skin '{}' has no joints", name));
+    }
+    if joints.len() > MAX_BONES {
+        return Err(anyhow::anyhow!(
+            "skin '{}' has {} joints, at most {} are supported",
+            name,
+            joints.len(),
+            MAX_BONES
+        ));
+    }
+    // inverse bind matrices, identity when the skin does not provide them
+    let reader = skin.reader(|buffer| Some(&buffer_data[buffer.index()]));
+    let matrices: Vec<[[f32; 4]; 4]> = match reader.read_inverse_bind_matrices() {
+        Some(inverse_matrices_attribute) => inverse_matrices_attribute.collect(),
+        None => vec![cgmath::Matrix4::identity().into(); joints.len()],
+    };
+    if matrices.len() != joints.len() {
+        return Err(anyhow::anyhow!(
+            "skin '{}' has {} joints but {} inverse bind matrices",
+            name,
+            joints.len(),
+            matrices.len()
+        ));
+    }
+    // k: node index, v: position of the joint in the skin
+    let mut joint_positions: HashMap<usize, usize> = HashMap::new();
+    for (position, joint) in joints.iter().enumerate() {
+        if joint_positions.insert(joint.index(), position).is_some() {
+            return Err(anyhow::anyhow!(
+                "skin '{}' lists node {} more than once",
+                name,
+                joint.index()
+            ));
         }
     }
-    // insert all other nodes
-    while ordered_bones.len() < bones.values().len() {
-        for bone in bones.values() {
-            // check if bone is present
-            let mut is_present = false;
-            for ord_bone in &ordered_bones {
-                if ord_bone.id == bone.id {
-                    is_present = true;
-                    break;
-                }
-            }
-            if is_present {
-                continue;
+    // find the parent joint of every joint walking up the full node tree,
+    // transforms of the non joint nodes in between are folded into parent_offset
+    let parents = node_parents(document)?;
+    let nodes: Vec<gltf::Node> = document.nodes().collect();
+    let mut joint_parents: Vec<Option<usize>> = vec![None; joints.len()];
+    let mut parent_offsets: Vec<Option<cgmath::Matrix4<f32>>> = vec![None; joints.len()];
+    for (position, joint) in joints.iter().enumerate() {
+        let mut offset: Option<cgmath::Matrix4<f32>> = None;
+        let mut current = parents.get(&joint.index()).copied();
+        let mut steps = 0;
+        while let Some(node_index) = current {
+            if node_index == joint.index() || steps > nodes.len() {
+                return Err(anyhow::anyhow!(
+                    "skin '{}': node hierarchy above joint {} is cyclic",
+                    name,
+                    joint.index()
+                ));
             }
-            if let Some(parent_id) = bone.parent_id {
-                // check if parent is present
-                for ord_bone in &ordered_bones {
-                    if ord_bone.id == parent_id as u32 {
-                        is_present = true;
-                        break;
-                    }
-                }
-                if is_present {
-                    // add node
-                    ordered_bones.push(bone.clone());
-                    continue;
-                }
+            if let Some(parent_position) = joint_positions.get(&node_index) {
+                joint_parents[position] = Some(*parent_position);
+                break;
             }
+            // non joint node: fold its transform
+            let local = node_local_matrix(&nodes[node_index]);
+            offset = Some(match offset {
+                Some(offset) => local * offset,
+                None => local,
+            });
+            current = parents.get(&node_index).copied();
+            steps += 1;
+        }
+        parent_offsets[position] = offset;
+    }
+    // topological order: roots first, then children breadth first (skin order)
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); joints.len()];
+    let mut ordered: Vec<usize> = Vec::new();
+    for (position, parent) in joint_parents.iter().enumerate() {
+        match parent {
+            Some(parent) => children[*parent].push(position),
+            None => ordered.push(position),
         }
     }
-    let mut index = 0;
-    // Now, ordered_bones contains bones in the desired order
-    for bone in &mut ordered_bones {
-        bone.id = index;
-        index += 1;
+    let mut next = 0;
+    while next < ordered.len() {
+        let position = ordered[next];
+        ordered.extend_from_slice(&children[position]);
+        next += 1;
+    }
+    // joints that never reach a root are parents of each other
+    if ordered.len() != joints.len() {
+        return Err(anyhow::anyhow!(
+            "skin '{}': {} joints form a cycle and are not reachable from a root joint",
+            name,
+            joints.len() - ordered.len()
+        ));
+    }
+    // k: position in the skin, v: bone id (position in the ordered bones)
+    let mut bone_ids = vec![0; joints.len()];
+    for (id, position) in ordered.iter().enumerate() {
+        bone_ids[*position] = id;
     }
-    Skeleton {
+    let mut bones = HashMap::new();
+    let mut ordered_bones: Vec<Bone> = Vec::new();
+    for (id, position) in ordered.iter().enumerate() {
+        let joint = &joints[*position];
+        let bone = Bone {
+            id: id as u32,
+            name: joint.name().unwrap_or("unnamed bone").to_string(),
+            parent_id: joint_parents[*position].map(|parent| bone_ids[parent]),
+            inverse_bind_matrix: matrices[*position],
+            // JOINTS_n values index the skin joint list
+            index: *position,
+            parent_offset: parent_offsets[*position].map(|offset| offset.into()),
+        };
+        bones.insert(id, bone.clone());
+        ordered_bones.push(bone);
+    }
+    Ok(Skeleton {
         name: name,
         bones: bones,
         bones_ordered: ordered_bones,
-    }
+    })
 }
 
 pub fn process_animations(
@@ -242,7 +342,16 @@ pub fn process_animations(
     buffer_data: &Vec<Vec<u8>>,
     skeleton: &Skeleton,
 ) -> Animation {
-    println!("processing animation {:#?}", animation.name());
+    let mut clip = process_animation_clip(animation, buffer_data);
+    clip.bind_bone_ids(skeleton);
+    log_binding_reports(&[ClipBindingReport::new(&clip, skeleton, "gltf")]);
+    clip
+}
+
+// read the channels of an animation, tracks are indexed by node name only
+// so the clip can be bound to any skeleton with matching bone names
+pub fn process_animation_clip(animation: &gltf::Animation, buffer_data: &[Vec<u8>]) -> Animation {
+    eprintln!("processing animation {:#?}", animation.name());
     let mut anim_bones: HashMap<usize, AnimatedBone> = HashMap::new();
     let mut children: HashMap<usize, usize> = HashMap::new();
     for channel in animation.channels() {
@@ -290,51 +399,53 @@ pub fn process_animations(
                     }
                 }
                 _ => {
-                    println!("Iter not supported")
+                    eprintln!("Iter not supported")
                 }
             }
         }
-        let mut index = 0;
-        if let Some(output) = reader.read_outputs() {
-            match output {
-                // add translation keyframes
-                gltf::animation::util::ReadOutputs::Translations(translations) => {
-                    translations.for_each(|translation| {
-                        if let Some(animated_bone) = anim_bones.get_mut(&bone_id) {
-                            animated_bone.translation_keys.push(KeyTranslation {
-                                translation: translation,
-                                timestamp: timestamps[index],
-                            });
-                            index += 1;
-                        }
-                    });
-                }
-                // add rotation keyframes
-                gltf::animation::util::ReadOutputs::Rotations(rotations) => {
-                    rotations.into_f32().for_each(|rotation| {
-                        if let Some(animated_bone) = anim_bones.get_mut(&bone_id) {
-                            animated_bone.rotation_keys.push(KeyRotation {
-                                rotation: rotation,
-                                timestamp: timestamps[index],
-                            });
-                            index += 1;
-                        }
-                    });
-                }
-                // add scale keyframes
-                gltf::animation::util::ReadOutputs::Scales(scales) => {
-                    scales.for_each(|scale| {
-                        if let Some(animated_bone) = anim_bones.get_mut(&bone_id) {
-                            animated_bone.scale_keys.push(KeyScale {
-                                scale,
-                                timestamp: timestamps[index],
-                            });
-                            index += 1;
-                        }
-                    });
-                }
-                _ => {}
+        // cubic spline samplers store (in tangent, value, out tangent) per key,
+        // only the values are kept
+        let cubic =
+            channel.sampler().interpolation() == gltf::animation::Interpolation::CubicSpline;
+        let Some(animated_bone) = anim_bones.get_mut(&bone_id) else {
+            continue;
+        };
+        let channel_name = &animated_bone.bone_name;
+        match reader.read_outputs() {
+            // add translation keyframes
+            Some(gltf::animation::util::ReadOutputs::Translations(translations)) => {
+                let keys = sampler_keys(translations.collect(), &timestamps, cubic, channel_name);
+                animated_bone.translation_keys.extend(keys.into_iter().map(
+                    |(timestamp, translation)| KeyTranslation {
+                        translation,
+                        timestamp,
+                    },
+                ));
+            }
+            // add rotation keyframes
+            Some(gltf::animation::util::ReadOutputs::Rotations(rotations)) => {
+                let keys = sampler_keys(
+                    rotations.into_f32().collect(),
+                    &timestamps,
+                    cubic,
+                    channel_name,
+                );
+                animated_bone.rotation_keys.extend(keys.into_iter().map(
+                    |(timestamp, rotation)| KeyRotation {
+                        rotation,
+                        timestamp,
+                    },
+                ));
             }
+            // add scale keyframes
+            Some(gltf::animation::util::ReadOutputs::Scales(scales)) => {
+                let keys = sampler_keys(scales.collect(), &timestamps, cubic, channel_name);
+                animated_bone.scale_keys.extend(
+                    keys.into_iter()
+                        .map(|(timestamp, scale)| KeyScale { scale, timestamp }),
+                );
+            }
+            _ => {}
         }
     }
     // calculate parents again
@@ -345,127 +456,607 @@ pub fn process_animations(
             bone.parent_index = None;
         }
     }
-    let mut ordered_hash_map: HashMap<usize, AnimatedBone> = HashMap::new();
-    for bone in &skeleton.bones_ordered {
-        // find anim bone
-        for anim_bone in anim_bones.values_mut() {
-            if anim_bone.bone_name == bone.name {
-                // add
-                anim_bone.bone_id = bone.id;
-                ordered_hash_map.insert(bone.id as usize, anim_bone.clone());
-                break;
-            }
+    let mut model_animation = Animation::new(animation.name().unwrap_or("Unnamed animation"));
+    for bone in anim_bones.into_values() {
+        model_animation.add_track(bone);
+    }
+    model_animation
+}
+
+// pair the sampler outputs with their key times, a channel whose output count
+// does not match its inputs is dropped
+fn sampler_keys<T: Copy>(
+    values: Vec<T>,
+    timestamps: &[f32],
+    cubic: bool,
+    channel_name: &str,
+) -> Vec<(f32, T)> {
+    let stride = if cubic { 3 } else { 1 };
+    if values.len() != timestamps.len() * stride {
+        eprintln!(
+            "animation channel of '{}' has {} outputs for {} keys, it is ignored",
+            channel_name,
+            values.len(),
+            timestamps.len()
+        );
+        return Vec::new();
+    }
+    let offset = if cubic { 1 } else { 0 };
+    timestamps
+        .iter()
+        .copied()
+        .zip(values.into_iter().skip(offset).step_by(stride))
+        .collect()
+}
+
+// mime types accepted for buffers embedded as data uris
+const VALID_MIME_TYPES: &[&str] = &["application/octet-stream", "application/gltf-buffer"];
+
+struct DataUri<'a> {
+    mime_type: &'a str,
+    base64: bool,
+    data: &'a str,
+}
+
+impl<'a> DataUri<'a> {
+    fn parse(uri: &'a str) -> anyhow::Result<DataUri<'a>> {
+        let uri = uri
+            .strip_prefix("data:")
+            .ok_or_else(|| anyhow::anyhow!("not a data uri"))?;
+        let (mime_type, data) = uri
+            .split_once(',')
+            .ok_or_else(|| anyhow::anyhow!("malformed data uri, missing ','"))?;
+
+        let (mime_type, base64) = match mime_type.strip_suffix(";base64") {
+            Some(mime_type) => (mime_type, true),
+            None => (mime_type, false),
+        };
+
+        Ok(DataUri {
+            mime_type,
+            base64,
+            data,
+        })
+    }
+
+    fn decode(&self) -> anyhow::Result<Vec<u8>> {
+        if self.base64 {
+            base64::decode(self.data)
+                .map_err(|err| anyhow::anyhow!("invalid base64 data uri ({})", err))
+        } else {
+            Ok(urlencoding::decode_binary(self.data.as_bytes()).into_owned())
         }
     }
-    Animation {
-        name: animation
-            .name()
-            .unwrap_or_else(|| "Unnamed animation")
-            .to_string(),
-        bone_keyframes: ordered_hash_map,
-        bone_keyframes_name: HashMap::new(),
+}
+
+// read the content of a data uri or of a file relative to `base_path`
+pub fn read_uri(
+    uri: &str,
+    base_path: &std::path::Path,
+    mime_types: &[&str],
+) -> anyhow::Result<Vec<u8>> {
+    if uri.starts_with("data:") {
+        let data_uri = DataUri::parse(uri)?;
+        if !mime_types.contains(&data_uri.mime_type) {
+            return Err(anyhow::anyhow!(
+                "unsupported data uri mime type '{}'",
+                data_uri.mime_type
+            ));
+        }
+        return data_uri.decode();
     }
+    if uri.contains("://") {
+        return Err(anyhow::anyhow!(
+            "unsupported uri '{}', only relative paths and data uris are supported",
+            uri
+        ));
+    }
+    let relative_path = urlencoding::decode(uri)
+        .map_err(|err| anyhow::anyhow!("invalid uri '{}' ({})", uri, err))?;
+    let file_path = base_path.join(relative_path.as_ref());
+    std::fs::read(&file_path)
+        .map_err(|err| anyhow::anyhow!("can't read {} ({})", file_path.display(), err))
 }
 
-// struct DataUri<'a> {
-//     mime_type: &'a str,
-//     base64: bool,
-//     data: &'a str,
-// }
-
-// fn split_once(input: &str, delimiter: char) -> Option<(&str, &str)> {
-//     let mut iter = input.splitn(2, delimiter);
-//     Some((iter.next()?, iter.next()?))
-// }
-
-// impl<'a> DataUri<'a> {
-//     fn parse(uri: &'a str) -> Result<DataUri<'a>, ()> {
-//         let uri = uri.strip_prefix("data:").ok_or(())?;
-//         let (mime_type, data) = split_once(uri, ',').ok_or(())?;
-
-//         let (mime_type, base64) = match mime_type.strip_suffix(";base64") {
-//             Some(mime_type) => (mime_type, true),
-//             None => (mime_type, false),
-//         };
-
-//         Ok(DataUri {
-//             mime_type,
-//             base64,
-//             data,
-//         })
-//     }
-
-//     fn decode(&self) -> Result<Vec<u8>, base64::DecodeError> {
-//         if self.base64 {
-//             base64::Engine::decode(&base64::engine::general_purpose::STANDARD, self.data)
-//         } else {
-//             Ok(self.data.as_bytes().to_owned())
-//         }
-//     }
-// }
+fn process_sampler(sampler: &gltf::texture::Sampler) -> SamplerInfo {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+    let address_mode = |mode: WrappingMode| match mode {
+        WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    };
+    let mut info = SamplerInfo {
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        ..Default::default()
+    };
+    if let Some(MagFilter::Nearest) = sampler.mag_filter() {
+        info.mag_filter = wgpu::FilterMode::Nearest;
+    }
+    if let Some(min_filter) = sampler.min_filter() {
+        let (min, mipmap) = match min_filter {
+            MinFilter::Nearest | MinFilter::NearestMipmapNearest => {
+                (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest)
+            }
+            MinFilter::Linear | MinFilter::LinearMipmapLinear => {
+                (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear)
+            }
+            MinFilter::NearestMipmapLinear => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Linear),
+            MinFilter::LinearMipmapNearest => (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest),
+        };
+        info.min_filter = min;
+        info.mipmap_filter = mipmap;
+    }
+    info
+}
 
-pub fn load_gltf(path: &str) -> anyhow::Result<(Model, Vec<Animation>)> {
-    return Err(anyhow::anyhow!("Not working"));
-    let file = std::fs::File::open(path)?;
-    let reader = std::io::BufReader::new(file);
-    let gltf = gltf::Gltf::from_reader(reader)?;
+fn process_texture_ref(texture: &gltf::Texture, tex_coord: u32) -> TextureRef {
+    TextureRef {
+        image: texture.source().index(),
+        tex_coord,
+        sampler: process_sampler(&texture.sampler()),
+    }
+}
+
+// materials in document order, so gltf material indices can be used directly
+pub fn process_materials(document: &gltf::Document) -> Vec<Material> {
+    document
+        .materials()
+        .map(|material| {
+            let pbr = material.pbr_metallic_roughness();
+            Material {
+                name: material
+                    .name()
+                    .map(|name| name.to_string())
+                    .unwrap_or_else(|| format!("material {}", material.index().unwrap_or(0))),
+                base_color_factor: pbr.base_color_factor(),
+                base_color_texture: pbr
+                    .base_color_texture()
+                    .map(|info| process_texture_ref(&info.texture(), info.tex_coord())),
+                metallic_factor: pbr.metallic_factor(),
+                roughness_factor: pbr.roughness_factor(),
+                metallic_roughness_texture: pbr
+                    .metallic_roughness_texture()
+                    .map(|info| process_texture_ref(&info.texture(), info.tex_coord())),
+                normal_texture: material
+                    .normal_texture()
+                    .map(|info| process_texture_ref(&info.texture(), info.tex_coord())),
+                normal_scale: material
+                    .normal_texture()
+                    .map(|info| info.scale())
+                    .unwrap_or(1.0),
+                occlusion_texture: material
+                    .occlusion_texture()
+                    .map(|info| process_texture_ref(&info.texture(), info.tex_coord())),
+                occlusion_strength: material
+                    .occlusion_texture()
+                    .map(|info| info.strength())
+                    .unwrap_or(1.0),
+                emissive_factor: material.emissive_factor(),
+                emissive_texture: material
+                    .emissive_texture()
+                    .map(|info| process_texture_ref(&info.texture(), info.tex_coord())),
+                alpha_mode: match material.alpha_mode() {
+                    gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                    gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+                    gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+                },
+                alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+                double_sided: material.double_sided(),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+// mime types accepted for images embedded as data uris
+const IMAGE_MIME_TYPES: &[&str] = &["image/png", "image/jpeg"];
 
-    // Assuming your GLTF file is in a directory, get the base path
-    let base_path = std::path::Path::new(path).parent().unwrap();
+// encoded bytes of every image in document order, from buffer views,
+// data uris or files relative to the gltf file
+pub fn load_images(
+    path: &str,
+    document: &gltf::Document,
+    buffer_data: &[Vec<u8>],
+) -> anyhow::Result<Vec<Arc<ImageData>>> {
+    let base_path = std::path::Path::new(path)
+        .parent()
+        .unwrap_or(std::path::Path::new(""));
+    document
+        .images()
+        .map(|image| {
+            let name = image
+                .name()
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| format!("image {}", image.index()));
+            let (bytes, mime_type) = match image.source() {
+                gltf::image::Source::View { view, mime_type } => {
+                    let buffer = &buffer_data[view.buffer().index()];
+                    let bytes = buffer
+                        .get(view.offset()..view.offset() + view.length())
+                        .ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "{}: image '{}' is outside of buffer {}",
+                                path,
+                                name,
+                                view.buffer().index()
+                            )
+                        })?;
+                    (bytes.to_vec(), Some(mime_type.to_string()))
+                }
+                gltf::image::Source::Uri { uri, mime_type } => (
+                    read_uri(uri, base_path, IMAGE_MIME_TYPES)
+                        .map_err(|err| anyhow::anyhow!("{}: image '{}': {}", path, name, err))?,
+                    mime_type.map(|mime_type| mime_type.to_string()),
+                ),
+            };
+            Ok(Arc::new(ImageData {
+                name,
+                mime_type,
+                bytes,
+            }))
+        })
+        .collect()
+}
+
+// parse a .gltf/.glb file and load its buffers, external buffers are
+// resolved relative to the gltf file
+pub fn read_gltf(path: &str) -> anyhow::Result<(gltf::Gltf, Vec<Vec<u8>>)> {
+    let file = std::fs::File::open(path)
+        .map_err(|err| anyhow::anyhow!("{}: can't open file ({})", path, err))?;
+    let reader = std::io::BufReader::new(file);
+    let gltf = gltf::Gltf::from_reader(reader)
+        .map_err(|err| anyhow::anyhow!("{}: invalid gltf ({})", path, err))?;
+    let base_path = std::path::Path::new(path)
+        .parent()
+        .unwrap_or(std::path::Path::new(""));
 
     let mut buffer_data: Vec<Vec<u8>> = Vec::new();
-    const VALID_MIME_TYPES: &[&str] = &["application/octet-stream", "application/gltf-buffer"];
     for buffer in gltf.buffers() {
-        match buffer.source() {
-            gltf::buffer::Source::Bin => {
-                if let Some(blob) = gltf.blob.as_deref() {
-                    buffer_data.push(blob.into());
-                } else {
-                    println!("error missing blob");
-                }
-            }
-            gltf::buffer::Source::Uri(uri) => {
-                return Err(anyhow::anyhow!("Unsupported buffer format"));
-                // let uri = percent_encoding::percent_decode_str(uri)
-                //     .decode_utf8()
-                //     .unwrap();
-                // let uri = uri.as_ref();
-                // let buffer_bytes = match DataUri::parse(uri) {
-                //     Ok(data_uri) if VALID_MIME_TYPES.contains(&data_uri.mime_type) => {
-                //         data_uri.decode()?
-                //     }
-                //     _ => {
-                //         return Err(anyhow::anyhow!("Unsupported buffer format"));
-                //     }
-                // };
-                // buffer_data.push(buffer_bytes);
-            }
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => gltf.blob.clone().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{}: buffer {} uses the binary chunk but the file has none",
+                    path,
+                    buffer.index()
+                )
+            })?,
+            gltf::buffer::Source::Uri(uri) => read_uri(uri, base_path, VALID_MIME_TYPES)
+                .map_err(|err| anyhow::anyhow!("{}: buffer {}: {}", path, buffer.index(), err))?,
+        };
+        if data.len() < buffer.length() {
+            return Err(anyhow::anyhow!(
+                "{}: buffer {} has {} bytes, {} expected",
+                path,
+                buffer.index(),
+                data.len(),
+                buffer.length()
+            ));
         }
+        buffer_data.push(data);
+    }
+    Ok((gltf, buffer_data))
+}
+
+// every animation of a .gltf/.glb file as skeleton independent clips
+pub fn load_gltf_animations(path: &str) -> anyhow::Result<Vec<Animation>> {
+    let (gltf, buffer_data) = read_gltf(path)?;
+    Ok(gltf
+        .animations()
+        .map(|animation| process_animation_clip(&animation, &buffer_data))
+        .collect())
+}
+
+// far plane used for infinite perspective projections
+const DEFAULT_FAR_PLANE: f32 = 1000.0;
+
+// gltf cameras look down -z of their node, like Camera with an identity rotation
+pub fn process_camera(camera: &gltf::Camera, world_matrix: cgmath::Matrix4<f32>) -> Camera {
+    let mut transform = Transform::from_matrix(world_matrix);
+    transform.scale = cgmath::Vector3::new(1.0, 1.0, 1.0);
+    match camera.projection() {
+        gltf::camera::Projection::Perspective(perspective) => Camera {
+            transform,
+            fov: perspective.yfov().to_degrees(),
+            // the window aspect ratio is used when the file does not set one
+            aspect_ratio: perspective.aspect_ratio().unwrap_or(1.0),
+            near: perspective.znear(),
+            far: perspective.zfar().unwrap_or(DEFAULT_FAR_PLANE),
+            projection: camera::Projection::Perspective,
+        },
+        gltf::camera::Projection::Orthographic(orthographic) => Camera {
+            transform,
+            fov: 45.0,
+            aspect_ratio: orthographic.xmag() / orthographic.ymag(),
+            near: orthographic.znear(),
+            far: orthographic.zfar(),
+            projection: camera::Projection::Orthographic {
+                x_mag: orthographic.xmag(),
+                y_mag: orthographic.ymag(),
+            },
+        },
+    }
+}
+
+// KHR_lights_punctual light, lights point down -z of their node
+pub fn process_light(
+    light: &gltf::khr_lights_punctual::Light,
+    world_matrix: cgmath::Matrix4<f32>,
+) -> Light {
+    use cgmath::InnerSpace;
+    let direction = (world_matrix * cgmath::Vector4::new(0.0, 0.0, -1.0, 0.0)).truncate();
+    let direction = if direction.magnitude2() > 0.0 {
+        direction.normalize()
+    } else {
+        cgmath::Vector3::new(0.0, 0.0, -1.0)
+    };
+    Light {
+        name: light.name().unwrap_or("light").to_string(),
+        kind: match light.kind() {
+            gltf::khr_lights_punctual::Kind::Directional => LightKind::Directional,
+            gltf::khr_lights_punctual::Kind::Point => LightKind::Point,
+            gltf::khr_lights_punctual::Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => LightKind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            },
+        },
+        color: light.color(),
+        intensity: light.intensity(),
+        range: light.range(),
+        position: world_matrix.w.truncate().into(),
+        direction: direction.into(),
     }
-    let mut animations = Vec::new();
+}
 
-    // Now you have the buffer data in the buffer_data vector
-    for node in gltf.nodes() {
-        match node.mesh() {
+// import the default scene (or the first one) with every node, mesh and animation
+pub fn load_gltf_scene(path: &str) -> anyhow::Result<Scene> {
+    let (gltf, buffer_data) = read_gltf(path)?;
+    let gltf_scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .ok_or_else(|| anyhow::anyhow!("{}: no scene found", path))?;
+    let mut scene = Scene {
+        name: gltf_scene.name().unwrap_or("scene").to_string(),
+        ..Default::default()
+    };
+    // depth first walk, k: gltf node index, v: scene node index
+    let mut visited: HashMap<usize, usize> = HashMap::new();
+    let mut world_matrices: Vec<cgmath::Matrix4<f32>> = Vec::new();
+    let mut stack: Vec<(gltf::Node, Option<usize>)> =
+        gltf_scene.nodes().map(|node| (node, None)).collect();
+    stack.reverse();
+    while let Some((node, parent)) = stack.pop() {
+        if visited.contains_key(&node.index()) {
+            return Err(anyhow::anyhow!(
+                "{}: node {} is reachable more than once from the scene",
+                path,
+                node.index()
+            ));
+        }
+        let index = scene.nodes.len();
+        visited.insert(node.index(), index);
+        let model = match node.mesh() {
             Some(mesh) => {
-                println!("mesh name: {:#?}", mesh.name());
-                //let skin = node.skin();
-                if let Some(skin) = node.skin() {
-                    println!("mesh has skin {:#?}", skin.name());
-                }
-                let model = process_mesh(&mesh, &buffer_data, &node);
-                // process animations
-                for anim in gltf.animations() {
-                    animations.push(process_animations(
-                        &anim,
-                        &buffer_data,
-                        &model.meshes[0].skeleton.as_ref().expect(""),
-                    ));
-                }
-                return Ok((model, animations));
+                let model = process_mesh(&mesh, &gltf.document, &buffer_data, &node)
+                    .map_err(|err| anyhow::anyhow!("{}: node {}: {}", path, node.index(), err))?;
+                scene.models.push(model);
+                Some(scene.models.len() - 1)
             }
-            _ => {}
+            None => None,
+        };
+        let world_matrix = match parent {
+            Some(parent) => world_matrices[parent] * node_local_matrix(&node),
+            None => node_local_matrix(&node),
+        };
+        world_matrices.push(world_matrix);
+        let camera = node.camera().map(|camera| {
+            scene.cameras.push(process_camera(&camera, world_matrix));
+            scene.cameras.len() - 1
+        });
+        let light = node.light().map(|light| {
+            scene.lights.push(process_light(&light, world_matrix));
+            scene.lights.len() - 1
+        });
+        let (translation, rotation, scale) = node.transform().decomposed();
+        scene.nodes.push(SceneNode {
+            name: node
+                .name()
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| format!("node {}", node.index())),
+            transform: Transform::new(
+                cgmath::Vector3::from(translation),
+                cgmath::Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2]),
+                cgmath::Vector3::from(scale),
+            ),
+            parent,
+            children: Vec::new(),
+            model,
+            skin: node.skin().map(|skin| skin.index()),
+            camera,
+            light,
+        });
+        match parent {
+            Some(parent) => scene.nodes[parent].children.push(index),
+            None => scene.roots.push(index),
+        }
+        for child in node.children().collect::<Vec<_>>().into_iter().rev() {
+            stack.push((child, Some(index)));
+        }
+    }
+    scene.materials = process_materials(&gltf.document);
+    scene.images = load_images(path, &gltf.document, &buffer_data)?;
+    scene.animations = gltf
+        .animations()
+        .map(|animation| process_animation_clip(&animation, &buffer_data))
+        .collect();
+    Ok(scene)
+}
+
+// every mesh of the default scene in a single model, with the animations bound
+// to the first skeleton (use load_gltf_scene for files with several skins)
+pub fn load_gltf(path: &str) -> anyhow::Result<(Model, Vec<Animation>)> {
+    let scene = load_gltf_scene(path)?;
+    let mut animations = scene.animations;
+    let model = Scene {
+        animations: Vec::new(),
+        ..scene
+    }
+    .into_model();
+    if model.meshes.is_empty() {
+        return Err(anyhow::anyhow!("{}: no mesh was found", path));
+    }
+    if let Some(skeleton) = model.meshes.iter().find_map(|mesh| mesh.skeleton.as_ref()) {
+        let reports: Vec<ClipBindingReport> = animations
+            .iter_mut()
+            .map(|animation| {
+                animation.bind_bone_ids(skeleton);
+                ClipBindingReport::new(animation, skeleton, path)
+            })
+            .collect();
+        log_binding_reports(&reports);
+    }
+    Ok((model, animations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // skin of a json only gltf file, the inverse bind matrices default to identity
+    fn skin_of(json: &str) -> anyhow::Result<Skeleton> {
+        let gltf = gltf::Gltf::from_slice(json.as_bytes())?;
+        let skin = gltf.document.skins().next().unwrap();
+        process_skin(&skin, &gltf.document, &[])
+    }
+
+    #[test]
+    fn joints_are_ordered_parents_first() {
+        // node 1 is not a joint, its transform becomes the offset of node 2
+        let skeleton = skin_of(
+            r#"{
+                "asset": {"version": "2.0"},
+                "nodes": [
+                    {"name": "root", "translation": [0, 1, 0], "children": [1]},
+                    {"name": "offset", "translation": [0, 0, 2], "children": [2]},
+                    {"name": "tip"}
+                ],
+                "skins": [{"name": "rig", "joints": [2, 0]}]
+            }"#,
+        )
+        .unwrap();
+        let names: Vec<&str> = skeleton
+            .bones_ordered
+            .iter()
+            .map(|bone| bone.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["root", "tip"]);
+        let (root, tip) = (&skeleton.bones_ordered[0], &skeleton.bones_ordered[1]);
+        assert_eq!(root.parent_id, None);
+        assert_eq!(tip.parent_id, Some(0));
+        // the vertex joint indices still point in the skin list
+        assert_eq!((root.index, tip.index), (1, 0));
+        assert_eq!(root.parent_offset, None);
+        let offset: [[f32; 4]; 4] =
+            cgmath::Matrix4::from_translation(cgmath::Vector3::new(0.0, 0.0, 2.0)).into();
+        assert_eq!(tip.parent_offset, Some(offset));
+        assert_eq!(skeleton.bones[&1].name, "tip");
+    }
+
+    #[test]
+    fn cyclic_hierarchies_are_rejected() {
+        // two joints that are parents of each other
+        let error = skin_of(
+            r#"{
+                "asset": {"version": "2.0"},
+                "nodes": [{"name": "a", "children": [1]}, {"name": "b", "children": [0]}],
+                "skins": [{"joints": [0, 1]}]
+            }"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("form a cycle"), "{}", error);
+        // a joint below a loop of non joint nodes
+        let error = skin_of(
+            r#"{
+                "asset": {"version": "2.0"},
+                "nodes": [
+                    {"name": "joint"},
+                    {"name": "a", "children": [0, 2]},
+                    {"name": "b", "children": [1]}
+                ],
+                "skins": [{"joints": [0]}]
+            }"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("is cyclic"), "{}", error);
+    }
+
+    #[test]
+    fn cubic_spline_keeps_the_key_values() {
+        // buffer: 2 key times, 6 cubic translations (tangent, value, tangent per key),
+        // then 3 linear rotations for the same 2 keys
+        let mut floats: Vec<f32> = vec![0.0, 1.0];
+        for value in [[9.0, 9.0, 9.0], [1.0, 2.0, 3.0], [9.0, 9.0, 9.0]] {
+            floats.extend(value);
+        }
+        for value in [[9.0, 9.0, 9.0], [4.0, 5.0, 6.0], [9.0, 9.0, 9.0]] {
+            floats.extend(value);
         }
+        for _ in 0..3 {
+            floats.extend([0.0, 0.0, 0.0, 1.0]);
+        }
+        let buffer: Vec<u8> = bytemuck::cast_slice(&floats).to_vec();
+        let json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "nodes": [{{"name": "hips"}}],
+                "buffers": [{{"byteLength": {length}}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteOffset": 0, "byteLength": 8}},
+                    {{"buffer": 0, "byteOffset": 8, "byteLength": 72}},
+                    {{"buffer": 0, "byteOffset": 80, "byteLength": 48}}
+                ],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": 2, "type": "SCALAR",
+                      "min": [0.0], "max": [1.0]}},
+                    {{"bufferView": 1, "componentType": 5126, "count": 6, "type": "VEC3"}},
+                    {{"bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC4"}}
+                ],
+                "animations": [{{
+                    "name": "move",
+                    "samplers": [
+                        {{"input": 0, "output": 1, "interpolation": "CUBICSPLINE"}},
+                        {{"input": 0, "output": 2, "interpolation": "LINEAR"}}
+                    ],
+                    "channels": [
+                        {{"sampler": 0, "target": {{"node": 0, "path": "translation"}}}},
+                        {{"sampler": 1, "target": {{"node": 0, "path": "rotation"}}}}
+                    ]
+                }}]
+            }}"#,
+            length = buffer.len()
+        );
+        let gltf = gltf::Gltf::from_slice(json.as_bytes()).unwrap();
+        let animation = gltf.document.animations().next().unwrap();
+        let clip = process_animation_clip(&animation, &[buffer]);
+
+        let track = clip.track("hips").unwrap();
+        assert_eq!(
+            track.translation_keys,
+            vec![
+                KeyTranslation {
+                    timestamp: 0.0,
+                    translation: [1.0, 2.0, 3.0],
+                },
+                KeyTranslation {
+                    timestamp: 1.0,
+                    translation: [4.0, 5.0, 6.0],
+                },
+            ]
+        );
+        // 3 rotations for 2 key times: the channel is dropped
+        assert!(track.rotation_keys.is_empty());
     }
-    Err(anyhow::anyhow!("No mesh was found"))
 }
@@ -0,0 +1,214 @@
+use crate::json_schema::{
+    AnimationData, AnimationFile, BoneData, BoneTrackData, Header, MeshData, MeshFile,
+    RotationKeyData, ScaleKeyData, SkeletonData, TranslationKeyData, VertexData, ANIMATION_FORMAT,
+    MESH_FORMAT,
+};
+use crate::model::{AnimatedBone, Animation, Model, ModelVertex};
+use cgmath::Vector3;
+use serde::Serialize;
+use serde_json::Value;
+use std::{fs::File, io::Write};
+
+// write meshes and the skeleton with the schema read by obj_loader::load_json_model,
+// materials are not part of the format
+pub fn write_json_model(filepath: &str, model: &Model) -> anyhow::Result<()> {
+    write_pretty(filepath, &json_mesh_file(model))
+}
+
+pub fn json_mesh_file(model: &Model) -> MeshFile {
+    let meshes = model
+        .meshes
+        .iter()
+        .map(|mesh| MeshData {
+            name: mesh.name.clone(),
+            vertices: mesh.vertices.iter().map(json_vertex).collect(),
+            indices: mesh.indices.clone(),
+        })
+        .collect();
+    // the loader gives the skeleton to the first mesh
+    let skeleton = model
+        .meshes
+        .iter()
+        .find_map(|mesh| mesh.skeleton.as_ref())
+        .map(|skeleton| SkeletonData {
+            bones: skeleton
+                .bones_ordered
+                .iter()
+                .map(|bone| {
+                    let parent = bone
+                        .parent_id
+                        .and_then(|parent| skeleton.bones_ordered.get(parent));
+                    BoneData {
+                        id: bone.id,
+                        name: bone.name.clone(),
+                        parent_name: parent.map(|parent| parent.name.clone()),
+                        parent_id: parent.map_or(-1, |parent| parent.id as i32),
+                        offset: bone.inverse_bind_matrix,
+                    }
+                })
+                .collect(),
+        });
+    MeshFile {
+        header: Some(Header::new(MESH_FORMAT)),
+        meshes,
+        skeleton,
+    }
+}
+
+fn json_vertex(vertex: &ModelVertex) -> VertexData {
+    let influences = vertex.bone_influences();
+    // four slots like the blender exports, eight when the second set is used
+    let slots = if influences[4..].iter().any(|(_, weight)| *weight > 0.0) {
+        8
+    } else {
+        4
+    };
+    let normal = Vector3::from(vertex.normal);
+    let tangent = Vector3::from([vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]]);
+    let has_tangent = vertex.tangent[3] != 0.0;
+    VertexData {
+        position: vertex.position,
+        normal: Some(vertex.normal),
+        tex_coords: Some(vertex.tex_coords),
+        tex_coords_1: (vertex.tex_coords_1 != [0.0, 0.0]).then_some(vertex.tex_coords_1),
+        tangent: has_tangent.then_some(tangent.into()),
+        bitangent: has_tangent.then(|| (normal.cross(tangent) * vertex.tangent[3]).into()),
+        bone_ids: influences[..slots]
+            .iter()
+            .map(|(id, weight)| if *weight > 0.0 { *id as i32 } else { -1 })
+            .collect(),
+        weights: influences[..slots]
+            .iter()
+            .map(|(_, weight)| *weight)
+            .collect(),
+    }
+}
+
+// write animations with the schema read by obj_loader::json_anim_loader
+pub fn write_json_animations<'a>(
+    filepath: &str,
+    animations: impl IntoIterator<Item = &'a Animation>,
+) -> anyhow::Result<()> {
+    write_pretty(filepath, &json_animation_file(animations))
+}
+
+fn write_pretty(filepath: &str, value: &impl Serialize) -> anyhow::Result<()> {
+    let file = File::create(filepath)
+        .map_err(|err| anyhow::anyhow!("{}: can't create file ({})", filepath, err))?;
+    let mut writer = std::io::BufWriter::new(file);
+    // same indentation as the files exported by the blender script
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+    let mut serializer = serde_json::Serializer::with_formatter(&mut writer, formatter);
+    value.serialize(&mut serializer)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn json_animation_file<'a>(
+    animations: impl IntoIterator<Item = &'a Animation>,
+) -> AnimationFile {
+    let animations = animations
+        .into_iter()
+        .map(|animation| AnimationData {
+            name: animation.name.clone(),
+            bones: animation_tracks(animation)
+                .into_iter()
+                .map(json_bone_track)
+                .collect(),
+        })
+        .collect();
+    AnimationFile {
+        header: Some(Header::new(ANIMATION_FORMAT)),
+        animations,
+    }
+}
+
+// tracks sorted by name so the output does not depend on the loading order
+fn animation_tracks(animation: &Animation) -> Vec<&AnimatedBone> {
+    let mut tracks: Vec<&AnimatedBone> = animation.tracks().iter().collect();
+    tracks.sort_by(|a, b| a.bone_name.cmp(&b.bone_name));
+    tracks
+}
+
+fn json_bone_track(bone: &AnimatedBone) -> BoneTrackData {
+    BoneTrackData {
+        name: bone.bone_name.clone(),
+        translation_keys: bone
+            .translation_keys
+            .iter()
+            .map(|key| TranslationKeyData {
+                position: key.translation,
+                time: key.timestamp,
+            })
+            .collect(),
+        rotation_keys: bone
+            .rotation_keys
+            .iter()
+            .map(|key| RotationKeyData {
+                rotation: key.rotation,
+                time: key.timestamp,
+            })
+            .collect(),
+        scale_keys: bone
+            .scale_keys
+            .iter()
+            .map(|key| ScaleKeyData {
+                scale: key.scale,
+                time: key.timestamp,
+            })
+            .collect(),
+    }
+}
+
+// shortest decimal that reads back as the same f32 (widening to f64 directly
+// would write 1.72462 as 1.7246199846267700), non finite values become null
+pub fn json_f32(value: f32) -> Value {
+    value
+        .to_string()
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obj_loader::json_anim_clips;
+
+    #[test]
+    fn animations_round_trip() {
+        let clips = json_anim_clips("res/anim_data.json").unwrap();
+        assert!(!clips.is_empty());
+        let path =
+            std::env::temp_dir().join(format!("anim_round_trip_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_json_animations(path, &clips).unwrap();
+        let reparsed = json_anim_clips(path);
+        std::fs::remove_file(path).unwrap();
+        let reparsed = reparsed.unwrap();
+
+        assert_eq!(clips.len(), reparsed.len());
+        for (clip, other) in clips.iter().zip(&reparsed) {
+            assert_eq!(clip.name, other.name);
+            assert!(clip
+                .tracks()
+                .iter()
+                .any(|track| !track.rotation_keys.is_empty()));
+            assert_eq!(clip.tracks().len(), other.tracks().len());
+            for track in clip.tracks() {
+                let name = &track.bone_name;
+                let other_track = other.track(name).unwrap();
+                assert_eq!(
+                    track.translation_keys, other_track.translation_keys,
+                    "{}",
+                    name
+                );
+                assert_eq!(track.rotation_keys, other_track.rotation_keys, "{}", name);
+                assert_eq!(track.scale_keys, other_track.scale_keys, "{}", name);
+            }
+        }
+    }
+}
@@ -0,0 +1,449 @@
+// serde schema of the custom json formats (mesh_data.json and anim_data.json)
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs::File, io::BufReader, marker::PhantomData};
+
+// version written in the header, files without a header are version 1
+pub const JSON_FORMAT_VERSION: u32 = 1;
+pub const MESH_FORMAT: &str = "mesh";
+pub const ANIMATION_FORMAT: &str = "animation";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Header {
+    // MESH_FORMAT or ANIMATION_FORMAT
+    pub format: String,
+    pub version: u32,
+}
+
+impl Header {
+    pub fn new(format: &str) -> Self {
+        Self {
+            format: format.to_string(),
+            version: JSON_FORMAT_VERSION,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MeshFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<Header>,
+    pub meshes: Vec<MeshData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skeleton: Option<SkeletonData>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MeshData {
+    // lowercase in the files exported by the blender script
+    #[serde(default, rename = "name", alias = "Name")]
+    pub name: String,
+    pub vertices: Vec<VertexData>,
+    pub indices: Vec<u32>,
+}
+
+// attributes other than the position are optional, missing normals and
+// tangents are generated by the loader
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct VertexData {
+    pub position: [f32; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normal: Option<[f32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tex_coords: Option<[f32; 2]>,
+    // second uv set, for the textures using texcoord 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tex_coords_1: Option<[f32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tangent: Option<[f32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitangent: Option<[f32; 3]>,
+    // one weight per bone id
+    #[serde(default, rename = "BoneIDs", skip_serializing_if = "Vec::is_empty")]
+    pub bone_ids: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub weights: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SkeletonData {
+    pub bones: Vec<BoneData>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct BoneData {
+    // ids go from 0 to the bone count - 1
+    pub id: u32,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_name: Option<String>,
+    // -1 for the root bones
+    pub parent_id: i32,
+    // inverse bind matrix
+    pub offset: [[f32; 4]; 4],
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AnimationFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<Header>,
+    pub animations: Vec<AnimationData>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AnimationData {
+    pub name: String,
+    pub bones: Vec<BoneTrackData>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct BoneTrackData {
+    pub name: String,
+    #[serde(default)]
+    pub translation_keys: Vec<TranslationKeyData>,
+    #[serde(default)]
+    pub rotation_keys: Vec<RotationKeyData>,
+    #[serde(default)]
+    pub scale_keys: Vec<ScaleKeyData>,
+}
+
+// times are in milliseconds
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TranslationKeyData {
+    pub position: [f32; 3],
+    pub time: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RotationKeyData {
+    // x, y, z, w
+    pub rotation: [f32; 4],
+    pub time: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ScaleKeyData {
+    pub scale: [f32; 3],
+    pub time: f32,
+}
+
+// parse a json file, errors name the file and the json path of the bad value
+pub fn read_json_file<T: DeserializeOwned>(filepath: &str) -> anyhow::Result<T> {
+    read_json_file_seed(filepath, PhantomData::<T>)
+}
+
+// same as read_json_file with a seed, to handle the values while they are parsed
+pub fn read_json_file_seed<'de, S: DeserializeSeed<'de>>(
+    filepath: &str,
+    seed: S,
+) -> anyhow::Result<S::Value> {
+    let file = File::open(filepath)
+        .map_err(|err| anyhow::anyhow!("{}: can't open file ({})", filepath, err))?;
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(file));
+    let mut track = serde_path_to_error::Track::new();
+    let value = seed
+        .deserialize(serde_path_to_error::Deserializer::new(
+            &mut deserializer,
+            &mut track,
+        ))
+        .and_then(|value| deserializer.end().map(|_| value));
+    value.map_err(|err| {
+        let path = track.path().to_string();
+        if path == "." {
+            anyhow::anyhow!("{}: {}", filepath, err)
+        } else {
+            anyhow::anyhow!("{}: {}: {}", filepath, path, err)
+        }
+    })
+}
+
+// files without a header are accepted as version 1 of any format
+pub fn check_header(filepath: &str, header: Option<&Header>, format: &str) -> anyhow::Result<()> {
+    let Some(header) = header else {
+        return Ok(());
+    };
+    if header.format != format {
+        return Err(anyhow::anyhow!(
+            "{}: Header.Format: expected '{}', found '{}'",
+            filepath,
+            format,
+            header.format
+        ));
+    }
+    if header.version == 0 || header.version > JSON_FORMAT_VERSION {
+        return Err(anyhow::anyhow!(
+            "{}: Header.Version: version {} is not supported (at most {})",
+            filepath,
+            header.version,
+            JSON_FORMAT_VERSION
+        ));
+    }
+    Ok(())
+}
+
+impl MeshFile {
+    pub fn read(filepath: &str) -> anyhow::Result<Self> {
+        let file: Self = read_json_file(filepath)?;
+        check_header(filepath, file.header.as_ref(), MESH_FORMAT)?;
+        file.validate()
+            .map_err(|err| anyhow::anyhow!("{}: {}", filepath, err))?;
+        Ok(file)
+    }
+
+    // checks serde can't express, errors start with the json path of the value
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (mesh_index, mesh) in self.meshes.iter().enumerate() {
+            if mesh.indices.len() % 3 != 0 {
+                return Err(anyhow::anyhow!(
+                    "Meshes[{}].Indices: {} indices is not a whole number of triangles",
+                    mesh_index,
+                    mesh.indices.len()
+                ));
+            }
+            if let Some((index_index, index)) = mesh
+                .indices
+                .iter()
+                .enumerate()
+                .find(|(_, index)| **index as usize >= mesh.vertices.len())
+            {
+                return Err(anyhow::anyhow!(
+                    "Meshes[{}].Indices[{}]: index {} is out of range ({} vertices)",
+                    mesh_index,
+                    index_index,
+                    index,
+                    mesh.vertices.len()
+                ));
+            }
+            for (vertex_index, vertex) in mesh.vertices.iter().enumerate() {
+                if vertex.bone_ids.len() != vertex.weights.len() {
+                    return Err(anyhow::anyhow!(
+                        "Meshes[{}].Vertices[{}]: {} BoneIDs but {} Weights",
+                        mesh_index,
+                        vertex_index,
+                        vertex.bone_ids.len(),
+                        vertex.weights.len()
+                    ));
+                }
+            }
+        }
+        if let Some(skeleton) = &self.skeleton {
+            let bones: Vec<(u32, i64)> = skeleton
+                .bones
+                .iter()
+                .map(|bone| (bone.id, bone.parent_id as i64))
+                .collect();
+            check_bone_hierarchy(&bones).map_err(|err| anyhow::anyhow!("Skeleton.{}", err))?;
+        }
+        Ok(())
+    }
+}
+
+// checks shared by the skeleton readers on (id, parent id) pairs, -1 for a root:
+// ids go from 0 to the bone count - 1 without duplicates, parents are in range and
+// no bone is its own ancestor, errors start with the path of the bone
+pub fn check_bone_hierarchy(bones: &[(u32, i64)]) -> anyhow::Result<()> {
+    let bone_count = bones.len();
+    let mut seen = vec![false; bone_count];
+    for (bone_index, (id, parent_id)) in bones.iter().enumerate() {
+        if *id as usize >= bone_count {
+            return Err(anyhow::anyhow!(
+                "Bones[{}].Id: id {} is out of range ({} bones)",
+                bone_index,
+                id,
+                bone_count
+            ));
+        }
+        if std::mem::replace(&mut seen[*id as usize], true) {
+            return Err(anyhow::anyhow!(
+                "Bones[{}].Id: id {} is used by another bone",
+                bone_index,
+                id
+            ));
+        }
+        if *parent_id < -1 || *parent_id >= bone_count as i64 {
+            return Err(anyhow::anyhow!(
+                "Bones[{}].ParentId: parent {} is out of range ({} bones)",
+                bone_index,
+                parent_id,
+                bone_count
+            ));
+        }
+    }
+    // the ids are unique and in range, walking up from a bone in a
+    // cycle comes back to it within bone_count steps
+    let mut parents = vec![-1; bone_count];
+    for (id, parent_id) in bones {
+        parents[*id as usize] = *parent_id;
+    }
+    for (bone_index, (id, parent_id)) in bones.iter().enumerate() {
+        let mut chain = vec![*id as i64];
+        let mut parent = *parent_id;
+        while parent >= 0 && chain.len() <= bone_count {
+            chain.push(parent);
+            if parent == *id as i64 {
+                let chain: Vec<String> = chain.iter().map(|id| id.to_string()).collect();
+                return Err(anyhow::anyhow!(
+                    "Bones[{}].ParentId: bone {} is its own ancestor ({})",
+                    bone_index,
+                    id,
+                    chain.join(" -> ")
+                ));
+            }
+            parent = parents[parent as usize];
+        }
+    }
+    Ok(())
+}
+
+impl AnimationFile {
+    pub fn read(filepath: &str) -> anyhow::Result<Self> {
+        let mut animations = Vec::new();
+        let header = Self::for_each_clip(filepath, |animation| animations.push(animation))?;
+        Ok(Self { header, animations })
+    }
+
+    // parse the clips one at a time, each one is handed to `f` as soon as it is
+    // parsed so the file is never held in memory as a whole, returns the header
+    pub fn for_each_clip(
+        filepath: &str,
+        mut f: impl FnMut(AnimationData),
+    ) -> anyhow::Result<Option<Header>> {
+        let header = read_json_file_seed(filepath, AnimationFileSeed(&mut f))?;
+        check_header(filepath, header.as_ref(), ANIMATION_FORMAT)?;
+        Ok(header)
+    }
+}
+
+// visits the top level of an animation file, the header is the value
+struct AnimationFileSeed<'a, F>(&'a mut F);
+
+impl<'de, F: FnMut(AnimationData)> DeserializeSeed<'de> for AnimationFileSeed<'_, F> {
+    type Value = Option<Header>;
+
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, F: FnMut(AnimationData)> Visitor<'de> for AnimationFileSeed<'_, F> {
+    type Value = Option<Header>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an animation file")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut header = None;
+        let mut has_animations = false;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "Header" => header = map.next_value::<Option<Header>>()?,
+                "Animations" => {
+                    map.next_value_seed(ClipSeqSeed(&mut *self.0))?;
+                    has_animations = true;
+                }
+                // like the derived structs, unknown fields are ignored
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        if !has_animations {
+            return Err(de::Error::missing_field("Animations"));
+        }
+        Ok(header)
+    }
+}
+
+// visits the Animations array, one clip at a time
+struct ClipSeqSeed<'a, F>(&'a mut F);
+
+impl<'de, F: FnMut(AnimationData)> DeserializeSeed<'de> for ClipSeqSeed<'_, F> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F: FnMut(AnimationData)> Visitor<'de> for ClipSeqSeed<'_, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sequence of animations")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(animation) = seq.next_element::<AnimationData>()? {
+            (self.0)(animation);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skeleton_file(parents: &[i32]) -> MeshFile {
+        MeshFile {
+            skeleton: Some(SkeletonData {
+                bones: parents
+                    .iter()
+                    .enumerate()
+                    .map(|(id, parent_id)| BoneData {
+                        id: id as u32,
+                        name: format!("bone_{}", id),
+                        parent_id: *parent_id,
+                        ..Default::default()
+                    })
+                    .collect(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parent_cycles_are_rejected() {
+        let err = skeleton_file(&[1, 0]).validate().unwrap_err().to_string();
+        assert!(
+            err.starts_with("Skeleton.Bones[0].ParentId: bone 0 is its own ancestor"),
+            "{}",
+            err
+        );
+        assert!(err.contains("0 -> 1 -> 0"), "{}", err);
+
+        let err = skeleton_file(&[-1, 1]).validate().unwrap_err().to_string();
+        assert!(err.starts_with("Skeleton.Bones[1].ParentId"), "{}", err);
+
+        // bone 0 hangs below the 1 -> 2 -> 3 -> 1 cycle without being in it
+        let err = skeleton_file(&[1, 3, 1, 2])
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("Skeleton.Bones[1].ParentId"), "{}", err);
+    }
+
+    #[test]
+    fn trees_are_accepted() {
+        skeleton_file(&[-1, 0, 1, 1, 0]).validate().unwrap();
+        skeleton_file(&[-1, -1, 0]).validate().unwrap();
+    }
+}
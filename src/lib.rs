@@ -0,0 +1,35 @@
+pub mod animation_library;
+pub mod animation_lod;
+pub mod app;
+pub mod binary_format;
+pub mod bvh_loader;
+pub mod camera;
+pub mod glb_exporter;
+pub mod gltf_loader;
+pub mod input;
+pub mod json_exporter;
+pub mod json_schema;
+pub mod light;
+pub mod material;
+pub mod mesh_optimizer;
+pub mod mesh_utils;
+pub mod model;
+pub mod model_loader;
+pub mod model_report;
+pub mod model_shader;
+pub mod obj_exporter;
+pub mod obj_loader;
+pub mod parallel;
+pub mod ply_loader;
+pub mod renderer;
+pub mod scene;
+pub mod shader;
+pub mod skin_validation;
+pub mod stl_loader;
+pub mod testing;
+pub mod texture;
+pub mod tokens;
+pub mod transform;
+pub mod vertex;
+pub mod vertex_packing;
+pub mod window;
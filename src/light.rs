@@ -11,6 +11,50 @@ pub struct LightUniform {
     pub _padding2: u32,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    // lights along `direction`, position is ignored
+    Directional,
+    Point,
+    // cone angles in radians from the direction
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+// scene light (e.g. imported from KHR_lights_punctual), in scene space
+#[derive(Debug, Clone, PartialEq)]
+pub struct Light {
+    pub name: String,
+    pub kind: LightKind,
+    pub color: [f32; 3],
+    // candela for point and spot lights, lux for directional lights
+    pub intensity: f32,
+    // None is infinite
+    pub range: Option<f32>,
+    pub position: [f32; 3],
+    pub direction: [f32; 3],
+}
+
+// distance used to place directional lights for the point light shader
+const DIRECTIONAL_LIGHT_DISTANCE: f32 = 1000.0;
+
+impl Light {
+    pub fn to_uniform(&self) -> LightUniform {
+        let position = match self.kind {
+            LightKind::Directional => self.direction.map(|d| -d * DIRECTIONAL_LIGHT_DISTANCE),
+            _ => self.position,
+        };
+        LightUniform {
+            position,
+            _padding: 0,
+            color: self.color,
+            _padding2: 0,
+        }
+    }
+}
+
 pub struct LightBufferHandler {
     pub buffer: wgpu::Buffer,
     pub buffer_bind_group: wgpu::BindGroup,
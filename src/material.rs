@@ -0,0 +1,393 @@
+use crate::texture::Texture;
+use std::sync::{Arc, Weak};
+use wgpu::util::DeviceExt;
+
+// sampler state of a texture reference, defaults to linear filtering and repeat
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplerInfo {
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+}
+
+impl Default for SamplerInfo {
+    fn default() -> Self {
+        Self {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+        }
+    }
+}
+
+impl SamplerInfo {
+    pub fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        wgpu::SamplerDescriptor {
+            label: Some("Material sampler"),
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureRef {
+    // index in Model::images
+    pub image: usize,
+    // TEXCOORD_n set used to sample the texture
+    pub tex_coord: u32,
+    pub sampler: SamplerInfo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    // fragments with alpha below Material::alpha_cutoff are discarded
+    Mask,
+    Blend,
+}
+
+// pbr metallic roughness material, defaults follow the gltf specification
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub name: String,
+    pub base_color_factor: [f32; 4],
+    pub base_color_texture: Option<TextureRef>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    // metalness in the blue channel, roughness in the green channel
+    pub metallic_roughness_texture: Option<TextureRef>,
+    pub normal_texture: Option<TextureRef>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<TextureRef>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+    pub emissive_texture: Option<TextureRef>,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+    // specular color of obj/mtl materials (Ks, map_Ks), not used by the pbr shader
+    pub specular_color_factor: [f32; 3],
+    pub specular_texture: Option<TextureRef>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            base_color_factor: [1.0, 1.0, 1.0, 1.0],
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_factor: [0.0, 0.0, 0.0],
+            emissive_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+            specular_color_factor: [1.0, 1.0, 1.0],
+            specular_texture: None,
+        }
+    }
+}
+
+// encoded image (png, jpeg...), decoded when the model is uploaded
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImageData {
+    pub name: String,
+    pub mime_type: Option<String>,
+    pub bytes: Vec<u8>,
+}
+
+impl ImageData {
+    // the stored mime type, or png/jpeg from the first bytes
+    pub fn guessed_mime_type(&self) -> Option<&str> {
+        match &self.mime_type {
+            Some(mime_type) => Some(mime_type),
+            None if self.bytes.starts_with(b"\x89PNG") => Some("image/png"),
+            None if self.bytes.starts_with(&[0xFF, 0xD8]) => Some("image/jpeg"),
+            None => None,
+        }
+    }
+}
+
+// textures sampled by the model shader, in binding order (texture 2 + 2 * slot,
+// sampler 3 + 2 * slot)
+pub const TEXTURE_SLOTS: usize = 5;
+const NORMAL_SLOT: usize = 3;
+
+impl Material {
+    // (texture, srgb) of every shader slot: base color, emissive, metallic
+    // roughness, normal and occlusion, the color maps are srgb encoded
+    pub fn texture_slots(&self) -> [(Option<&TextureRef>, bool); TEXTURE_SLOTS] {
+        [
+            (self.base_color_texture.as_ref(), true),
+            (self.emissive_texture.as_ref(), true),
+            (self.metallic_roughness_texture.as_ref(), false),
+            (self.normal_texture.as_ref(), false),
+            (self.occlusion_texture.as_ref(), false),
+        ]
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    pub base_color_factor: [f32; 4],
+    pub emissive_factor: [f32; 3],
+    // 0 when the alpha mode is not Mask
+    pub alpha_cutoff: f32,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    // TEXCOORD set of every texture slot (0 or 1), padded to two vec4<u32>
+    pub tex_coords: [[u32; 4]; 2],
+}
+
+impl MaterialUniform {
+    pub fn new(material: &Material) -> Self {
+        let mut tex_coords = [[0; 4]; 2];
+        for (slot, (texture_ref, _)) in material.texture_slots().iter().enumerate() {
+            tex_coords[slot / 4][slot % 4] =
+                texture_ref.map_or(0, |texture_ref| texture_ref.tex_coord.min(1));
+        }
+        Self {
+            base_color_factor: material.base_color_factor,
+            emissive_factor: material.emissive_factor,
+            alpha_cutoff: match material.alpha_mode {
+                AlphaMode::Mask => material.alpha_cutoff,
+                _ => 0.0,
+            },
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+            tex_coords,
+        }
+    }
+}
+
+fn pixel_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    pixel: [u8; 4],
+    label: &str,
+) -> Texture {
+    let image =
+        image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(pixel)));
+    Texture::from_image_format(
+        device,
+        queue,
+        &image,
+        Some(label),
+        wgpu::TextureFormat::Rgba8Unorm,
+    )
+    .expect(label)
+}
+
+// 1x1 texture used by the material slots without an image
+pub fn white_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+    pixel_texture(device, queue, [255, 255, 255, 255], "White texture")
+}
+
+// 1x1 tangent space normal pointing along the surface normal, for meshes
+// without a normal map
+pub fn flat_normal_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+    pixel_texture(device, queue, [128, 128, 255, 255], "Flat normal texture")
+}
+
+// decoded images, shared by the models using the same image (the models of a
+// scene share its images), an entry lives as long as a model uses it
+#[derive(Default)]
+pub struct TextureCache {
+    entries: Vec<(Weak<ImageData>, bool, Weak<Texture>)>,
+}
+
+impl TextureCache {
+    // the texture of the image in srgb or linear format, None when the image
+    // can't be decoded
+    pub fn get_or_decode(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &Arc<ImageData>,
+        srgb: bool,
+    ) -> Option<Arc<Texture>> {
+        self.entries
+            .retain(|(image, _, texture)| image.strong_count() > 0 && texture.strong_count() > 0);
+        let cached = self
+            .entries
+            .iter()
+            .find(|(other, other_srgb, _)| {
+                *other_srgb == srgb && std::ptr::eq(other.as_ptr(), Arc::as_ptr(image))
+            })
+            .and_then(|(_, _, texture)| texture.upgrade());
+        if cached.is_some() {
+            return cached;
+        }
+        let format = if srgb {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+        let texture = image::load_from_memory(&image.bytes)
+            .map_err(anyhow::Error::from)
+            .and_then(|decoded| {
+                Texture::from_image_format(device, queue, &decoded, Some(&image.name), format)
+            });
+        match texture {
+            Ok(texture) => {
+                let texture = Arc::new(texture);
+                self.entries
+                    .push((Arc::downgrade(image), srgb, Arc::downgrade(&texture)));
+                Some(texture)
+            }
+            Err(err) => {
+                eprintln!("can't decode image '{}': {}", image.name, err);
+                None
+            }
+        }
+    }
+
+    // the textures of every slot of a material, None for the empty slots
+    pub fn material_textures(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[Arc<ImageData>],
+        material: &Material,
+    ) -> [Option<Arc<Texture>>; TEXTURE_SLOTS] {
+        material.texture_slots().map(|(texture_ref, srgb)| {
+            let image = images.get(texture_ref?.image)?;
+            self.get_or_decode(device, queue, image, srgb)
+        })
+    }
+}
+
+// material uniform, textures and light of one mesh (bind group 0 of the model shader)
+pub struct MaterialBufferHandler {
+    pub buffer: wgpu::Buffer,
+    pub samplers: Vec<wgpu::Sampler>,
+    // kept alive for the bind group, shared with the other models using them
+    pub textures: [Option<Arc<Texture>>; TEXTURE_SLOTS],
+    pub buffer_bind_group: wgpu::BindGroup,
+}
+
+impl MaterialBufferHandler {
+    // the empty slots sample the white texture, or the flat normal for the normal map
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        light_buffer: &wgpu::Buffer,
+        material: &Material,
+        textures: [Option<Arc<Texture>>; TEXTURE_SLOTS],
+        white_texture: &Texture,
+        flat_normal_texture: &Texture,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[MaterialUniform::new(material)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let views: Vec<&wgpu::TextureView> = textures
+            .iter()
+            .enumerate()
+            .map(|(slot, texture)| match texture {
+                Some(texture) => &texture.view,
+                None if slot == NORMAL_SLOT => &flat_normal_texture.view,
+                None => &white_texture.view,
+            })
+            .collect();
+        let samplers: Vec<wgpu::Sampler> = material
+            .texture_slots()
+            .iter()
+            .map(|(texture_ref, _)| {
+                let info = texture_ref
+                    .map(|texture_ref| texture_ref.sampler)
+                    .unwrap_or_default();
+                device.create_sampler(&info.descriptor())
+            })
+            .collect();
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: light_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: buffer.as_entire_binding(),
+            },
+        ];
+        for (slot, (view, sampler)) in views.iter().zip(&samplers).enumerate() {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 2 + 2 * slot as u32,
+                resource: wgpu::BindingResource::TextureView(view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: 3 + 2 * slot as u32,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            });
+        }
+        let buffer_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some("Material bind group"),
+        });
+
+        Self {
+            buffer,
+            samplers,
+            textures,
+            buffer_bind_group,
+        }
+    }
+
+    pub fn layout_entries() -> Vec<wgpu::BindGroupLayoutEntry> {
+        let uniform = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        // light and material, then a texture and a sampler per slot
+        let mut entries = vec![uniform(0), uniform(1)];
+        for slot in 0..TEXTURE_SLOTS as u32 {
+            entries.push(texture(2 + 2 * slot));
+            entries.push(sampler(3 + 2 * slot));
+        }
+        entries
+    }
+}
@@ -0,0 +1,439 @@
+// mesh optimization before the upload: identical vertices are welded, the
+// triangles reordered for the post-transform vertex cache (tom forsyth's linear
+// speed vertex cache optimisation) and the vertices reordered by first use so
+// the vertex fetch reads memory in order
+use crate::mesh_utils::has_vertex_colors;
+use crate::model::{Mesh, Model, ModelVertex};
+use std::collections::HashMap;
+use std::fmt;
+
+// entries of the simulated post-transform cache, the triangle order targets
+// the same size
+pub const DEFAULT_CACHE_SIZE: usize = 32;
+
+// bits of a vertex and of its color, welded vertices have the same key
+type WeldKey = ([u32; std::mem::size_of::<ModelVertex>() / 4], [u32; 4]);
+
+// forsyth's scoring constants
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OptimizeOptions {
+    pub weld: bool,
+    pub vertex_cache: bool,
+    pub vertex_fetch: bool,
+    pub cache_size: usize,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        Self {
+            weld: true,
+            vertex_cache: true,
+            vertex_fetch: true,
+            cache_size: DEFAULT_CACHE_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizeReport {
+    pub mesh: String,
+    pub vertices_before: usize,
+    pub vertices_after: usize,
+    // average cache miss ratio: vertex shader runs per triangle, 0.5 at best
+    // for large regular meshes, 3 without any reuse
+    pub acmr_before: f32,
+    pub acmr_after: f32,
+    // meshes with out of range indices or a partial triangle are left as loaded
+    pub skipped: bool,
+}
+
+impl fmt::Display for OptimizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.skipped {
+            return write!(f, "mesh '{}': invalid indices, not optimized", self.mesh);
+        }
+        write!(
+            f,
+            "mesh '{}': {} -> {} vertices, acmr {:.3} -> {:.3}",
+            self.mesh, self.vertices_before, self.vertices_after, self.acmr_before, self.acmr_after
+        )
+    }
+}
+
+// cache misses per triangle with a fifo cache of `cache_size` vertices
+pub fn acmr(indices: &[u32], cache_size: usize) -> f32 {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return 0.0;
+    }
+    let vertex_count = indices.iter().max().map_or(0, |max| *max as usize + 1);
+    // a vertex is cached while less than cache_size misses happened since its own
+    let mut miss_time = vec![0usize; vertex_count];
+    let mut misses = 0usize;
+    for index in indices {
+        let time = &mut miss_time[*index as usize];
+        if *time == 0 || misses + 1 - *time > cache_size {
+            misses += 1;
+            *time = misses;
+        }
+    }
+    misses as f32 / triangle_count as f32
+}
+
+// merge the vertices with the same bytes (and color), returns the number of
+// vertices removed
+pub fn weld_vertices(mesh: &mut Mesh) -> usize {
+    let has_colors = has_vertex_colors(mesh);
+    let vertex_count = mesh.vertices.len();
+    let mut welded: HashMap<WeldKey, u32> = HashMap::with_capacity(vertex_count);
+    let mut remap: Vec<u32> = Vec::with_capacity(vertex_count);
+    let mut vertices: Vec<ModelVertex> = Vec::with_capacity(vertex_count);
+    let mut colors: Vec<[f32; 4]> = Vec::new();
+    for (index, vertex) in mesh.vertices.iter().enumerate() {
+        let color = if has_colors {
+            mesh.colors[index]
+        } else {
+            [0.0; 4]
+        };
+        let key = (bytemuck::cast(*vertex), color.map(f32::to_bits));
+        let new_index = *welded.entry(key).or_insert_with(|| {
+            vertices.push(*vertex);
+            if has_colors {
+                colors.push(color);
+            }
+            vertices.len() as u32 - 1
+        });
+        remap.push(new_index);
+    }
+    for index in &mut mesh.indices {
+        *index = remap[*index as usize];
+    }
+    let removed = vertex_count - vertices.len();
+    mesh.vertices = vertices;
+    if has_colors {
+        mesh.colors = colors;
+    }
+    removed
+}
+
+// score of a vertex from its position in the lru cache (-1 outside) and its
+// triangles not yet emitted
+fn vertex_score(cache_position: i32, remaining: u32, cache_size: usize) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        position if position < 0 => 0.0,
+        // the triangle just emitted, its vertices have the same score so the
+        // order they were added in doesn't matter
+        position if position < 3 => LAST_TRIANGLE_SCORE,
+        position => {
+            let scale = 1.0 / (cache_size - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+    // vertices with few triangles left are finished first
+    cache_score + VALENCE_BOOST_SCALE * (remaining as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+// triangle order for a post-transform cache of `cache_size` vertices, the
+// indices must be in range and form whole triangles
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize, cache_size: usize) -> Vec<u32> {
+    let cache_size = cache_size.max(4);
+    let triangle_count = indices.len() / 3;
+    // triangles of every vertex, the live ones first in each range
+    let mut remaining = vec![0u32; vertex_count];
+    for index in indices {
+        remaining[*index as usize] += 1;
+    }
+    let mut offsets = vec![0usize; vertex_count + 1];
+    for vertex in 0..vertex_count {
+        offsets[vertex + 1] = offsets[vertex] + remaining[vertex] as usize;
+    }
+    let mut adjacency = vec![0u32; indices.len()];
+    let mut filled = offsets.clone();
+    for (corner, index) in indices.iter().enumerate() {
+        adjacency[filled[*index as usize]] = (corner / 3) as u32;
+        filled[*index as usize] += 1;
+    }
+
+    let mut cache_position = vec![-1i32; vertex_count];
+    let mut scores: Vec<f32> = remaining
+        .iter()
+        .map(|remaining| vertex_score(-1, *remaining, cache_size))
+        .collect();
+    let triangle_score = |scores: &[f32], triangle: usize| -> f32 {
+        indices[triangle * 3..triangle * 3 + 3]
+            .iter()
+            .map(|index| scores[*index as usize])
+            .sum()
+    };
+    let mut triangle_scores: Vec<f32> = (0..triangle_count)
+        .map(|triangle| triangle_score(&scores, triangle))
+        .collect();
+    let mut emitted = vec![false; triangle_count];
+    let mut output: Vec<u32> = Vec::with_capacity(indices.len());
+    let mut cache: Vec<u32> = Vec::with_capacity(cache_size + 3);
+    let mut new_cache: Vec<u32> = Vec::with_capacity(cache_size + 3);
+    // next triangle in input order, used when no cached vertex has triangles left
+    let mut cursor = 0;
+    let mut best =
+        (0..triangle_count).max_by(|a, b| triangle_scores[*a].total_cmp(&triangle_scores[*b]));
+
+    while let Some(triangle) = best {
+        emitted[triangle] = true;
+        let corners = &indices[triangle * 3..triangle * 3 + 3];
+        output.extend_from_slice(corners);
+        for vertex in corners {
+            let vertex = *vertex as usize;
+            let live =
+                &mut adjacency[offsets[vertex]..offsets[vertex] + remaining[vertex] as usize];
+            if let Some(position) = live.iter().position(|other| *other as usize == triangle) {
+                let last = live.len() - 1;
+                live.swap(position, last);
+                remaining[vertex] -= 1;
+            }
+        }
+
+        // lru: the triangle's vertices first, the entries past cache_size are evicted
+        new_cache.clear();
+        new_cache.extend_from_slice(corners);
+        new_cache.extend(cache.iter().filter(|vertex| !corners.contains(vertex)));
+        for (position, vertex) in new_cache.iter().enumerate() {
+            cache_position[*vertex as usize] = if position < cache_size {
+                position as i32
+            } else {
+                -1
+            };
+        }
+        for vertex in &new_cache {
+            let vertex = *vertex as usize;
+            scores[vertex] = vertex_score(cache_position[vertex], remaining[vertex], cache_size);
+        }
+        best = None;
+        let mut best_score = f32::MIN;
+        for vertex in &new_cache {
+            let vertex = *vertex as usize;
+            for other in &adjacency[offsets[vertex]..offsets[vertex] + remaining[vertex] as usize] {
+                let other = *other as usize;
+                triangle_scores[other] = triangle_score(&scores, other);
+                if triangle_scores[other] > best_score {
+                    best_score = triangle_scores[other];
+                    best = Some(other);
+                }
+            }
+        }
+        new_cache.truncate(cache_size);
+        std::mem::swap(&mut cache, &mut new_cache);
+
+        if best.is_none() {
+            while cursor < triangle_count && emitted[cursor] {
+                cursor += 1;
+            }
+            best = (cursor < triangle_count).then_some(cursor);
+        }
+    }
+    output
+}
+
+// vertices in the order the indices first use them, unused vertices last
+pub fn optimize_vertex_fetch(mesh: &mut Mesh) {
+    let has_colors = has_vertex_colors(mesh);
+    let vertex_count = mesh.vertices.len();
+    let mut remap = vec![u32::MAX; vertex_count];
+    let mut order: Vec<usize> = Vec::with_capacity(vertex_count);
+    for index in &mut mesh.indices {
+        let old = *index as usize;
+        if remap[old] == u32::MAX {
+            remap[old] = order.len() as u32;
+            order.push(old);
+        }
+        *index = remap[old];
+    }
+    order.extend((0..vertex_count).filter(|vertex| remap[*vertex] == u32::MAX));
+    mesh.vertices = order.iter().map(|vertex| mesh.vertices[*vertex]).collect();
+    if has_colors {
+        mesh.colors = order.iter().map(|vertex| mesh.colors[*vertex]).collect();
+    }
+}
+
+pub fn optimize_mesh(mesh: &mut Mesh, options: &OptimizeOptions) -> OptimizeReport {
+    let vertex_count = mesh.vertices.len();
+    let mut report = OptimizeReport {
+        mesh: mesh.name.clone(),
+        vertices_before: vertex_count,
+        vertices_after: vertex_count,
+        acmr_before: acmr(&mesh.indices, options.cache_size),
+        acmr_after: 0.0,
+        skipped: false,
+    };
+    if !mesh.indices.len().is_multiple_of(3)
+        || mesh
+            .indices
+            .iter()
+            .any(|index| *index as usize >= vertex_count)
+    {
+        report.skipped = true;
+        report.acmr_after = report.acmr_before;
+        return report;
+    }
+    if options.weld {
+        weld_vertices(mesh);
+    }
+    if options.vertex_cache {
+        mesh.indices =
+            optimize_vertex_cache(&mesh.indices, mesh.vertices.len(), options.cache_size);
+    }
+    if options.vertex_fetch {
+        optimize_vertex_fetch(mesh);
+    }
+    report.vertices_after = mesh.vertices.len();
+    report.acmr_after = acmr(&mesh.indices, options.cache_size);
+    report
+}
+
+pub fn optimize_model(model: &mut Model, options: &OptimizeOptions) -> Vec<OptimizeReport> {
+    model
+        .meshes
+        .iter_mut()
+        .map(|mesh| optimize_mesh(mesh, options))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32) -> ModelVertex {
+        ModelVertex {
+            position: [x, y, 0.0],
+            ..Default::default()
+        }
+    }
+
+    // triangles of a size x size quad grid, in a scrambled order
+    fn scrambled_grid(size: u32) -> Mesh {
+        let mut vertices = Vec::new();
+        for y in 0..=size {
+            for x in 0..=size {
+                vertices.push(vertex(x as f32, y as f32));
+            }
+        }
+        let mut triangles: Vec<[u32; 3]> = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                let corner = y * (size + 1) + x;
+                triangles.push([corner, corner + 1, corner + size + 2]);
+                triangles.push([corner, corner + size + 2, corner + size + 1]);
+            }
+        }
+        // fixed linear congruential shuffle
+        let mut state = 12345u32;
+        for index in (1..triangles.len()).rev() {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            triangles.swap(index, (state >> 16) as usize % (index + 1));
+        }
+        Mesh {
+            name: "grid".to_string(),
+            vertices,
+            indices: triangles.concat(),
+            ..Default::default()
+        }
+    }
+
+    // the triangles as corner positions, rotated to start at the smallest one so
+    // the winding is kept, then sorted
+    fn triangle_set(mesh: &Mesh) -> Vec<[[u32; 3]; 3]> {
+        let mut triangles: Vec<[[u32; 3]; 3]> = mesh
+            .indices
+            .chunks(3)
+            .map(|triangle| {
+                let corners = [0, 1, 2].map(|corner| {
+                    let position = mesh.vertices[triangle[corner] as usize].position;
+                    position.map(f32::to_bits)
+                });
+                let first = (0..3).min_by_key(|corner| corners[*corner]).unwrap();
+                [0, 1, 2].map(|corner| corners[(first + corner) % 3])
+            })
+            .collect();
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn weld_merges_identical_vertices() {
+        // a quad with the diagonal corners stored twice, one copy with another color
+        let mut mesh = Mesh {
+            vertices: vec![
+                vertex(0.0, 0.0),
+                vertex(1.0, 0.0),
+                vertex(1.0, 1.0),
+                vertex(0.0, 0.0),
+                vertex(1.0, 1.0),
+                vertex(0.0, 1.0),
+            ],
+            indices: vec![0, 1, 2, 3, 4, 5],
+            ..Default::default()
+        };
+        let before = triangle_set(&mesh);
+        assert_eq!(weld_vertices(&mut mesh), 2);
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(triangle_set(&mesh), before);
+
+        let mut colored = Mesh {
+            vertices: vec![vertex(0.0, 0.0), vertex(0.0, 0.0), vertex(0.0, 0.0)],
+            colors: vec![[1.0; 4], [0.5, 0.5, 0.5, 1.0], [1.0; 4]],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        };
+        assert_eq!(weld_vertices(&mut colored), 1);
+        assert_eq!(colored.indices, vec![0, 1, 0]);
+        assert_eq!(colored.colors, vec![[1.0; 4], [0.5, 0.5, 0.5, 1.0]]);
+    }
+
+    #[test]
+    fn vertex_cache_order_keeps_the_triangles() {
+        let mut mesh = scrambled_grid(20);
+        let before = triangle_set(&mesh);
+        let acmr_before = acmr(&mesh.indices, DEFAULT_CACHE_SIZE);
+        mesh.indices =
+            optimize_vertex_cache(&mesh.indices, mesh.vertices.len(), DEFAULT_CACHE_SIZE);
+        let acmr_after = acmr(&mesh.indices, DEFAULT_CACHE_SIZE);
+
+        assert_eq!(triangle_set(&mesh), before);
+        assert!(
+            acmr_after < acmr_before * 0.6,
+            "{} -> {}",
+            acmr_before,
+            acmr_after
+        );
+        // a grid can not do better than one new vertex per two triangles
+        assert!(acmr_after >= 0.5);
+    }
+
+    #[test]
+    fn vertex_fetch_order_is_the_first_use() {
+        let mut mesh = scrambled_grid(4);
+        // an unused vertex
+        mesh.vertices.push(vertex(-1.0, -1.0));
+        let before = triangle_set(&mesh);
+        optimize_vertex_fetch(&mut mesh);
+
+        assert_eq!(triangle_set(&mesh), before);
+        let mut next = 0;
+        for index in &mesh.indices {
+            assert!(*index <= next, "vertex {} used before {}", index, next);
+            if *index == next {
+                next += 1;
+            }
+        }
+        assert_eq!(next as usize, mesh.vertices.len() - 1);
+        assert_eq!(mesh.vertices.last().unwrap().position, [-1.0, -1.0, 0.0]);
+    }
+}
@@ -0,0 +1,384 @@
+use crate::model::{Mesh, Model, ModelVertex};
+use cgmath::{InnerSpace, Vector3};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NormalMode {
+    // one normal per triangle, vertices shared by several triangles are split
+    Flat,
+    // normals of the triangles around a vertex, weighted by their angle at the vertex
+    #[default]
+    Smooth,
+}
+
+// replace the normals of a mesh, computed from its positions and triangles
+pub fn generate_normals(mesh: &mut Mesh, mode: NormalMode) {
+    match mode {
+        NormalMode::Flat => flat_normals(mesh),
+        NormalMode::Smooth => smooth_normals(&mut mesh.vertices, &mesh.indices),
+    }
+}
+
+// corners of a triangle, None when an index is out of range
+fn triangle_positions(vertices: &[ModelVertex], triangle: &[u32]) -> Option<[Vector3<f32>; 3]> {
+    let a = vertices.get(triangle[0] as usize)?;
+    let b = vertices.get(triangle[1] as usize)?;
+    let c = vertices.get(triangle[2] as usize)?;
+    Some([
+        Vector3::from(a.position),
+        Vector3::from(b.position),
+        Vector3::from(c.position),
+    ])
+}
+
+fn face_normal([a, b, c]: [Vector3<f32>; 3]) -> Option<Vector3<f32>> {
+    let normal = (b - a).cross(c - a);
+    (normal.magnitude2() > 0.0).then(|| normal.normalize())
+}
+
+// vertices of degenerate triangles only point up
+fn smooth_normals(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut normals = vec![Vector3::new(0.0f32, 0.0, 0.0); vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let Some(corners) = triangle_positions(vertices, triangle) else {
+            continue;
+        };
+        let Some(normal) = face_normal(corners) else {
+            continue;
+        };
+        for corner in 0..3 {
+            let edge_a = corners[(corner + 1) % 3] - corners[corner];
+            let edge_b = corners[(corner + 2) % 3] - corners[corner];
+            if edge_a.magnitude2() == 0.0 || edge_b.magnitude2() == 0.0 {
+                continue;
+            }
+            let angle = edge_a.angle(edge_b).0;
+            normals[triangle[corner] as usize] += normal * angle;
+        }
+    }
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        vertex.normal = if normal.magnitude2() > 0.0 {
+            normal.normalize().into()
+        } else {
+            [0.0, 1.0, 0.0]
+        };
+    }
+}
+
+fn flat_normals(mesh: &mut Mesh) {
+    let has_colors = has_vertex_colors(mesh);
+    let mut vertices: Vec<ModelVertex> = Vec::with_capacity(mesh.indices.len());
+    let mut colors: Vec<[f32; 4]> = Vec::new();
+    for triangle in mesh.indices.chunks_exact(3) {
+        let Some(corners) = triangle_positions(&mesh.vertices, triangle) else {
+            continue;
+        };
+        let normal = face_normal(corners).unwrap_or(Vector3::unit_y());
+        for index in triangle {
+            vertices.push(ModelVertex {
+                normal: normal.into(),
+                ..mesh.vertices[*index as usize]
+            });
+            if has_colors {
+                colors.push(mesh.colors[*index as usize]);
+            }
+        }
+    }
+    mesh.indices = (0..vertices.len() as u32).collect();
+    mesh.vertices = vertices;
+    mesh.colors = colors;
+}
+
+// one color per vertex (other lengths are ignored by the renderer)
+pub fn has_vertex_colors(mesh: &Mesh) -> bool {
+    !mesh.colors.is_empty() && mesh.colors.len() == mesh.vertices.len()
+}
+
+// 8 or 16 bit color channels of the mesh formats are srgb encoded
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// mikktspace view of an indexed triangle mesh, the tangents are written per
+// corner (unwelded) since the corners sharing a vertex can get different ones
+struct TangentGeometry<'a> {
+    vertices: &'a [ModelVertex],
+    indices: &'a [u32],
+    // one per index, zero for the corners mikktspace skipped
+    corner_tangents: Vec<[f32; 4]>,
+}
+
+impl TangentGeometry<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> &ModelVertex {
+        &self.vertices[self.indices[face * 3 + vert] as usize]
+    }
+}
+
+impl bevy_mikktspace::Geometry for TangentGeometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).position
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).normal
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.vertex(face, vert).tex_coords
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.corner_tangents[face * 3 + vert] = tangent;
+    }
+}
+
+fn same_tangent(a: [f32; 4], b: [f32; 4]) -> bool {
+    a[3] == b[3] && (0..3).all(|i| (a[i] - b[i]).abs() <= 1e-4)
+}
+
+// weld the corners back on (vertex, tangent): a vertex keeps the tangent of its
+// first corner, corners with a different tangent get a copy of the vertex
+fn weld_corner_tangents(mesh: &mut Mesh, corner_tangents: &[[f32; 4]]) {
+    let has_colors = has_vertex_colors(mesh);
+    // k: original vertex, v: (tangent, vertex index) of its copies
+    let mut copies: Vec<Vec<([f32; 4], u32)>> = vec![Vec::new(); mesh.vertices.len()];
+    for (corner, tangent) in corner_tangents.iter().enumerate() {
+        if *tangent == [0.0; 4] {
+            continue;
+        }
+        let original = mesh.indices[corner] as usize;
+        let existing = copies[original]
+            .iter()
+            .find(|(other, _)| same_tangent(*other, *tangent))
+            .map(|(_, index)| *index);
+        let index = existing.unwrap_or_else(|| {
+            let index = if copies[original].is_empty() {
+                original as u32
+            } else {
+                mesh.vertices.push(mesh.vertices[original]);
+                if has_colors {
+                    mesh.colors.push(mesh.colors[original]);
+                }
+                mesh.vertices.len() as u32 - 1
+            };
+            copies[original].push((*tangent, index));
+            mesh.vertices[index as usize].tangent = *tangent;
+            index
+        });
+        mesh.indices[corner] = index;
+    }
+}
+
+// mikktspace tangents (xyz, w: bitangent sign) from the positions, normals and
+// uvs, vertices left without a usable tangent get one orthogonal to their normal
+pub fn generate_tangents(mesh: &mut Mesh) {
+    let vertex_count = mesh.vertices.len();
+    if mesh
+        .indices
+        .iter()
+        .any(|index| *index as usize >= vertex_count)
+    {
+        eprintln!("mesh '{}' has out of range indices, no tangents", mesh.name);
+        return;
+    }
+    for vertex in &mut mesh.vertices {
+        vertex.tangent = [0.0; 4];
+    }
+    let mut geometry = TangentGeometry {
+        vertices: &mesh.vertices,
+        indices: &mesh.indices,
+        corner_tangents: vec![[0.0; 4]; mesh.indices.len()],
+    };
+    if !bevy_mikktspace::generate_tangents(&mut geometry) {
+        eprintln!(
+            "mesh '{}': mikktspace failed, using fallback tangents",
+            mesh.name
+        );
+    }
+    let corner_tangents = geometry.corner_tangents;
+    weld_corner_tangents(mesh, &corner_tangents);
+    for vertex in &mut mesh.vertices {
+        if !has_valid_tangent(vertex) {
+            vertex.tangent = fallback_tangent(vertex.normal);
+        }
+    }
+}
+
+// unit xyz and a +1/-1 bitangent sign
+pub fn has_valid_tangent(vertex: &ModelVertex) -> bool {
+    let [x, y, z, w] = vertex.tangent;
+    let length = (x * x + y * y + z * z).sqrt();
+    (length - 1.0).abs() < 1e-3 && w.abs() == 1.0
+}
+
+fn fallback_tangent(normal: [f32; 3]) -> [f32; 4] {
+    let normal = Vector3::from(normal);
+    // any axis not parallel to the normal
+    let axis = if normal.x.abs() < 0.9 {
+        Vector3::unit_x()
+    } else {
+        Vector3::unit_y()
+    };
+    let tangent = axis - normal * normal.dot(axis);
+    let tangent = if tangent.magnitude2() > 0.0 {
+        tangent.normalize()
+    } else {
+        Vector3::unit_x()
+    };
+    [tangent.x, tangent.y, tangent.z, 1.0]
+}
+
+// tangent with its handedness from a tangent/bitangent pair (w = sign of
+// dot(cross(normal, tangent), bitangent))
+pub fn tangent_from_bitangent(
+    normal: [f32; 3],
+    tangent: [f32; 3],
+    bitangent: [f32; 3],
+) -> [f32; 4] {
+    let normal = Vector3::from(normal);
+    let tangent = Vector3::from(tangent);
+    let sign = if normal.cross(tangent).dot(Vector3::from(bitangent)) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    };
+    let tangent = if tangent.magnitude2() > 0.0 {
+        tangent.normalize()
+    } else {
+        tangent
+    };
+    [tangent.x, tangent.y, tangent.z, sign]
+}
+
+// a mesh with a skeleton or with weighted vertices (the json format only gives
+// the skeleton to the first mesh)
+pub fn is_skinned(mesh: &Mesh) -> bool {
+    mesh.skeleton.is_some()
+        || mesh.vertices.iter().any(|vertex| {
+            vertex
+                .bone_influences()
+                .iter()
+                .any(|(_, weight)| *weight > 0.0)
+        })
+}
+
+// one mesh per material, skinned and static meshes are kept apart, named after
+// the first mesh of each group (only correct with at most one skeleton)
+pub fn merge_meshes(model: &mut Model) {
+    let mut merged: Vec<(Option<usize>, bool, Mesh)> = Vec::new();
+    for mesh in std::mem::take(&mut model.meshes) {
+        let skinned = is_skinned(&mesh);
+        match merged.iter_mut().find(|(material, other_skinned, _)| {
+            *material == mesh.material && *other_skinned == skinned
+        }) {
+            Some((_, _, group)) => {
+                let offset = group.vertices.len() as u32;
+                group
+                    .indices
+                    .extend(mesh.indices.iter().map(|index| index + offset));
+                // meshes without colors are white in the merged mesh
+                if has_vertex_colors(group) || has_vertex_colors(&mesh) {
+                    if !has_vertex_colors(group) {
+                        group.colors = vec![[1.0; 4]; group.vertices.len()];
+                    }
+                    if has_vertex_colors(&mesh) {
+                        group.colors.extend(&mesh.colors);
+                    } else {
+                        group
+                            .colors
+                            .resize(offset as usize + mesh.vertices.len(), [1.0; 4]);
+                    }
+                }
+                group.vertices.extend(mesh.vertices);
+                if group.skeleton.is_none() {
+                    group.skeleton = mesh.skeleton;
+                }
+            }
+            None => merged.push((mesh.material, skinned, mesh)),
+        }
+    }
+    model.meshes = merged.into_iter().map(|(_, _, mesh)| mesh).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad(uvs: [[f32; 2]; 4]) -> Mesh {
+        let positions = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+        ];
+        Mesh {
+            name: "quad".to_string(),
+            vertices: positions
+                .iter()
+                .zip(uvs)
+                .map(|(position, tex_coords)| ModelVertex {
+                    position: *position,
+                    tex_coords,
+                    normal: [0.0, 0.0, 1.0],
+                    ..Default::default()
+                })
+                .collect(),
+            indices: vec![0, 1, 2, 1, 3, 2],
+            colors: vec![[1.0, 0.0, 0.0, 1.0]; 4],
+            ..Default::default()
+        }
+    }
+
+    fn assert_tangent(vertex: &ModelVertex, expected: [f32; 4]) {
+        assert!(
+            same_tangent(vertex.tangent, expected),
+            "{:?} != {:?}",
+            vertex.tangent,
+            expected
+        );
+    }
+
+    #[test]
+    fn continuous_uvs_stay_welded() {
+        let mut mesh = quad([[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]]);
+        generate_tangents(&mut mesh);
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, [0, 1, 2, 1, 3, 2]);
+        for vertex in &mesh.vertices {
+            assert_tangent(vertex, [1.0, 0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_split_shared_vertices() {
+        // the second triangle is mirrored: u runs along -y and v along -x
+        let mut mesh = quad([[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [0.0, 0.0]]);
+        generate_tangents(&mut mesh);
+        // the two vertices of the shared edge are split
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.colors.len(), 6);
+        for (corner, index) in mesh.indices.iter().enumerate() {
+            let expected = if corner < 3 {
+                [1.0, 0.0, 0.0, 1.0]
+            } else {
+                [0.0, -1.0, 0.0, -1.0]
+            };
+            assert_tangent(&mesh.vertices[*index as usize], expected);
+        }
+        // the copies keep the other attributes
+        let shared = [mesh.indices[3], mesh.indices[5]];
+        assert_eq!(mesh.vertices[shared[0] as usize].position, [1.0, 0.0, 0.0]);
+        assert_eq!(mesh.vertices[shared[1] as usize].position, [0.0, 1.0, 0.0]);
+    }
+}
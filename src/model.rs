@@ -1,3 +1,4 @@
+use crate::vertex_packing::{PackedLayout, WeightPrecision};
 use std::collections::HashMap;
 use wgpu::util::DeviceExt;
 
@@ -10,6 +11,43 @@ pub struct ModelVertex {
     pub tangent: [f32; 4],
     pub bone_ids: [f32; 4],
     pub bone_weights: [f32; 4],
+    // influences 5-8
+    pub bone_ids_1: [f32; 4],
+    pub bone_weights_1: [f32; 4],
+    // second uv set (TEXCOORD_1), zero when the mesh has none
+    pub tex_coords_1: [f32; 2],
+}
+
+// bone influences a vertex can carry (two sets of four)
+pub const MAX_BONE_INFLUENCES: usize = 8;
+
+impl ModelVertex {
+    // every influence slot as (bone id, weight)
+    pub fn bone_influences(&self) -> [(f32, f32); MAX_BONE_INFLUENCES] {
+        let mut influences = [(0.0, 0.0); MAX_BONE_INFLUENCES];
+        for slot in 0..4 {
+            influences[slot] = (self.bone_ids[slot], self.bone_weights[slot]);
+            influences[slot + 4] = (self.bone_ids_1[slot], self.bone_weights_1[slot]);
+        }
+        influences
+    }
+
+    // write the influence slots, missing slots are cleared
+    pub fn set_bone_influences(&mut self, influences: &[(f32, f32)]) {
+        for slot in 0..MAX_BONE_INFLUENCES {
+            let (id, weight) = influences
+                .get(slot)
+                .copied()
+                .unwrap_or((crate::skin_validation::UNUSED_BONE_ID, 0.0));
+            if slot < 4 {
+                self.bone_ids[slot] = id;
+                self.bone_weights[slot] = weight;
+            } else {
+                self.bone_ids_1[slot - 4] = id;
+                self.bone_weights_1[slot - 4] = weight;
+            }
+        }
+    }
 }
 
 impl crate::vertex::Vertex for ModelVertex {
@@ -55,6 +93,24 @@ impl crate::vertex::Vertex for ModelVertex {
                     shader_location: 5,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                // bone_ids_1
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // bone_weights_1
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 24]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // tex_coords_1
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 28]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
             ],
         }
     }
@@ -62,6 +118,37 @@ impl crate::vertex::Vertex for ModelVertex {
 #[derive(Debug, Default)]
 pub struct Model {
     pub meshes: Vec<Mesh>,
+    pub materials: Vec<crate::material::Material>,
+    // images referenced by the material textures, shared with the other
+    // models of a scene
+    pub images: Vec<std::sync::Arc<crate::material::ImageData>>,
+    // skin problems the loader found and fixed, SkinReport::mesh_index indexes meshes
+    pub skin_reports: Vec<crate::skin_validation::SkinReport>,
+}
+impl Model {
+    // move the meshes of `other` after ours, keeping its skin reports pointing at them
+    pub fn append(&mut self, mut other: Model) {
+        for report in &mut other.skin_reports {
+            report.mesh_index += self.meshes.len();
+        }
+        self.skin_reports.append(&mut other.skin_reports);
+        self.meshes.append(&mut other.meshes);
+    }
+
+    // axis aligned bounds of every mesh (min, max)
+    pub fn bounds(&self) -> Option<([f32; 3], [f32; 3])> {
+        let mut bounds: Option<([f32; 3], [f32; 3])> = None;
+        for (min, max) in self.meshes.iter().filter_map(|mesh| mesh.bounds()) {
+            bounds = Some(match bounds {
+                Some((b_min, b_max)) => (
+                    [0, 1, 2].map(|i| b_min[i].min(min[i])),
+                    [0, 1, 2].map(|i| b_max[i].max(max[i])),
+                ),
+                None => (min, max),
+            });
+        }
+        bounds
+    }
 }
 #[derive(Debug, Default)]
 pub struct Mesh {
@@ -69,6 +156,83 @@ pub struct Mesh {
     pub vertices: Vec<ModelVertex>,
     pub indices: Vec<u32>,
     pub skeleton: Option<Skeleton>,
+    // index in Model::materials, None uses the default material
+    pub material: Option<usize>,
+    // linear rgba of every vertex, empty when the mesh has no vertex colors
+    pub colors: Vec<[f32; 4]>,
+    // layout of the uploaded vertex buffer
+    pub vertex_format: MeshVertexFormat,
+}
+// the vertices are uploaded as ModelVertex or in the packed layout of
+// vertex_packing, with the given weight precision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MeshVertexFormat {
+    #[default]
+    Full,
+    Packed(WeightPrecision),
+}
+impl Mesh {
+    // 16 bit indices when they can address every vertex, 0xffff is left out as
+    // it is the strip restart value
+    pub fn index_format(&self) -> wgpu::IndexFormat {
+        let fits = |value: usize| value < u16::MAX as usize;
+        // the last vertex of a 0xffff vertex mesh is 0xfffe
+        let vertices_fit = self.vertices.len() <= u16::MAX as usize;
+        if vertices_fit && self.indices.iter().all(|index| fits(*index as usize)) {
+            wgpu::IndexFormat::Uint16
+        } else {
+            wgpu::IndexFormat::Uint32
+        }
+    }
+
+    // axis aligned bounds of the vertices (min, max)
+    pub fn bounds(&self) -> Option<([f32; 3], [f32; 3])> {
+        let first = self.vertices.first()?.position;
+        let mut min = first;
+        let mut max = first;
+        for vertex in &self.vertices {
+            for i in 0..3 {
+                min[i] = min[i].min(vertex.position[i]);
+                max[i] = max[i].max(vertex.position[i]);
+            }
+        }
+        Some((min, max))
+    }
+
+    // bake a transform into the vertices (e.g. a scene node world matrix)
+    pub fn transform(&mut self, matrix: cgmath::Matrix4<f32>) {
+        use cgmath::{InnerSpace, Matrix, Vector3};
+        let linear = cgmath::Matrix3::from_cols(
+            matrix.x.truncate(),
+            matrix.y.truncate(),
+            matrix.z.truncate(),
+        );
+        // normals use the inverse transpose so non uniform scales keep them perpendicular
+        let normal_matrix = linear
+            .invert()
+            .map(|inverse| inverse.transpose())
+            .unwrap_or(linear);
+        for vertex in &mut self.vertices {
+            let position = matrix * Vector3::from(vertex.position).extend(1.0);
+            vertex.position = position.truncate().into();
+            let normal = normal_matrix * Vector3::from(vertex.normal);
+            if normal.magnitude2() > 0.0 {
+                vertex.normal = normal.normalize().into();
+            }
+            let tangent =
+                linear * Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+            if tangent.magnitude2() > 0.0 {
+                let tangent = tangent.normalize();
+                vertex.tangent = [tangent.x, tangent.y, tangent.z, vertex.tangent[3]];
+            }
+        }
+        // a mirroring transform flips the triangles, restore the winding
+        if linear.determinant() < 0.0 {
+            for triangle in self.indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+    }
 }
 #[derive(Debug, PartialEq, Default, Clone)]
 pub struct Bone {
@@ -77,30 +241,61 @@ pub struct Bone {
     pub parent_id: Option<usize>,
     pub inverse_bind_matrix: [[f32; 4]; 4],
     pub index: usize,
+    // transform of the non bone nodes between the parent bone (or the scene root)
+    // and this bone, None when there are none
+    pub parent_offset: Option<[[f32; 4]; 4]>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Skeleton {
     pub name: String,
     pub bones: HashMap<usize, Bone>,
     pub bones_ordered: Vec<Bone>,
 }
-#[derive(Debug, Default, Clone)]
+impl Skeleton {
+    // true for every ordered bone that no other bone uses as parent
+    pub fn leaf_bones(&self) -> Vec<bool> {
+        let mut leaves = vec![true; self.bones_ordered.len()];
+        for bone in &self.bones_ordered {
+            if let Some(parent) = bone.parent_id {
+                if parent < leaves.len() {
+                    leaves[parent] = false;
+                }
+            }
+        }
+        leaves
+    }
+
+    // bind pose of a bone relative to its parent, rebuilt from the inverse bind matrices
+    pub fn bind_local_matrix(&self, bone: &Bone) -> cgmath::Matrix4<f32> {
+        let global = cgmath::Matrix4::from(bone.inverse_bind_matrix)
+            .invert()
+            .unwrap_or_else(cgmath::Matrix4::identity);
+        match bone
+            .parent_id
+            .and_then(|parent| self.bones_ordered.get(parent))
+        {
+            Some(parent) => cgmath::Matrix4::from(parent.inverse_bind_matrix) * global,
+            None => global,
+        }
+    }
+}
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct KeyTranslation {
     pub timestamp: f32,
     pub translation: [f32; 3],
 }
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct KeyRotation {
     pub timestamp: f32,
     pub rotation: [f32; 4],
 }
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct KeyScale {
     pub timestamp: f32,
     pub scale: [f32; 3],
 }
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct AnimatedBone {
     pub bone_id: u32,
     pub bone_name: String,
@@ -112,33 +307,177 @@ pub struct AnimatedBone {
 #[derive(Debug, Default)]
 pub struct Animation {
     pub name: String,
-    pub bone_keyframes: HashMap<usize, AnimatedBone>,
-    pub bone_keyframes_name: HashMap<String, AnimatedBone>,
+    // the tracks, stored once
+    tracks: Vec<AnimatedBone>,
+    // bone name -> index in tracks
+    track_names: HashMap<String, usize>,
+    // bone id -> index in tracks, see bind_bone_ids
+    track_ids: Vec<Option<usize>>,
+}
+impl Animation {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // a track for the same bone name replaces the previous one
+    pub fn add_track(&mut self, track: AnimatedBone) {
+        match self.track_names.get(&track.bone_name) {
+            Some(&index) => self.tracks[index] = track,
+            None => {
+                self.track_names
+                    .insert(track.bone_name.clone(), self.tracks.len());
+                self.tracks.push(track);
+            }
+        }
+    }
+
+    pub fn tracks(&self) -> &[AnimatedBone] {
+        &self.tracks
+    }
+
+    pub fn track(&self, bone_name: &str) -> Option<&AnimatedBone> {
+        self.track_names
+            .get(bone_name)
+            .map(|&index| &self.tracks[index])
+    }
+
+    // index the tracks matching the skeleton bone names by bone id
+    pub fn bind_bone_ids(&mut self, skeleton: &Skeleton) {
+        self.track_ids.clear();
+        for bone in &skeleton.bones_ordered {
+            self.bind_track_id(bone.id as usize, &bone.name);
+        }
+    }
+
+    // index the track of a bone name by bone id, false when there is no such track
+    pub fn bind_track_id(&mut self, id: usize, bone_name: &str) -> bool {
+        let Some(&index) = self.track_names.get(bone_name) else {
+            return false;
+        };
+        if self.track_ids.len() <= id {
+            self.track_ids.resize(id + 1, None);
+        }
+        self.track_ids[id] = Some(index);
+        self.tracks[index].bone_id = id as u32;
+        true
+    }
+
+    pub fn track_by_id(&self, id: usize) -> Option<&AnimatedBone> {
+        self.track_ids
+            .get(id)
+            .copied()
+            .flatten()
+            .map(|index| &self.tracks[index])
+    }
+
+    // (bone id, track) of the tracks bound by bind_bone_ids, by increasing id
+    pub fn tracks_by_id(&self) -> impl Iterator<Item = (usize, &AnimatedBone)> {
+        self.track_ids
+            .iter()
+            .enumerate()
+            .filter_map(|(id, index)| index.map(|index| (id, &self.tracks[index])))
+    }
+
+    // multiply every key time, converts the clip to another time unit
+    pub fn scale_times(&mut self, scale: f32) {
+        if scale == 1.0 {
+            return;
+        }
+        for track in &mut self.tracks {
+            for key in &mut track.translation_keys {
+                key.timestamp *= scale;
+            }
+            for key in &mut track.rotation_keys {
+                key.timestamp *= scale;
+            }
+            for key in &mut track.scale_keys {
+                key.timestamp *= scale;
+            }
+        }
+    }
+}
+
+// the vertex colors are a second vertex buffer, white for meshes without colors
+pub const VERTEX_COLOR_LOCATION: u32 = 8;
+
+pub fn vertex_color_desc() -> wgpu::VertexBufferLayout<'static> {
+    wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &[wgpu::VertexAttribute {
+            offset: 0,
+            shader_location: VERTEX_COLOR_LOCATION,
+            format: wgpu::VertexFormat::Float32x4,
+        }],
+    }
 }
 
 pub struct MeshLayout {
     pub vertex_buffer: wgpu::Buffer,
+    pub color_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_indices: u32,
+    // format of the index buffer, picked from the vertex count
+    pub index_format: wgpu::IndexFormat,
+    // layout of the vertex buffer, None for ModelVertex
+    pub packed: Option<PackedLayout>,
 }
 impl MeshLayout {
     pub fn new(device: &wgpu::Device, mesh: &Mesh) -> Self {
+        let packed = match mesh.vertex_format {
+            MeshVertexFormat::Full => None,
+            MeshVertexFormat::Packed(weights) => Some(PackedLayout::for_mesh(mesh, weights)),
+        };
+        let packed_vertices: Vec<u8>;
+        let contents: &[u8] = match &packed {
+            Some(layout) => {
+                packed_vertices = crate::vertex_packing::encode_vertices(mesh, layout);
+                &packed_vertices
+            }
+            None => bytemuck::cast_slice(&mesh.vertices),
+        };
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&mesh.vertices),
+            contents,
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let colors = if mesh.colors.len() == mesh.vertices.len() {
+            mesh.colors.clone()
+        } else {
+            vec![[1.0; 4]; mesh.vertices.len()]
+        };
+        let color_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Color Buffer"),
+            contents: bytemuck::cast_slice(&colors),
             usage: wgpu::BufferUsages::VERTEX,
         });
         // --- Index Buffer---
         let num_indices = mesh.indices.len() as u32;
+        let index_format = mesh.index_format();
+        let indices_u16: Vec<u16>;
+        let contents: &[u8] = match index_format {
+            wgpu::IndexFormat::Uint16 => {
+                indices_u16 = mesh.indices.iter().map(|index| *index as u16).collect();
+                bytemuck::cast_slice(&indices_u16)
+            }
+            wgpu::IndexFormat::Uint32 => bytemuck::cast_slice(&mesh.indices),
+        };
+        // the size is padded to 4 bytes for odd u16 counts
         let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(&mesh.indices),
+            contents,
             usage: wgpu::BufferUsages::INDEX,
         });
         Self {
             vertex_buffer,
+            color_buffer,
             index_buffer,
             num_indices,
+            index_format,
+            packed,
         }
     }
 }
@@ -199,4 +538,64 @@ impl BoneBufferHandler {
     ) {
         queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[new_transforms]));
     }
+
+    // upload only the first `bone_count` matrices, the rest of the buffer is left untouched
+    pub fn change_transforms_range(
+        &mut self,
+        new_transforms: &BoneTransformsUniform,
+        bone_count: usize,
+        queue: &wgpu::Queue,
+    ) {
+        let bone_count = bone_count.min(MAX_BONES);
+        if bone_count == 0 {
+            return;
+        }
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&new_transforms.transforms[..bone_count]),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mesh(vertex_count: usize, indices: Vec<u32>) -> Mesh {
+        Mesh {
+            vertices: vec![ModelVertex::default(); vertex_count],
+            indices,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn index_format_boundary() {
+        let last = |count: usize| vec![0, 1, count as u32 - 1];
+        assert_eq!(
+            mesh(65534, last(65534)).index_format(),
+            wgpu::IndexFormat::Uint16
+        );
+        assert_eq!(
+            mesh(65535, last(65535)).index_format(),
+            wgpu::IndexFormat::Uint16
+        );
+        assert_eq!(
+            mesh(65536, last(65536)).index_format(),
+            wgpu::IndexFormat::Uint32
+        );
+    }
+
+    #[test]
+    fn restart_index_value_needs_32_bits() {
+        assert_eq!(
+            mesh(100, vec![0, 1, 2]).index_format(),
+            wgpu::IndexFormat::Uint16
+        );
+        assert_eq!(
+            mesh(100, vec![0, 1, 0xffff]).index_format(),
+            wgpu::IndexFormat::Uint32
+        );
+    }
 }
@@ -0,0 +1,438 @@
+// one entry point for every model format: the registry picks a loader from the
+// file extension, or from the first bytes of the file when the extension is unknown
+use crate::model::{Animation, Model};
+use crate::stl_loader::{self, StlOptions};
+use crate::{binary_format, gltf_loader, obj_loader, ply_loader};
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// bytes read from the start of a file to detect its format
+const MAGIC_BYTES: usize = 4096;
+
+#[derive(Debug)]
+pub enum LoadError {
+    // no registered loader handles the extension or the content of the file
+    UnsupportedFormat {
+        path: String,
+    },
+    // the file can't be opened or read
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    // the loader picked for the file rejected it
+    Invalid {
+        path: String,
+        format: &'static str,
+        source: anyhow::Error,
+    },
+    // the file loaded but has nothing to draw
+    NoMesh {
+        path: String,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::UnsupportedFormat { path } => {
+                write!(f, "{}: unsupported model format", path)
+            }
+            LoadError::Io { path, source } => write!(f, "{}: can't read file ({})", path, source),
+            // loader errors already start with the path
+            LoadError::Invalid { format, source, .. } => {
+                write!(f, "{:#} (loaded as {})", source, format)
+            }
+            LoadError::NoMesh { path } => write!(f, "{}: the model has no mesh", path),
+        }
+    }
+}
+
+// the messages already include the source errors, anyhow would print them twice
+impl std::error::Error for LoadError {}
+
+pub trait ModelLoader {
+    // short format name used in messages
+    fn name(&self) -> &'static str;
+    // lowercase file extensions, without the dot
+    fn extensions(&self) -> &'static [&'static str];
+    // true when the first bytes of a file (at most MAGIC_BYTES) are in this format
+    fn matches_magic(&self, header: &[u8]) -> bool;
+    // length of one unit of the animation key times in the file, in seconds,
+    // LoaderRegistry::load converts the returned clips to seconds with it
+    fn time_unit(&self) -> f32;
+    fn load(&self, path: &str) -> anyhow::Result<(Model, Vec<Animation>)>;
+}
+
+// first line that is not empty or a comment
+fn first_text_line(header: &[u8]) -> Option<&str> {
+    let text = std::str::from_utf8(header)
+        .or_else(|err| std::str::from_utf8(&header[..err.valid_up_to()]))
+        .ok()?;
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+// json object with one of the given keys near the start
+fn is_json_with_key(header: &[u8], key: &str) -> bool {
+    let quoted = format!("\"{}\"", key);
+    first_text_line(header).is_some_and(|line| line.starts_with('{'))
+        && String::from_utf8_lossy(header).contains(&quoted)
+}
+
+pub struct ObjLoader;
+
+impl ModelLoader for ObjLoader {
+    fn name(&self) -> &'static str {
+        "obj"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["obj"]
+    }
+
+    fn matches_magic(&self, header: &[u8]) -> bool {
+        let statements = ["v", "vt", "vn", "f", "o", "g", "s", "mtllib", "usemtl"];
+        first_text_line(header)
+            .and_then(|line| line.split_whitespace().next())
+            .is_some_and(|statement| statements.contains(&statement))
+    }
+
+    // obj has no animation
+    fn time_unit(&self) -> f32 {
+        1.0
+    }
+
+    fn load(&self, path: &str) -> anyhow::Result<(Model, Vec<Animation>)> {
+        Ok((obj_loader::load_obj(path)?, Vec::new()))
+    }
+}
+
+pub struct GltfLoader;
+
+impl ModelLoader for GltfLoader {
+    fn name(&self) -> &'static str {
+        "gltf"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["gltf", "glb"]
+    }
+
+    fn matches_magic(&self, header: &[u8]) -> bool {
+        header.starts_with(b"glTF") || is_json_with_key(header, "asset")
+    }
+
+    fn time_unit(&self) -> f32 {
+        1.0
+    }
+
+    fn load(&self, path: &str) -> anyhow::Result<(Model, Vec<Animation>)> {
+        gltf_loader::load_gltf(path)
+    }
+}
+
+// animation half of a json mesh file: "<stem>_anim.json" as written by
+// asset_tool, or the file name with "mesh" replaced by "anim"
+// (mesh_data.json -> anim_data.json)
+pub fn json_animation_path(model_path: &str) -> Option<PathBuf> {
+    let path = Path::new(model_path);
+    let stem = path.file_stem()?.to_str()?;
+    let file_name = path.file_name()?.to_str()?;
+    let mut candidates = vec![path.with_file_name(format!("{}_anim.json", stem))];
+    if file_name.contains("mesh") {
+        candidates.push(path.with_file_name(file_name.replacen("mesh", "anim", 1)));
+    }
+    candidates.into_iter().find(|candidate| candidate.is_file())
+}
+
+// mesh file of the json pair, its clips come from json_animation_path
+pub struct JsonLoader;
+
+impl ModelLoader for JsonLoader {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["json"]
+    }
+
+    fn matches_magic(&self, header: &[u8]) -> bool {
+        is_json_with_key(header, "Meshes")
+    }
+
+    fn time_unit(&self) -> f32 {
+        0.001
+    }
+
+    fn load(&self, path: &str) -> anyhow::Result<(Model, Vec<Animation>)> {
+        let model = obj_loader::load_json_model(path)?;
+        let Some(anim_path) = json_animation_path(path) else {
+            return Ok((model, Vec::new()));
+        };
+        let anim_path = anim_path.to_string_lossy();
+        let skeleton = model.meshes.iter().find_map(|mesh| mesh.skeleton.as_ref());
+        let animations = match skeleton {
+            Some(skeleton) => obj_loader::json_anim_loader(&anim_path, skeleton)?,
+            None => obj_loader::json_anim_clips(&anim_path)?,
+        };
+        Ok((model, animations))
+    }
+}
+
+pub struct BinaryLoader;
+
+impl ModelLoader for BinaryLoader {
+    fn name(&self) -> &'static str {
+        "binary"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &[binary_format::BINARY_EXTENSION]
+    }
+
+    fn matches_magic(&self, header: &[u8]) -> bool {
+        header.starts_with(&binary_format::BINARY_MAGIC)
+    }
+
+    fn time_unit(&self) -> f32 {
+        0.001
+    }
+
+    fn load(&self, path: &str) -> anyhow::Result<(Model, Vec<Animation>)> {
+        let asset = binary_format::read_binary(path)?;
+        Ok((asset.model, asset.animations))
+    }
+}
+
+// triangle soups of cad tools, welded with the default options
+pub struct StlLoader;
+
+impl ModelLoader for StlLoader {
+    fn name(&self) -> &'static str {
+        "stl"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["stl"]
+    }
+
+    // only text files, binary files have a free form header
+    fn matches_magic(&self, header: &[u8]) -> bool {
+        first_text_line(header).is_some_and(|line| line.starts_with("solid"))
+    }
+
+    // stl has no animation
+    fn time_unit(&self) -> f32 {
+        1.0
+    }
+
+    fn load(&self, path: &str) -> anyhow::Result<(Model, Vec<Animation>)> {
+        Ok((
+            stl_loader::load_stl(path, &StlOptions::default())?,
+            Vec::new(),
+        ))
+    }
+}
+
+pub struct PlyLoader;
+
+impl ModelLoader for PlyLoader {
+    fn name(&self) -> &'static str {
+        "ply"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["ply"]
+    }
+
+    fn matches_magic(&self, header: &[u8]) -> bool {
+        header.starts_with(b"ply\n") || header.starts_with(b"ply\r\n")
+    }
+
+    // ply has no animation
+    fn time_unit(&self) -> f32 {
+        1.0
+    }
+
+    fn load(&self, path: &str) -> anyhow::Result<(Model, Vec<Animation>)> {
+        Ok((ply_loader::load_ply(path)?, Vec::new()))
+    }
+}
+
+pub struct LoaderRegistry {
+    // most recently registered first
+    loaders: Vec<Box<dyn ModelLoader>>,
+}
+
+// every loader of the crate, the obj text heuristic is tried last
+impl Default for LoaderRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register(Box::new(ObjLoader));
+        registry.register(Box::new(JsonLoader));
+        registry.register(Box::new(GltfLoader));
+        registry.register(Box::new(BinaryLoader));
+        registry.register(Box::new(StlLoader));
+        registry.register(Box::new(PlyLoader));
+        registry
+    }
+}
+
+impl LoaderRegistry {
+    // a registry without any loader
+    pub fn new() -> Self {
+        Self {
+            loaders: Vec::new(),
+        }
+    }
+
+    // loaders registered later are tried first, for extensions and magic bytes
+    pub fn register(&mut self, loader: Box<dyn ModelLoader>) {
+        self.loaders.insert(0, loader);
+    }
+
+    pub fn loaders(&self) -> impl Iterator<Item = &dyn ModelLoader> {
+        self.loaders.iter().map(|loader| loader.as_ref())
+    }
+
+    pub fn find_by_extension(&self, path: &str) -> Option<&dyn ModelLoader> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())?
+            .to_ascii_lowercase();
+        self.loaders()
+            .find(|loader| loader.extensions().contains(&extension.as_str()))
+    }
+
+    pub fn find_by_magic(&self, header: &[u8]) -> Option<&dyn ModelLoader> {
+        self.loaders().find(|loader| loader.matches_magic(header))
+    }
+
+    // loader for a file, from its extension or else its first bytes
+    pub fn find(&self, path: &str) -> Result<&dyn ModelLoader, LoadError> {
+        if let Some(loader) = self.find_by_extension(path) {
+            return Ok(loader);
+        }
+        let io_error = |source| LoadError::Io {
+            path: path.to_string(),
+            source,
+        };
+        let mut header = Vec::with_capacity(MAGIC_BYTES);
+        std::fs::File::open(path)
+            .map_err(io_error)?
+            .take(MAGIC_BYTES as u64)
+            .read_to_end(&mut header)
+            .map_err(io_error)?;
+        self.find_by_magic(&header)
+            .ok_or_else(|| LoadError::UnsupportedFormat {
+                path: path.to_string(),
+            })
+    }
+
+    pub fn load(&self, path: &str) -> Result<(Model, Vec<Animation>), LoadError> {
+        let loader = self.find(path)?;
+        // report a missing file as such instead of as a loader error
+        std::fs::metadata(path).map_err(|source| LoadError::Io {
+            path: path.to_string(),
+            source,
+        })?;
+        let (model, mut animations) = loader.load(path).map_err(|source| LoadError::Invalid {
+            path: path.to_string(),
+            format: loader.name(),
+            source,
+        })?;
+        for animation in &mut animations {
+            animation.scale_times(loader.time_unit());
+        }
+        Ok((model, animations))
+    }
+}
+
+// load a model and its animations with the default registry, the key times
+// are in seconds whatever the format
+pub fn load_model(path: &str) -> Result<(Model, Vec<Animation>), LoadError> {
+    LoaderRegistry::default().load(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary_format::BinaryWriteOptions;
+    use crate::model::{AnimatedBone, KeyTranslation};
+
+    fn last_key_time(animation: &Animation) -> f32 {
+        let mut tracks: Vec<&AnimatedBone> = animation
+            .tracks()
+            .iter()
+            .filter(|track| !track.translation_keys.is_empty())
+            .collect();
+        tracks.sort_by(|a, b| a.bone_name.cmp(&b.bone_name));
+        tracks[0].translation_keys.last().unwrap().timestamp
+    }
+
+    #[test]
+    fn json_model_loads_its_animation_file_in_seconds() {
+        let (_, animations) = load_model("res/mesh_data.json").unwrap();
+        let clips = obj_loader::json_anim_clips("res/anim_data.json").unwrap();
+        assert!(!clips.is_empty());
+        assert_eq!(animations.len(), clips.len());
+        for (animation, clip) in animations.iter().zip(&clips) {
+            assert_eq!(animation.name, clip.name);
+            assert_eq!(last_key_time(animation), last_key_time(clip) * 0.001);
+        }
+    }
+
+    #[test]
+    fn json_animation_path_candidates() {
+        let dir = std::env::temp_dir().join(format!("json_pair_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        // no animation file next to the mesh
+        assert_eq!(json_animation_path(&path("hero_mesh.json")), None);
+        std::fs::write(path("hero_anim.json"), "{}").unwrap();
+        assert_eq!(
+            json_animation_path(&path("hero_mesh.json")),
+            Some(dir.join("hero_anim.json"))
+        );
+        // the asset_tool name is preferred
+        std::fs::write(path("hero_mesh_anim.json"), "{}").unwrap();
+        assert_eq!(
+            json_animation_path(&path("hero_mesh.json")),
+            Some(dir.join("hero_mesh_anim.json"))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn binary_clips_are_loaded_in_seconds() {
+        let mut animation = Animation::new("walk");
+        animation.add_track(AnimatedBone {
+            bone_name: "root".to_string(),
+            translation_keys: vec![KeyTranslation {
+                timestamp: 500.0,
+                translation: [0.0; 3],
+            }],
+            ..Default::default()
+        });
+        let path = std::env::temp_dir().join(format!("clips_{}.rrb", std::process::id()));
+        let path = path.to_string_lossy();
+        binary_format::write_binary(
+            &path,
+            &Model::default(),
+            [&animation],
+            &BinaryWriteOptions::default(),
+        )
+        .unwrap();
+        let result = load_model(&path);
+        std::fs::remove_file(path.as_ref()).unwrap();
+
+        let (model, animations) = result.unwrap();
+        assert!(model.meshes.is_empty());
+        assert_eq!(last_key_time(&animations[0]), 0.5);
+    }
+}
@@ -0,0 +1,506 @@
+// inspection report of a loaded model and its animations, printable as text or
+// serialized as json (see the asset_tool inspect command)
+use crate::animation_library::ClipBindingReport;
+use crate::mesh_utils::{has_valid_tangent, has_vertex_colors, is_skinned};
+use crate::model::{Animation, Mesh, MeshVertexFormat, Model, Skeleton};
+use crate::skin_validation::{validate_mesh_skin, SkinValidationOptions};
+use crate::vertex_packing::{PackedLayout, VertexMemory, WeightPrecision};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// normals further than this from unit length are reported
+const NORMAL_LENGTH_TOLERANCE: f32 = 1e-2;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ModelReport {
+    pub source: String,
+    // None for a model without vertices
+    pub bounds: Option<([f32; 3], [f32; 3])>,
+    pub meshes: Vec<MeshReport>,
+    pub materials: usize,
+    pub images: usize,
+    // first skeleton of the model, the one the animations are bound to
+    pub skeleton: Option<SkeletonReport>,
+    pub animations: Vec<AnimationReport>,
+    // one line per anomaly of the sections above
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MeshReport {
+    pub name: String,
+    pub vertices: usize,
+    pub indices: usize,
+    // bits of the uploaded indices, 16 or 32
+    pub index_bits: u32,
+    pub triangles: usize,
+    pub material: Option<usize>,
+    pub bounds: Option<([f32; 3], [f32; 3])>,
+    pub skinned: bool,
+    pub vertex_colors: bool,
+    // vertex buffer size as ModelVertex and in the packed layout (with the
+    // weight precision of the mesh format, 16 bits for full meshes)
+    pub vertex_bytes: usize,
+    pub packed_vertex_bytes: usize,
+    pub anomalies: MeshAnomalies,
+}
+
+// vertex counts unless stated otherwise
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MeshAnomalies {
+    pub invalid_positions: usize,
+    // not finite or not unit length
+    pub invalid_normals: usize,
+    pub invalid_uvs: usize,
+    // every uv is (0, 0), the loaders fill missing uvs with zeros
+    pub missing_uvs: bool,
+    pub invalid_tangents: usize,
+    pub degenerate_triangles: usize,
+    pub out_of_range_indices: usize,
+    // vertices of a skinned mesh without any weight
+    pub unweighted_vertices: usize,
+    // skin_validation issue count per kind
+    pub skin_issues: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SkeletonReport {
+    pub name: String,
+    pub bones: Vec<BoneReport>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BoneReport {
+    pub index: usize,
+    pub id: u32,
+    pub name: String,
+    pub parent: Option<usize>,
+    pub parent_name: Option<String>,
+    // 0 for the roots
+    pub depth: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AnimationReport {
+    pub name: String,
+    pub duration_seconds: f32,
+    pub tracks: usize,
+    pub translation_keys: usize,
+    pub rotation_keys: usize,
+    pub scale_keys: usize,
+    // tracks without a bone of the same name in the skeleton
+    pub unmatched_tracks: Vec<String>,
+    pub unanimated_bones: usize,
+}
+
+impl MeshAnomalies {
+    pub fn new(mesh: &Mesh, bone_count: Option<usize>) -> Self {
+        let mut anomalies = Self::default();
+        for vertex in &mesh.vertices {
+            if vertex.position.iter().any(|value| !value.is_finite()) {
+                anomalies.invalid_positions += 1;
+            }
+            let [x, y, z] = vertex.normal;
+            let length = (x * x + y * y + z * z).sqrt();
+            if !length.is_finite() || (length - 1.0).abs() > NORMAL_LENGTH_TOLERANCE {
+                anomalies.invalid_normals += 1;
+            }
+            if vertex.tex_coords.iter().any(|value| !value.is_finite()) {
+                anomalies.invalid_uvs += 1;
+            }
+            if !has_valid_tangent(vertex) {
+                anomalies.invalid_tangents += 1;
+            }
+        }
+        anomalies.missing_uvs = !mesh.vertices.is_empty()
+            && mesh
+                .vertices
+                .iter()
+                .all(|vertex| vertex.tex_coords == [0.0, 0.0]);
+        for triangle in mesh.indices.chunks_exact(3) {
+            let corners: Vec<[f32; 3]> = triangle
+                .iter()
+                .filter_map(|index| mesh.vertices.get(*index as usize))
+                .map(|vertex| vertex.position)
+                .collect();
+            if corners.len() < 3 {
+                continue;
+            }
+            let edge = |a: [f32; 3], b: [f32; 3]| [0, 1, 2].map(|i| b[i] - a[i]);
+            let (u, v) = (edge(corners[0], corners[1]), edge(corners[0], corners[2]));
+            let cross = [
+                u[1] * v[2] - u[2] * v[1],
+                u[2] * v[0] - u[0] * v[2],
+                u[0] * v[1] - u[1] * v[0],
+            ];
+            if cross.iter().all(|value| *value == 0.0) {
+                anomalies.degenerate_triangles += 1;
+            }
+        }
+        anomalies.out_of_range_indices = mesh
+            .indices
+            .iter()
+            .filter(|index| **index as usize >= mesh.vertices.len())
+            .count();
+        if let (true, Some(bone_count)) = (is_skinned(mesh), bone_count) {
+            anomalies.unweighted_vertices = mesh
+                .vertices
+                .iter()
+                .filter(|vertex| {
+                    vertex
+                        .bone_influences()
+                        .iter()
+                        .all(|(_, weight)| *weight == 0.0)
+                })
+                .count();
+            // validation fixes the vertices it checks, run it on a copy
+            let mut copy = Mesh {
+                name: mesh.name.clone(),
+                vertices: mesh.vertices.clone(),
+                ..Default::default()
+            };
+            let report =
+                validate_mesh_skin(&mut copy, 0, bone_count, &SkinValidationOptions::default());
+            for (kind, count) in report.counts() {
+                anomalies.skin_issues.insert(kind.to_string(), count);
+            }
+        }
+        anomalies
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        let counts = [
+            (self.invalid_positions, "vertices with an invalid position"),
+            (self.invalid_normals, "vertices with an invalid normal"),
+            (self.invalid_uvs, "vertices with an invalid uv"),
+            (self.invalid_tangents, "vertices with an invalid tangent"),
+            (self.degenerate_triangles, "degenerate triangles"),
+            (self.out_of_range_indices, "out of range indices"),
+            (self.unweighted_vertices, "skinned vertices without weight"),
+        ];
+        let mut warnings: Vec<String> = counts
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, what)| format!("{} {}", count, what))
+            .collect();
+        if self.missing_uvs {
+            warnings.push("no uvs".to_string());
+        }
+        warnings.extend(
+            self.skin_issues
+                .iter()
+                .map(|(kind, count)| format!("{} skin issues: {}", count, kind)),
+        );
+        warnings
+    }
+}
+
+impl SkeletonReport {
+    pub fn new(skeleton: &Skeleton) -> Self {
+        let ordered = &skeleton.bones_ordered;
+        let bones = ordered
+            .iter()
+            .enumerate()
+            .map(|(index, bone)| {
+                let parent = bone.parent_id.and_then(|parent| ordered.get(parent));
+                // stop at the bone count in case the parents form a cycle
+                let mut depth = 0;
+                let mut ancestor = bone.parent_id;
+                while let Some(parent) = ancestor.and_then(|parent| ordered.get(parent)) {
+                    depth += 1;
+                    if depth >= ordered.len() {
+                        break;
+                    }
+                    ancestor = parent.parent_id;
+                }
+                BoneReport {
+                    index,
+                    id: bone.id,
+                    name: bone.name.clone(),
+                    parent: bone.parent_id,
+                    parent_name: parent.map(|parent| parent.name.clone()),
+                    depth,
+                }
+            })
+            .collect();
+        Self {
+            name: skeleton.name.clone(),
+            bones,
+        }
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for bone in &self.bones {
+            match bone.parent {
+                Some(parent) if parent >= self.bones.len() => warnings.push(format!(
+                    "bone '{}': parent {} is out of range",
+                    bone.name, parent
+                )),
+                Some(parent) if parent == bone.index || bone.depth >= self.bones.len() => {
+                    warnings.push(format!("bone '{}': parent links form a cycle", bone.name))
+                }
+                _ => {}
+            }
+        }
+        warnings
+    }
+}
+
+impl AnimationReport {
+    // time_unit: length of one unit of the key times in seconds (0.001 for the
+    // millisecond formats)
+    pub fn new(animation: &Animation, skeleton: Option<&Skeleton>, time_unit: f32) -> Self {
+        let tracks = animation.tracks().iter();
+        let key_times = tracks.clone().flat_map(|track| {
+            let translations = track.translation_keys.iter().map(|key| key.timestamp);
+            let rotations = track.rotation_keys.iter().map(|key| key.timestamp);
+            let scales = track.scale_keys.iter().map(|key| key.timestamp);
+            translations.chain(rotations).chain(scales)
+        });
+        let duration = key_times.fold(0.0f32, f32::max);
+        let (unmatched_tracks, unanimated_bones) = match skeleton {
+            Some(skeleton) => {
+                let binding = ClipBindingReport::new(animation, skeleton, "");
+                (binding.unmatched_tracks, binding.unanimated_bones.len())
+            }
+            None => {
+                let mut names: Vec<String> = animation
+                    .tracks()
+                    .iter()
+                    .map(|track| track.bone_name.clone())
+                    .collect();
+                names.sort();
+                (names, 0)
+            }
+        };
+        Self {
+            name: animation.name.clone(),
+            duration_seconds: duration * time_unit,
+            tracks: animation.tracks().len(),
+            translation_keys: tracks
+                .clone()
+                .map(|track| track.translation_keys.len())
+                .sum(),
+            rotation_keys: tracks.clone().map(|track| track.rotation_keys.len()).sum(),
+            scale_keys: tracks.map(|track| track.scale_keys.len()).sum(),
+            unmatched_tracks,
+            unanimated_bones,
+        }
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !self.unmatched_tracks.is_empty() {
+            warnings.push(format!(
+                "{} tracks without bone [{}]",
+                self.unmatched_tracks.len(),
+                self.unmatched_tracks.join(", ")
+            ));
+        }
+        if self.tracks == self.unmatched_tracks.len() {
+            warnings.push("nothing to animate".to_string());
+        }
+        warnings
+    }
+}
+
+impl ModelReport {
+    pub fn new(source: &str, model: &Model, animations: &[Animation], time_unit: f32) -> Self {
+        let skeleton = model.meshes.iter().find_map(|mesh| mesh.skeleton.as_ref());
+        let meshes: Vec<MeshReport> = model
+            .meshes
+            .iter()
+            .enumerate()
+            .map(|(index, mesh)| {
+                // the json format only gives the skeleton to the first mesh
+                let bone_count = mesh
+                    .skeleton
+                    .as_ref()
+                    .or(skeleton)
+                    .map(|skeleton| skeleton.bones_ordered.len());
+                let weights = match mesh.vertex_format {
+                    MeshVertexFormat::Packed(weights) => weights,
+                    MeshVertexFormat::Full => WeightPrecision::default(),
+                };
+                let memory = VertexMemory::new(mesh, &PackedLayout::for_mesh(mesh, weights));
+                let mut anomalies = MeshAnomalies::new(mesh, bone_count);
+                // issues the loader fixed are gone from the vertices, add them back
+                for report in model
+                    .skin_reports
+                    .iter()
+                    .filter(|report| report.mesh_index == index)
+                {
+                    for (kind, count) in report.counts() {
+                        *anomalies.skin_issues.entry(kind.to_string()).or_default() += count;
+                    }
+                }
+                MeshReport {
+                    name: mesh.name.clone(),
+                    vertices: mesh.vertices.len(),
+                    indices: mesh.indices.len(),
+                    index_bits: match mesh.index_format() {
+                        wgpu::IndexFormat::Uint16 => 16,
+                        wgpu::IndexFormat::Uint32 => 32,
+                    },
+                    triangles: mesh.indices.len() / 3,
+                    material: mesh.material,
+                    bounds: mesh.bounds(),
+                    skinned: is_skinned(mesh),
+                    vertex_colors: has_vertex_colors(mesh),
+                    vertex_bytes: memory.full_bytes,
+                    packed_vertex_bytes: memory.packed_bytes,
+                    anomalies,
+                }
+            })
+            .collect();
+        let skeleton_report = skeleton.map(SkeletonReport::new);
+        let animations: Vec<AnimationReport> = animations
+            .iter()
+            .map(|animation| AnimationReport::new(animation, skeleton, time_unit))
+            .collect();
+
+        let mut warnings = Vec::new();
+        for mesh in &meshes {
+            if !mesh.indices.is_multiple_of(3) {
+                warnings.push(format!(
+                    "mesh '{}': {} indices is not a whole number of triangles",
+                    mesh.name, mesh.indices
+                ));
+            }
+            if let Some(material) = mesh
+                .material
+                .filter(|material| *material >= model.materials.len())
+            {
+                warnings.push(format!(
+                    "mesh '{}': material {} is missing",
+                    mesh.name, material
+                ));
+            }
+            for warning in mesh.anomalies.warnings() {
+                warnings.push(format!("mesh '{}': {}", mesh.name, warning));
+            }
+        }
+        if let Some(skeleton) = &skeleton_report {
+            warnings.extend(skeleton.warnings());
+        }
+        for animation in &animations {
+            for warning in animation.warnings() {
+                warnings.push(format!("animation '{}': {}", animation.name, warning));
+            }
+        }
+        Self {
+            source: source.to_string(),
+            bounds: model.bounds(),
+            meshes,
+            materials: model.materials.len(),
+            images: model.images.len(),
+            skeleton: skeleton_report,
+            animations,
+            warnings,
+        }
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+fn write_bounds(f: &mut impl fmt::Write, bounds: &Option<([f32; 3], [f32; 3])>) -> fmt::Result {
+    match bounds {
+        Some((min, max)) => write!(f, "bounds {:?} .. {:?}", min, max),
+        None => write!(f, "no bounds"),
+    }
+}
+
+impl fmt::Display for ModelReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.source)?;
+        write_bounds(f, &self.bounds)?;
+        writeln!(f, ", {} materials, {} images", self.materials, self.images)?;
+        writeln!(f, "meshes ({}):", self.meshes.len())?;
+        for mesh in &self.meshes {
+            write!(
+                f,
+                "  '{}': {} vertices, {} triangles ({} bit indices), material {:?}, {}, ",
+                mesh.name,
+                mesh.vertices,
+                mesh.triangles,
+                mesh.index_bits,
+                mesh.material,
+                if mesh.skinned { "skinned" } else { "static" }
+            )?;
+            if mesh.vertex_colors {
+                write!(f, "vertex colors, ")?;
+            }
+            write_bounds(f, &mesh.bounds)?;
+            writeln!(f)?;
+        }
+        let vertex_bytes: usize = self.meshes.iter().map(|mesh| mesh.vertex_bytes).sum();
+        let packed_bytes: usize = self
+            .meshes
+            .iter()
+            .map(|mesh| mesh.packed_vertex_bytes)
+            .sum();
+        if vertex_bytes > 0 {
+            writeln!(
+                f,
+                "vertex buffers: {} bytes, {} packed ({:.0}%)",
+                vertex_bytes,
+                packed_bytes,
+                packed_bytes as f32 * 100.0 / vertex_bytes as f32
+            )?;
+        }
+        match &self.skeleton {
+            Some(skeleton) => {
+                writeln!(
+                    f,
+                    "skeleton '{}' ({} bones):",
+                    skeleton.name,
+                    skeleton.bones.len()
+                )?;
+                for bone in &skeleton.bones {
+                    write!(
+                        f,
+                        "{}{} '{}' (id {}",
+                        "  ".repeat(bone.depth + 1),
+                        bone.index,
+                        bone.name,
+                        bone.id
+                    )?;
+                    if let Some(parent) = bone.parent {
+                        write!(f, ", parent {}", parent)?;
+                    }
+                    writeln!(f, ")")?;
+                }
+            }
+            None => writeln!(f, "no skeleton")?,
+        }
+        writeln!(f, "animations ({}):", self.animations.len())?;
+        for animation in &self.animations {
+            writeln!(
+                f,
+                "  '{}': {:.3}s, {} tracks, {} translation / {} rotation / {} scale keys, {} bones without track",
+                animation.name,
+                animation.duration_seconds,
+                animation.tracks,
+                animation.translation_keys,
+                animation.rotation_keys,
+                animation.scale_keys,
+                animation.unanimated_bones
+            )?;
+        }
+        if self.warnings.is_empty() {
+            write!(f, "no warnings")
+        } else {
+            writeln!(f, "warnings ({}):", self.warnings.len())?;
+            let lines: Vec<String> = self
+                .warnings
+                .iter()
+                .map(|warning| format!("  {}", warning))
+                .collect();
+            write!(f, "{}", lines.join("\n"))
+        }
+    }
+}
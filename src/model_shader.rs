@@ -1,19 +1,29 @@
 use crate::camera::CameraBufferHandler;
 use crate::camera::ModelMatrixBufferHandler;
 use crate::light::LightBufferHandler;
+use crate::material::{self, Material, MaterialBufferHandler};
 use crate::model::BoneBufferHandler;
 use crate::model::MeshLayout;
 use crate::renderer;
 use crate::shader::{self, ColorBufferHandler, Render};
+use crate::texture::Texture;
 use crate::vertex::Vertex;
+use crate::vertex_packing::{PackedLayout, VertexMemory};
 pub struct ModelShader {
     pub render_pipeline: wgpu::RenderPipeline,
+    // one pipeline per packed vertex layout used by the meshes
+    pub packed_pipelines: Vec<(PackedLayout, wgpu::RenderPipeline)>,
     pub color_buffer: ColorBufferHandler,
     pub vertex_layouts: Vec<MeshLayout>,
     pub camera_buffer: CameraBufferHandler,
     pub bone_transform_buffer: BoneBufferHandler,
     pub light_buffer: LightBufferHandler,
     pub model_buffer: ModelMatrixBufferHandler,
+    pub material_buffers: Vec<MaterialBufferHandler>,
+    // index in material_buffers of every mesh
+    pub mesh_materials: Vec<usize>,
+    pub white_texture: Texture,
+    pub flat_normal_texture: Texture,
 }
 impl ModelShader {
     pub fn new(path: &str, renderer: &renderer::Renderer, model: &crate::model::Model) -> Self {
@@ -98,7 +108,7 @@ impl ModelShader {
                 });
         let bones_buffer = BoneBufferHandler::new(&renderer.device, &bones_bind_group_layout);
 
-        // light buffer
+        // light buffer, the model shader binds it with the material of each mesh
         let light_bind_group_layout =
             renderer
                 .device
@@ -116,6 +126,51 @@ impl ModelShader {
                     label: Some("Light bind group layout"),
                 });
         let light_buffer = LightBufferHandler::new(&renderer.device, &light_bind_group_layout);
+
+        // light + material + textures, one bind group per material
+        let material_bind_group_layout =
+            renderer
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &MaterialBufferHandler::layout_entries(),
+                    label: Some("Material bind group layout"),
+                });
+        let white_texture = material::white_texture(&renderer.device, &renderer.queue);
+        let flat_normal_texture = material::flat_normal_texture(&renderer.device, &renderer.queue);
+        let mut texture_cache = renderer.texture_cache.borrow_mut();
+        // the last one is the default material for meshes without material
+        let default_material = Material::default();
+        let material_buffers: Vec<MaterialBufferHandler> = model
+            .materials
+            .iter()
+            .chain(std::iter::once(&default_material))
+            .map(|material| {
+                MaterialBufferHandler::new(
+                    &renderer.device,
+                    &material_bind_group_layout,
+                    &light_buffer.buffer,
+                    material,
+                    texture_cache.material_textures(
+                        &renderer.device,
+                        &renderer.queue,
+                        &model.images,
+                        material,
+                    ),
+                    &white_texture,
+                    &flat_normal_texture,
+                )
+            })
+            .collect();
+        drop(texture_cache);
+        let mesh_materials = model
+            .meshes
+            .iter()
+            .map(|mesh| {
+                mesh.material
+                    .filter(|material| *material < model.materials.len())
+                    .unwrap_or(model.materials.len())
+            })
+            .collect();
         // shader only
         let render_pipeline_layout =
             renderer
@@ -124,50 +179,96 @@ impl ModelShader {
                     label: Some("Render Pipeline Layout"),
                     bind_group_layouts: &[
                         //&color_bind_group_layout,
-                        &light_bind_group_layout,
+                        &material_bind_group_layout,
                         &camera_bind_group_layout,
                         &model_bind_group_layout,
                         &bones_bind_group_layout,
                     ],
                     push_constant_ranges: &[],
                 });
+        let mut vertices = Vec::new();
+        for mesh in &model.meshes {
+            let layout = MeshLayout::new(&renderer.device, mesh);
+            if layout.packed.is_some() {
+                println!("{}", VertexMemory::uploaded(mesh, &layout.vertex_buffer));
+            }
+            vertices.push(layout);
+        }
+        let mut packed_pipelines: Vec<(PackedLayout, wgpu::RenderPipeline)> = Vec::new();
+        for packed in vertices.iter().filter_map(|layout| layout.packed) {
+            if packed_pipelines.iter().any(|(other, _)| *other == packed) {
+                continue;
+            }
+            let pipeline = shader::create_render_pipeline_with_entry(
+                &renderer.device,
+                &render_pipeline_layout,
+                renderer.config.format,
+                Some(crate::texture::Texture::DEPTH_FORMAT),
+                &[packed.desc(), crate::model::vertex_color_desc()],
+                &shader,
+                packed.vertex_entry(),
+                Some("Packed render pipeline"),
+            );
+            packed_pipelines.push((packed, pipeline));
+        }
         let render_pipeline = shader::create_render_pipeline(
             &renderer.device,
             &render_pipeline_layout,
             renderer.config.format,
             Some(crate::texture::Texture::DEPTH_FORMAT),
-            &[crate::model::ModelVertex::desc()],
+            &[
+                crate::model::ModelVertex::desc(),
+                crate::model::vertex_color_desc(),
+            ],
             shader,
             Some("Render pipeline"),
         );
-        let mut vertices = Vec::new();
-        for mesh in &model.meshes {
-            vertices.push(MeshLayout::new(&renderer.device, &mesh));
-        }
         Self {
             render_pipeline,
+            packed_pipelines,
             color_buffer,
             vertex_layouts: vertices,
             camera_buffer,
             model_buffer,
             bone_transform_buffer: bones_buffer,
             light_buffer,
+            material_buffers,
+            mesh_materials,
+            white_texture,
+            flat_normal_texture,
         }
     }
 }
 impl Render for ModelShader {
     fn render<'a, 'b: 'a>(&'b self, render_pass: &mut wgpu::RenderPass<'a>) {
         render_pass.set_pipeline(&self.render_pipeline);
-        render_pass.set_bind_group(0, &self.light_buffer.buffer_bind_group, &[]);
+        let mut current_layout: Option<PackedLayout> = None;
         render_pass.set_bind_group(1, &self.camera_buffer.buffer_bind_group, &[]);
         render_pass.set_bind_group(2, &self.model_buffer.buffer_bind_group, &[]);
         render_pass.set_bind_group(3, &self.bone_transform_buffer.buffer_bind_group, &[]);
         //render_pass.set_bind_group(0, &self.color_buffer.buffer_bind_group, &[]);
-        for vertex_layout in &self.vertex_layouts {
+        for (vertex_layout, material) in self.vertex_layouts.iter().zip(&self.mesh_materials) {
+            // light and material
+            render_pass.set_bind_group(0, &self.material_buffers[*material].buffer_bind_group, &[]);
+            // the pipeline of the vertex layout, switched only when it changes
+            if vertex_layout.packed != current_layout {
+                current_layout = vertex_layout.packed;
+                let pipeline = match &current_layout {
+                    Some(packed) => self
+                        .packed_pipelines
+                        .iter()
+                        .find(|(other, _)| other == packed)
+                        .map(|(_, pipeline)| pipeline)
+                        .expect("pipeline of a packed layout"),
+                    None => &self.render_pipeline,
+                };
+                render_pass.set_pipeline(pipeline);
+            }
             render_pass.set_vertex_buffer(0, vertex_layout.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, vertex_layout.color_buffer.slice(..));
             render_pass.set_index_buffer(
                 vertex_layout.index_buffer.slice(..),
-                wgpu::IndexFormat::Uint32,
+                vertex_layout.index_format,
             );
             render_pass.draw_indexed(0..vertex_layout.num_indices, 0, 0..1);
         }
@@ -6,6 +6,11 @@ struct VertexInput {
     @location(3) tangent: vec4<f32>,
     @location(4) bone_ids: vec4<f32>,
     @location(5) weights: vec4<f32>,
+    @location(6) bone_ids_1: vec4<f32>,
+    @location(7) weights_1: vec4<f32>,
+    // second vertex buffer, white when the mesh has no vertex colors
+    @location(8) color: vec4<f32>,
+    @location(9) tex_coords_1: vec2<f32>,
 }
 
 struct Camera {
@@ -30,52 +35,169 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) world_position: vec3<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) tex_coords_1: vec2<f32>,
+    // w is the handedness of the bitangent
+    @location(5) world_tangent: vec4<f32>,
 }
 
+// weighted bone matrix of one influence slot (zero for unused slots)
+fn bone_influence(bone_id: f32, weight: f32) -> mat4x4<f32> {
+    if (weight > 0.0 && bone_id > -1.0) {
+        return weight * bone_matrices[u32(bone_id)];
+    }
+    return mat4x4<f32>();
+}
+
+// clip and world position of the skinned vertex
+fn vertex_output(
+    position: vec3<f32>,
+    tex_coords: vec2<f32>,
+    tex_coords_1: vec2<f32>,
+    normal: vec3<f32>,
+    tangent: vec4<f32>,
+    color: vec4<f32>,
+    bone_transform: mat4x4<f32>,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = tex_coords;
+    out.tex_coords_1 = tex_coords_1;
+    out.world_normal = normal;
+    out.world_tangent = tangent;
+    out.color = color;
+    var total_position: vec4<f32> = bone_transform * vec4<f32>(position, 1.0);
+    out.world_position = total_position.xyz;
+    out.clip_position = camera.proj_matrix * model_matrix.matrix * total_position;
+    return out;
+}
+
+const IDENTITY: mat4x4<f32> = mat4x4<f32>(
+    vec4<f32>(1.0, 0.0, 0.0, 0.0),
+    vec4<f32>(0.0, 1.0, 0.0, 0.0),
+    vec4<f32>(0.0, 0.0, 1.0, 0.0),
+    vec4<f32>(0.0, 0.0, 0.0, 1.0)
+);
+
 @vertex
 fn vs_main(
     model: VertexInput,
 ) -> VertexOutput {
-    var out: VertexOutput;
-    out.tex_coords = model.tex_coords;
-    out.world_normal = model.normal;
     // Calculate bone transformation
     var bone_transform: mat4x4<f32> = mat4x4<f32>();
     // Check if any bone influences are present
-    if (model.weights.x > 0.0 || model.bone_ids.x > -1.0 ||
-        model.weights.y > 0.0 || model.bone_ids.y > -1.0 ||
-        model.weights.z > 0.0 || model.bone_ids.z > -1.0 ||
-        model.weights.w > 0.0 || model.bone_ids.w > -1.0) {
-        
-        // Apply bone transformations
-        if (model.weights.x > 0.0 && model.bone_ids.x > -1.0) {
-            bone_transform = bone_transform + model.weights.x * bone_matrices[u32(model.bone_ids.x)];
-        }
-        if (model.weights.y > 0.0 && model.bone_ids.y > -1.0) {
-            bone_transform = bone_transform + model.weights.y * bone_matrices[u32(model.bone_ids.y)];
-        }
-        if (model.weights.z > 0.0 && model.bone_ids.z > -1.0) {
-            bone_transform = bone_transform + model.weights.z * bone_matrices[u32(model.bone_ids.z)];
-        }
-        if (model.weights.w > 0.0 && model.bone_ids.w > -1.0) {
-            bone_transform = bone_transform + model.weights.w * bone_matrices[u32(model.bone_ids.w)];
-        }
+    let total_weight = dot(model.weights, vec4<f32>(1.0)) + dot(model.weights_1, vec4<f32>(1.0));
+    if (total_weight > 0.0) {
+        // Apply bone transformations (influences 1-4 and 5-8)
+        bone_transform = bone_transform + bone_influence(model.bone_ids.x, model.weights.x);
+        bone_transform = bone_transform + bone_influence(model.bone_ids.y, model.weights.y);
+        bone_transform = bone_transform + bone_influence(model.bone_ids.z, model.weights.z);
+        bone_transform = bone_transform + bone_influence(model.bone_ids.w, model.weights.w);
+        bone_transform = bone_transform + bone_influence(model.bone_ids_1.x, model.weights_1.x);
+        bone_transform = bone_transform + bone_influence(model.bone_ids_1.y, model.weights_1.y);
+        bone_transform = bone_transform + bone_influence(model.bone_ids_1.z, model.weights_1.z);
+        bone_transform = bone_transform + bone_influence(model.bone_ids_1.w, model.weights_1.w);
     } else {
         // Set to identity matrix if no bone influences
-        bone_transform = mat4x4<f32>(
-            vec4<f32>(1.0, 0.0, 0.0, 0.0),
-            vec4<f32>(0.0, 1.0, 0.0, 0.0),
-            vec4<f32>(0.0, 0.0, 1.0, 0.0),
-            vec4<f32>(0.0, 0.0, 0.0, 1.0)
-        );
+        bone_transform = IDENTITY;
     }
-    var total_position: vec4<f32> = bone_transform * vec4<f32>(model.position, 1.0);
-    //var total_position: vec4<f32> = vec4<f32>(model.position, 1.0);
-    out.world_position = total_position.xyz;
-    out.clip_position = camera.proj_matrix * model_matrix.matrix * total_position;
-    return out;
+    return vertex_output(
+        model.position,
+        model.tex_coords,
+        model.tex_coords_1,
+        model.normal,
+        model.tangent,
+        model.color,
+        bone_transform,
+    );
 }
 
+// packed vertices (see vertex_packing.rs), the vertex fetch converts the half
+// float, snorm and unorm attributes to floats
+struct PackedVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    // octahedral
+    @location(2) normal: vec2<f32>,
+    @location(3) tangent: vec4<f32>,
+    // u8 or u16, the biggest value marks the unused slots
+    @location(4) bone_ids: vec4<u32>,
+    @location(5) weights: vec4<f32>,
+    @location(6) bone_ids_1: vec4<u32>,
+    @location(7) weights_1: vec4<f32>,
+    @location(8) color: vec4<f32>,
+    @location(9) tex_coords_1: vec2<f32>,
+}
+
+// static meshes are packed without the skin attributes
+struct PackedStaticVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec2<f32>,
+    @location(3) tangent: vec4<f32>,
+    @location(8) color: vec4<f32>,
+    @location(9) tex_coords_1: vec2<f32>,
+}
+
+// the octahedron folded back from [-1, 1]²
+fn octahedral_decode(encoded: vec2<f32>) -> vec3<f32> {
+    var normal = vec3<f32>(encoded, 1.0 - abs(encoded.x) - abs(encoded.y));
+    let fold = max(-normal.z, 0.0);
+    normal.x += select(fold, -fold, normal.x >= 0.0);
+    normal.y += select(fold, -fold, normal.y >= 0.0);
+    return normalize(normal);
+}
+
+fn packed_bone_influence(bone_id: u32, weight: f32) -> mat4x4<f32> {
+    if (weight > 0.0 && bone_id < u32(MAX_BONES)) {
+        return weight * bone_matrices[bone_id];
+    }
+    return mat4x4<f32>();
+}
+
+@vertex
+fn vs_packed(
+    model: PackedVertexInput,
+) -> VertexOutput {
+    // the packed weights are normalized, a zero sum means no influence
+    var bone_transform: mat4x4<f32> = IDENTITY;
+    let total_weight = dot(model.weights, vec4<f32>(1.0)) + dot(model.weights_1, vec4<f32>(1.0));
+    if (total_weight > 0.0) {
+        bone_transform = packed_bone_influence(model.bone_ids.x, model.weights.x)
+            + packed_bone_influence(model.bone_ids.y, model.weights.y)
+            + packed_bone_influence(model.bone_ids.z, model.weights.z)
+            + packed_bone_influence(model.bone_ids.w, model.weights.w)
+            + packed_bone_influence(model.bone_ids_1.x, model.weights_1.x)
+            + packed_bone_influence(model.bone_ids_1.y, model.weights_1.y)
+            + packed_bone_influence(model.bone_ids_1.z, model.weights_1.z)
+            + packed_bone_influence(model.bone_ids_1.w, model.weights_1.w);
+    }
+    let normal = octahedral_decode(model.normal);
+    return vertex_output(
+        model.position,
+        model.tex_coords,
+        model.tex_coords_1,
+        normal,
+        model.tangent,
+        model.color,
+        bone_transform,
+    );
+}
+
+@vertex
+fn vs_packed_static(
+    model: PackedStaticVertexInput,
+) -> VertexOutput {
+    let normal = octahedral_decode(model.normal);
+    return vertex_output(
+        model.position,
+        model.tex_coords,
+        model.tex_coords_1,
+        normal,
+        model.tangent,
+        model.color,
+        IDENTITY,
+    );
+}
 
 // Fragment shader
 
@@ -89,6 +211,66 @@ struct Light {
 @group(0) @binding(0)
 var<uniform> light: Light;
 
+struct Material {
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec3<f32>,
+    // 0 unless the alpha mode is mask
+    alpha_cutoff: f32,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    // uv set of every texture: base color, emissive, metallic roughness,
+    // normal, occlusion
+    tex_coords: array<vec4<u32>, 2>,
+}
+@group(0) @binding(1)
+var<uniform> material: Material;
+@group(0) @binding(2)
+var base_color_texture: texture_2d<f32>;
+@group(0) @binding(3)
+var base_color_sampler: sampler;
+@group(0) @binding(4)
+var emissive_texture: texture_2d<f32>;
+@group(0) @binding(5)
+var emissive_sampler: sampler;
+// metallic in b, roughness in g
+@group(0) @binding(6)
+var metallic_roughness_texture: texture_2d<f32>;
+@group(0) @binding(7)
+var metallic_roughness_sampler: sampler;
+// tangent space, flat (0.5, 0.5, 1) without a normal map
+@group(0) @binding(8)
+var normal_texture: texture_2d<f32>;
+@group(0) @binding(9)
+var normal_sampler: sampler;
+@group(0) @binding(10)
+var occlusion_texture: texture_2d<f32>;
+@group(0) @binding(11)
+var occlusion_sampler: sampler;
+
+// uv set sampled by a texture slot
+fn slot_tex_coords(in: VertexOutput, slot: u32) -> vec2<f32> {
+    if (material.tex_coords[slot / 4u][slot % 4u] == 1u) {
+        return in.tex_coords_1;
+    }
+    return in.tex_coords;
+}
+
+// the normal map in the tangent frame, the vertex normal without tangents
+fn shading_normal(in: VertexOutput) -> vec3<f32> {
+    let normal = normalize(in.world_normal);
+    let tangent_length = length(in.world_tangent.xyz);
+    if (tangent_length == 0.0) {
+        return normal;
+    }
+    let tangent = in.world_tangent.xyz / tangent_length;
+    let bitangent = cross(normal, tangent) * select(1.0, -1.0, in.world_tangent.w < 0.0);
+    let texel = textureSample(normal_texture, normal_sampler, slot_tex_coords(in, 3u)).xyz;
+    let mapped = (texel * 2.0 - 1.0) * vec3<f32>(material.normal_scale, material.normal_scale, 1.0);
+    return normalize(mat3x3<f32>(tangent, bitangent, normal) * mapped);
+}
+
 // @fragment
 // fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 //     return vec4<f32>(in.tex_coords, 0.0, 1.0);
@@ -96,20 +278,46 @@ var<uniform> light: Light;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = vec4<f32>(1.0,1.0,1.0,1.0);
-    
+    let object_color: vec4<f32> = material.base_color_factor
+        * textureSample(base_color_texture, base_color_sampler, slot_tex_coords(in, 0u))
+        * in.color;
+    if (object_color.a < material.alpha_cutoff) {
+        discard;
+    }
+    let emissive = material.emissive_factor
+        * textureSample(emissive_texture, emissive_sampler, slot_tex_coords(in, 1u)).rgb;
+    let metallic_roughness =
+        textureSample(metallic_roughness_texture, metallic_roughness_sampler, slot_tex_coords(in, 2u));
+    let metallic = material.metallic_factor * metallic_roughness.b;
+    let roughness = material.roughness_factor * metallic_roughness.g;
+    let occlusion = mix(
+        1.0,
+        textureSample(occlusion_texture, occlusion_sampler, slot_tex_coords(in, 4u)).r,
+        material.occlusion_strength
+    );
+    let normal = shading_normal(in);
+
     // Simplify lighting to get a toon shading effect
     let light_dir = normalize(light.position - in.world_position);
-    let diffuse_strength = max(dot(in.world_normal, light_dir), 0.0);
+    let diffuse_strength = max(dot(normal, light_dir), 0.0);
 
     // Use smoothstep for smoother transitions between shadow and light
     let threshold_min = 0.4; // Adjust as needed
     let threshold_max = 0.6; // Adjust as needed
     let toon_diffuse = smoothstep(threshold_min, threshold_max, diffuse_strength);
 
-    // Quantize the result to create a stylized look
+    // Quantize the result to create a stylized look, occlusion only darkens the ambient part
     let toon_color = vec3<f32>(1.0, 1.0, 1.0); // White color for objects in light
-    let result = mix(vec3<f32>(0.3), toon_color, toon_diffuse) * object_color.xyz;
+    let shade = mix(vec3<f32>(0.3 * occlusion), toon_color, toon_diffuse);
+    // metals tint their highlights with the base color
+    let specular_color = mix(vec3<f32>(0.04), object_color.xyz, metallic);
+    // toon specular band, sharper and brighter on smooth surfaces (none at roughness 1)
+    let view_dir = normalize(camera.matrix[3].xyz - in.world_position);
+    let half_dir = normalize(light_dir + view_dir);
+    let shininess = mix(256.0, 4.0, roughness);
+    let specular_strength = pow(max(dot(normal, half_dir), 0.0), shininess) * toon_diffuse;
+    let toon_specular = smoothstep(0.45, 0.55, specular_strength) * (1.0 - roughness);
+    let result = shade * object_color.xyz + specular_color * toon_specular + emissive;
     //return object_color;
     return vec4<f32>(result, object_color.a);
-}
\ No newline at end of file
+}
@@ -0,0 +1,149 @@
+use crate::material::{ImageData, Material, TextureRef};
+use crate::model::Model;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::Arc;
+
+// write the meshes as obj, with an mtl file and the texture images next to it
+// when the model has materials (skeletons and weights are not part of the format)
+pub fn write_obj(filepath: &str, model: &Model) -> anyhow::Result<()> {
+    let path = Path::new(filepath);
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("model");
+    let write = |file_name: &str, bytes: &[u8]| {
+        let file_path = directory.join(file_name);
+        std::fs::write(&file_path, bytes)
+            .map_err(|err| anyhow::anyhow!("{}: can't write file ({})", file_path.display(), err))
+    };
+
+    // mtl names can't hold spaces and must be unique
+    let mut used_names: HashSet<String> = HashSet::new();
+    let material_names: Vec<String> = model
+        .materials
+        .iter()
+        .enumerate()
+        .map(|(index, material)| {
+            let mut name: String = material
+                .name
+                .chars()
+                .map(|c| if c.is_whitespace() { '_' } else { c })
+                .collect();
+            if name.is_empty() || used_names.contains(&name) {
+                name = format!("{}_{}", name, index);
+            }
+            used_names.insert(name.clone());
+            name
+        })
+        .collect();
+
+    let mut obj = String::new();
+    if !model.materials.is_empty() {
+        let mtl_name = format!("{}.mtl", stem);
+        let image_files = write_images(stem, &model.images, &write)?;
+        let mut mtl = String::new();
+        for (material, name) in model.materials.iter().zip(&material_names) {
+            write_mtl_material(&mut mtl, material, name, &image_files)?;
+        }
+        write(&mtl_name, mtl.as_bytes())?;
+        writeln!(obj, "mtllib {}", mtl_name)?;
+    }
+    // obj indices are 1-based and shared by every object of the file
+    let mut offset = 1;
+    for mesh in &model.meshes {
+        writeln!(obj, "o {}", mesh.name)?;
+        if let Some(name) = mesh
+            .material
+            .and_then(|material| material_names.get(material))
+        {
+            writeln!(obj, "usemtl {}", name)?;
+        }
+        for vertex in &mesh.vertices {
+            let [x, y, z] = vertex.position;
+            writeln!(obj, "v {} {} {}", x, y, z)?;
+        }
+        for vertex in &mesh.vertices {
+            let [u, v] = vertex.tex_coords;
+            writeln!(obj, "vt {} {}", u, v)?;
+        }
+        for vertex in &mesh.vertices {
+            let [x, y, z] = vertex.normal;
+            writeln!(obj, "vn {} {} {}", x, y, z)?;
+        }
+        for triangle in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [
+                triangle[0] + offset,
+                triangle[1] + offset,
+                triangle[2] + offset,
+            ];
+            writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+        offset += mesh.vertices.len() as u32;
+    }
+    std::fs::write(filepath, obj)
+        .map_err(|err| anyhow::anyhow!("{}: can't write file ({})", filepath, err))
+}
+
+// file name of every image, written as <stem>_<index>.<png|jpg>
+fn write_images(
+    stem: &str,
+    images: &[Arc<ImageData>],
+    write: &impl Fn(&str, &[u8]) -> anyhow::Result<()>,
+) -> anyhow::Result<Vec<String>> {
+    images
+        .iter()
+        .enumerate()
+        .map(|(index, image)| {
+            let extension = match image.guessed_mime_type() {
+                Some("image/png") => "png",
+                Some("image/jpeg") => "jpg",
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "image '{}' is neither png nor jpeg",
+                        image.name
+                    ))
+                }
+            };
+            let file_name = format!("{}_{}.{}", stem, index, extension);
+            write(&file_name, &image.bytes)?;
+            Ok(file_name)
+        })
+        .collect()
+}
+
+// inverse of the conversion done by obj_loader
+fn write_mtl_material(
+    mtl: &mut String,
+    material: &Material,
+    name: &str,
+    image_files: &[String],
+) -> anyhow::Result<()> {
+    let [r, g, b, a] = material.base_color_factor;
+    let [sr, sg, sb] = material.specular_color_factor;
+    let [er, eg, eb] = material.emissive_factor;
+    let roughness = material.roughness_factor.max(0.01);
+    writeln!(mtl, "newmtl {}", name)?;
+    writeln!(mtl, "Kd {} {} {}", r, g, b)?;
+    writeln!(mtl, "Ks {} {} {}", sr, sg, sb)?;
+    writeln!(mtl, "Ns {}", 2.0 / (roughness * roughness) - 2.0)?;
+    writeln!(mtl, "Ke {} {} {}", er, eg, eb)?;
+    writeln!(mtl, "d {}", a)?;
+    let maps = [
+        ("map_Kd", &material.base_color_texture),
+        ("map_Ks", &material.specular_texture),
+        ("map_Bump", &material.normal_texture),
+    ];
+    for (map, texture) in maps {
+        if let Some(TextureRef { image, .. }) = texture {
+            let file_name = image_files.get(*image).ok_or_else(|| {
+                anyhow::anyhow!("material '{}' uses missing image {}", material.name, image)
+            })?;
+            writeln!(mtl, "{} {}", map, file_name)?;
+        }
+    }
+    writeln!(mtl)?;
+    Ok(())
+}
@@ -1,288 +1,396 @@
 use crate::{
-    gltf_loader::load_gltf,
+    animation_library::{log_binding_reports, ClipBindingReport},
+    json_schema::{AnimationData, AnimationFile, MeshFile},
+    material::{AlphaMode, ImageData, Material, SamplerInfo, TextureRef},
+    mesh_utils::{generate_normals, generate_tangents, tangent_from_bitangent, NormalMode},
     model::{
         AnimatedBone, Animation, Bone, KeyRotation, KeyScale, KeyTranslation, Mesh, Model,
-        ModelVertex, Skeleton,
+        ModelVertex, Skeleton, MAX_BONE_INFLUENCES,
     },
+    skin_validation::{log_reports, validate_model_skins, SkinValidationOptions},
 };
-use cgmath::num_traits::zero;
-use gltf::animation::{self, util::rotations};
-use serde_json::Value;
-use std::{
-    fs::File,
-    io::{self, BufRead, Read},
-};
-// mesh in obj format
+use cgmath::Vector3;
+use std::{collections::HashMap, sync::Arc};
+// mesh in obj format, with the materials of its mtl files
 pub fn load_obj(path: &str) -> anyhow::Result<Model> {
-    let res: Result<
-        (
-            Vec<tobj::Model>,
-            Result<Vec<tobj::Material>, tobj::LoadError>,
-        ),
-        tobj::LoadError,
-    > = tobj::load_obj(
+    let (models, materials) = tobj::load_obj(
         path,
         &tobj::LoadOptions {
             triangulate: true,
             single_index: true,
             ..Default::default()
         },
-    );
-    if let Ok(obj) = res {
-        //load modes
-        let models = obj.0;
-        let meshes = models
-            .into_iter()
-            .map(|m| {
-                let vertices = (0..m.mesh.positions.len() / 3)
-                    .map(|i| ModelVertex {
-                        position: [
-                            m.mesh.positions[i * 3],
-                            m.mesh.positions[i * 3 + 1],
-                            m.mesh.positions[i * 3 + 2],
-                        ],
-                        tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                        normal: [
-                            m.mesh.normals[i * 3],
-                            m.mesh.normals[i * 3 + 1],
-                            m.mesh.normals[i * 3 + 2],
-                        ],
-                        tangent: [0.0; 4],
-                        bone_ids: [0.0; 4],
-                        bone_weights: [0.0; 4],
-                    })
-                    .collect::<Vec<ModelVertex>>();
-                let indices = m.mesh.indices;
-
-                Mesh {
-                    name: m.name,
-                    vertices,
-                    indices,
-                    skeleton: None,
+    )
+    .map_err(|err| anyhow::anyhow!("{}: can't load obj file ({})", path, err))?;
+    // tobj accepts any text, a file without faces is not a mesh
+    if models.iter().all(|m| m.mesh.indices.is_empty()) {
+        return Err(anyhow::anyhow!("{}: no geometry found (no faces)", path));
+    }
+    // a missing or broken mtl file only loses the materials
+    let materials = materials.unwrap_or_else(|err| {
+        eprintln!("{}: can't load mtl materials ({})", path, err);
+        Vec::new()
+    });
+    let mut model = Model::default();
+    // k: texture path, v: index in model.images
+    let mut image_indices: HashMap<String, usize> = HashMap::new();
+    for material in &materials {
+        let material = process_mtl_material(path, material, &mut model, &mut image_indices);
+        model.materials.push(material);
+    }
+    for m in models {
+        let vertex_count = m.mesh.positions.len() / 3;
+        let has_tex_coords = m.mesh.texcoords.len() == vertex_count * 2;
+        let has_normals = m.mesh.normals.len() == vertex_count * 3;
+        let vertices = (0..vertex_count)
+            .map(|i| {
+                let mut vertex = ModelVertex {
+                    position: [
+                        m.mesh.positions[i * 3],
+                        m.mesh.positions[i * 3 + 1],
+                        m.mesh.positions[i * 3 + 2],
+                    ],
+                    ..Default::default()
+                };
+                if has_tex_coords {
+                    vertex.tex_coords = [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]];
                 }
+                if has_normals {
+                    vertex.normal = [
+                        m.mesh.normals[i * 3],
+                        m.mesh.normals[i * 3 + 1],
+                        m.mesh.normals[i * 3 + 2],
+                    ];
+                }
+                vertex
             })
-            .collect::<Vec<Mesh>>();
+            .collect::<Vec<ModelVertex>>();
+        let mut mesh = Mesh {
+            name: m.name,
+            vertices,
+            indices: m.mesh.indices,
+            skeleton: None,
+            material: m
+                .mesh
+                .material_id
+                .filter(|material| *material < model.materials.len()),
+            colors: Vec::new(),
+            vertex_format: Default::default(),
+        };
+        if !has_normals {
+            eprintln!(
+                "{}: mesh '{}' has no normals, generating them",
+                path, mesh.name
+            );
+            generate_normals(&mut mesh, NormalMode::Smooth);
+        }
+        // obj has no tangents
+        generate_tangents(&mut mesh);
+        model.meshes.push(mesh);
+    }
+    Ok(model)
+}
 
-        return Ok(Model { meshes });
+// mtl material as a pbr material: Kd/d as base color, Ns as roughness, no metalness,
+// textures that can't be read are dropped
+fn process_mtl_material(
+    path: &str,
+    material: &tobj::Material,
+    model: &mut Model,
+    image_indices: &mut HashMap<String, usize>,
+) -> Material {
+    let mut texture = |texture: &Option<String>| -> Option<TextureRef> {
+        let texture = texture.as_ref()?;
+        // texture options ("-bm 0.5 normal.png") come before the file name
+        let file_name = if texture.starts_with('-') {
+            texture.split_whitespace().last().unwrap_or_default()
+        } else {
+            texture.as_str()
+        };
+        let image = match image_indices.get(file_name) {
+            Some(image) => *image,
+            None => {
+                let file_path = std::path::Path::new(path)
+                    .parent()
+                    .unwrap_or(std::path::Path::new(""))
+                    .join(file_name);
+                let bytes = match std::fs::read(&file_path) {
+                    Ok(bytes) => bytes,
+                    Err(err) => {
+                        eprintln!(
+                            "{}: material '{}': can't read texture {} ({}), ignoring it",
+                            path,
+                            material.name,
+                            file_path.display(),
+                            err
+                        );
+                        return None;
+                    }
+                };
+                model.images.push(Arc::new(ImageData {
+                    name: file_name.to_string(),
+                    mime_type: None,
+                    bytes,
+                }));
+                image_indices.insert(file_name.to_string(), model.images.len() - 1);
+                model.images.len() - 1
+            }
+        };
+        Some(TextureRef {
+            image,
+            tex_coord: 0,
+            sampler: SamplerInfo::default(),
+        })
+    };
+    let diffuse = material.diffuse.unwrap_or([1.0, 1.0, 1.0]);
+    let dissolve = material.dissolve.unwrap_or(1.0);
+    // Ke is not parsed by tobj
+    let emissive_factor = material
+        .unknown_param
+        .get("Ke")
+        .and_then(|value| {
+            let values: Vec<f32> = value
+                .split_whitespace()
+                .filter_map(|value| value.parse().ok())
+                .collect();
+            <[f32; 3]>::try_from(values).ok()
+        })
+        .unwrap_or([0.0, 0.0, 0.0]);
+    Material {
+        name: material.name.clone(),
+        base_color_factor: [diffuse[0], diffuse[1], diffuse[2], dissolve],
+        base_color_texture: texture(&material.diffuse_texture),
+        metallic_factor: 0.0,
+        // usual phong exponent to roughness conversion
+        roughness_factor: material
+            .shininess
+            .map(|shininess| (2.0 / (shininess.max(0.0) + 2.0)).sqrt())
+            .unwrap_or(1.0),
+        normal_texture: texture(&material.normal_texture),
+        emissive_factor,
+        alpha_mode: if dissolve < 1.0 {
+            AlphaMode::Blend
+        } else {
+            AlphaMode::Opaque
+        },
+        specular_color_factor: material.specular.unwrap_or([0.0, 0.0, 0.0]),
+        specular_texture: texture(&material.specular_texture),
+        ..Default::default()
     }
-    return Err(anyhow::anyhow!("Error"));
 }
 
-// mesh in custom json format
+// mesh and animations in custom json format
 pub fn load_json_obj(
     model_filepath: &str,
     anims_filepath: &str,
 ) -> anyhow::Result<(Model, Vec<Animation>)> {
-    let file = File::open(model_filepath)?;
-    let mut reader = std::io::BufReader::new(file);
-
-    let mut content = String::new();
-    reader.read_to_string(&mut content)?;
+    let model = load_json_model(model_filepath)?;
+    // load animations
+    let skeleton = model.meshes[0]
+        .skeleton
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("{} has no skeleton", model_filepath))?;
+    let anim = json_anim_loader(anims_filepath, skeleton)?;
+    Ok((model, anim))
+}
 
-    let json: Value = serde_json::from_str(&content)?;
+// mesh and skeleton in custom json format
+pub fn load_json_model(model_filepath: &str) -> anyhow::Result<Model> {
+    let file = MeshFile::read(model_filepath)?;
     let mut model: Model = Default::default();
-    if let Some(meshes) = json["Meshes"].as_array() {
-        for mesh in meshes {
-            let mut model_mesh: Mesh = Default::default();
-            if let Some(vertices) = mesh["Vertices"].as_array() {
-                for vertex in vertices {
-                    let mut model_vertex: ModelVertex = Default::default();
-                    if let Some(positions) = vertex["Position"].as_array() {
-                        let x: f32 = positions[0].as_f64().unwrap_or_default() as f32;
-                        let y = positions[1].as_f64().unwrap_or_default() as f32;
-                        let z = positions[2].as_f64().unwrap_or_default() as f32;
-                        model_vertex.position = [x, y, z];
-                    }
-                    if let Some(normals) = vertex["Normal"].as_array() {
-                        let x: f32 = normals[0].as_f64().unwrap_or_default() as f32;
-                        let y = normals[1].as_f64().unwrap_or_default() as f32;
-                        let z = normals[2].as_f64().unwrap_or_default() as f32;
-                        model_vertex.normal = [x, y, z];
-                    }
-                    if let Some(tex_coords) = vertex["TexCoords"].as_array() {
-                        let x: f32 = tex_coords[0].as_f64().unwrap_or_default() as f32;
-                        let y = tex_coords[1].as_f64().unwrap_or_default() as f32;
-                        model_vertex.tex_coords = [x, y];
-                    }
-                    if let Some(bone_id) = vertex["BoneIDs"].as_array() {
-                        let x: f32 = bone_id[0].as_f64().unwrap_or_default() as f32;
-                        let y = bone_id[1].as_f64().unwrap_or_default() as f32;
-                        let z = bone_id[2].as_f64().unwrap_or_default() as f32;
-                        let w = bone_id[3].as_f64().unwrap_or_default() as f32;
-                        model_vertex.bone_ids = [x, y, z, w];
-                    }
-                    if let Some(weights) = vertex["Weights"].as_array() {
-                        let x: f32 = weights[0].as_f64().unwrap_or_default() as f32;
-                        let y = weights[1].as_f64().unwrap_or_default() as f32;
-                        let z = weights[2].as_f64().unwrap_or_default() as f32;
-                        let w = weights[3].as_f64().unwrap_or_default() as f32;
-                        model_vertex.bone_weights = [x, y, z, w];
-                    }
-                    model_mesh.vertices.push(model_vertex);
-                }
-            }
-            if let Some(indices_array) = mesh["Indices"].as_array() {
-                let indices: Vec<u32> = indices_array
-                    .iter()
-                    .filter_map(|index| index.as_u64().map(|idx| idx as u32))
-                    .collect();
-                model_mesh.indices = indices;
+    for mesh in file.meshes {
+        let mut model_mesh = Mesh {
+            name: mesh.name,
+            indices: mesh.indices,
+            ..Default::default()
+        };
+        // attributes missing on any vertex are generated for the whole mesh
+        let has_normals = mesh.vertices.iter().all(|vertex| vertex.normal.is_some());
+        let has_tangents = mesh.vertices.iter().all(|vertex| vertex.tangent.is_some());
+        for vertex in mesh.vertices {
+            let mut model_vertex = ModelVertex {
+                position: vertex.position,
+                normal: vertex.normal.unwrap_or_default(),
+                tex_coords: vertex.tex_coords.unwrap_or_default(),
+                tex_coords_1: vertex.tex_coords_1.unwrap_or_default(),
+                ..Default::default()
+            };
+            if let Some(tangent) = vertex.tangent {
+                // the handedness comes from the bitangent when there is one
+                let bitangent = vertex.bitangent.unwrap_or_else(|| {
+                    Vector3::from(model_vertex.normal)
+                        .cross(Vector3::from(tangent))
+                        .into()
+                });
+                model_vertex.tangent =
+                    tangent_from_bitangent(model_vertex.normal, tangent, bitangent);
             }
-            model.meshes.push(model_mesh);
+            // up to MAX_BONE_INFLUENCES ids and weights
+            let influences: Vec<(f32, f32)> = vertex
+                .bone_ids
+                .iter()
+                .zip(&vertex.weights)
+                .take(MAX_BONE_INFLUENCES)
+                .map(|(id, weight)| (*id as f32, *weight))
+                .collect();
+            model_vertex.set_bone_influences(&influences);
+            model_mesh.vertices.push(model_vertex);
+        }
+        if !has_normals {
+            eprintln!("{}: mesh has no normals, generating them", model_filepath);
+            generate_normals(&mut model_mesh, NormalMode::Smooth);
+        }
+        if !has_normals || !has_tangents {
+            generate_tangents(&mut model_mesh);
         }
+        model.meshes.push(model_mesh);
     }
 
-    // load skeleton
-    if let Some(bones) = json["Skeleton"]["Bones"].as_array() {
+    // load skeleton, the ids were checked to go from 0 to the bone count - 1
+    if let Some(skeleton_data) = file.skeleton {
         let mut skeleton: Skeleton = Default::default();
-        for bone in bones {
-            let bone_id: u32 = bone["Id"].as_u64().expect("") as u32;
-            let bone_name: String = bone["Name"].as_str().unwrap_or("Unknown").to_string();
-            let bone_parent_name = bone["ParentName"].as_str().unwrap_or("Unknown").to_string();
-            let bone_parent_id: i32 = bone["ParentId"].as_i64().expect("") as i32;
-            // Parse offset matrix
-            let offset_matrix: [[f32; 4]; 4] = if let Some(offset) = bone["Offset"].as_array() {
-                let mut matrix: [[f32; 4]; 4] = [[0.0; 4]; 4];
-                for (row_index, row) in offset.iter().enumerate() {
-                    if let Some(row_values) = row.as_array() {
-                        for (col_index, value) in row_values.iter().enumerate() {
-                            if let Some(float_value) = value.as_f64() {
-                                matrix[row_index][col_index] = float_value as f32;
-                            }
-                        }
-                    }
-                }
-                matrix
-            } else {
-                // Handle the case where offset is not an array
-                // You might want to provide a default matrix or handle the error accordingly
-                [[0.0; 4]; 4]
+        let mut bones: Vec<Bone> = vec![Default::default(); skeleton_data.bones.len()];
+        for bone in skeleton_data.bones {
+            let bone = Bone {
+                name: bone.name,
+                id: bone.id,
+                parent_id: usize::try_from(bone.parent_id).ok(),
+                inverse_bind_matrix: bone.offset,
+                index: bone.id as usize,
+                parent_offset: None,
             };
-            let mut parent_id = None;
-            if bone_parent_id > -1 {
-                parent_id = Some(bone_parent_id as usize);
-            }
-            skeleton.bones.insert(
-                bone_id as usize,
-                Bone {
-                    name: bone_name,
-                    id: bone_id,
-                    parent_id: parent_id,
-                    inverse_bind_matrix: offset_matrix,
-                    index: bone_id as usize,
-                },
-            );
-        }
-        // order bones
-        let mut bones: Vec<Bone> = Vec::new();
-        let size = skeleton.bones.values().len();
-        for _ in 0..size {
-            bones.push(Default::default());
-        }
-        for bone in skeleton.bones.values() {
-            bones[bone.id as usize] = bone.clone();
+            skeleton.bones.insert(bone.id as usize, bone.clone());
+            let id = bone.id as usize;
+            bones[id] = bone;
         }
         skeleton.bones_ordered = bones;
         // update model skeleton
-        model.meshes[0].skeleton = Some(skeleton);
+        match model.meshes.first_mut() {
+            Some(mesh) => mesh.skeleton = Some(skeleton),
+            None => {
+                return Err(anyhow::anyhow!(
+                    "{}: Skeleton: there is no mesh to attach it to",
+                    model_filepath
+                ))
+            }
+        }
     }
-    // load animations
-    let anim =
-        json_anim_loader(anims_filepath, model.meshes[0].skeleton.as_ref().expect("")).expect("");
-    Ok((model, anim))
+    // fix bone weights and ids before they reach the gpu
+    model.skin_reports = validate_model_skins(&mut model, &SkinValidationOptions::default());
+    log_reports(&model.skin_reports);
+    Ok(model)
 }
 
+// animations in custom json format, bound to the skeleton bone ids
 pub fn json_anim_loader(filepath: &str, skeleton: &Skeleton) -> anyhow::Result<Vec<Animation>> {
-    let file = File::open(filepath)?;
-    let mut reader = std::io::BufReader::new(file);
+    let mut anims = json_anim_clips(filepath)?;
+    let reports: Vec<ClipBindingReport> = anims
+        .iter_mut()
+        .map(|anim| {
+            anim.bind_bone_ids(skeleton);
+            ClipBindingReport::new(anim, skeleton, filepath)
+        })
+        .collect();
+    log_binding_reports(&reports);
+    Ok(anims)
+}
 
-    let mut content = String::new();
-    reader.read_to_string(&mut content)?;
+// animations in custom json format, tracks indexed by bone name only, each clip
+// is converted as soon as it is parsed
+pub fn json_anim_clips(filepath: &str) -> anyhow::Result<Vec<Animation>> {
+    let mut anims = Vec::new();
+    AnimationFile::for_each_clip(filepath, |animation| anims.push(json_animation(animation)))?;
+    Ok(anims)
+}
 
-    let json: Value = serde_json::from_str(&content)?;
+pub fn json_animation(animation: AnimationData) -> Animation {
+    let mut model_animation = Animation::new(&animation.name);
+    for bone in animation.bones {
+        let animated_bone = AnimatedBone {
+            bone_name: bone.name,
+            translation_keys: bone
+                .translation_keys
+                .into_iter()
+                .map(|key| KeyTranslation {
+                    timestamp: key.time,
+                    translation: key.position,
+                })
+                .collect(),
+            rotation_keys: bone
+                .rotation_keys
+                .into_iter()
+                .map(|key| KeyRotation {
+                    timestamp: key.time,
+                    rotation: key.rotation,
+                })
+                .collect(),
+            scale_keys: bone
+                .scale_keys
+                .into_iter()
+                .map(|key| KeyScale {
+                    timestamp: key.time,
+                    scale: key.scale,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        model_animation.add_track(animated_bone);
+    }
+    model_animation
+}
 
-    let mut anims: Vec<Animation> = Vec::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if let Some(animations) = json["Animations"].as_array() {
-        for animation in animations {
-            let mut model_animation: Animation = Default::default();
-            let name: String = animation["Name"].as_str().unwrap_or("Unknown").to_string();
-            model_animation.name = name;
-            if let Some(bones) = animation["Bones"].as_array() {
-                for bone in bones {
-                    let mut animated_bone: AnimatedBone = Default::default();
-                    let bone_name = bone["Name"].as_str().unwrap_or("Unknown").to_string();
-                    // translation
-                    if let Some(keys) = bone["TranslationKeys"].as_array() {
-                        for key in keys {
-                            if let Some(positions) = key["Position"].as_array() {
-                                let x: f32 = positions[0].as_f64().unwrap_or_default() as f32;
-                                let y: f32 = positions[1].as_f64().unwrap_or_default() as f32;
-                                let z: f32 = positions[2].as_f64().unwrap_or_default() as f32;
-                                //model_vertex.position = [x, y, z];
-                                let time = key["Time"].as_f64().expect("") as f32;
-                                animated_bone.translation_keys.push(KeyTranslation {
-                                    timestamp: time,
-                                    translation: [x, y, z],
-                                })
-                            }
-                        }
-                    }
-                    // rotation
-                    if let Some(keys) = bone["RotationKeys"].as_array() {
-                        for key in keys {
-                            if let Some(rotations) = key["Rotation"].as_array() {
-                                let x: f32 = rotations[0].as_f64().unwrap_or_default() as f32;
-                                let y: f32 = rotations[1].as_f64().unwrap_or_default() as f32;
-                                let z: f32 = rotations[2].as_f64().unwrap_or_default() as f32;
-                                let w: f32 = rotations[3].as_f64().unwrap_or_default() as f32;
-                                //model_vertex.position = [x, y, z];
-                                let time = key["Time"].as_f64().expect("") as f32;
-                                animated_bone.rotation_keys.push(KeyRotation {
-                                    timestamp: time,
-                                    rotation: [x, y, z, w],
-                                })
-                            }
-                        }
-                    }
-                    // scale
-                    if let Some(keys) = bone["ScaleKeys"].as_array() {
-                        for key in keys {
-                            if let Some(scales) = key["Scale"].as_array() {
-                                let x: f32 = scales[0].as_f64().unwrap_or_default() as f32;
-                                let y: f32 = scales[1].as_f64().unwrap_or_default() as f32;
-                                let z: f32 = scales[2].as_f64().unwrap_or_default() as f32;
-                                //model_vertex.position = [x, y, z];
-                                let time = key["Time"].as_f64().expect("") as f32;
-                                animated_bone.scale_keys.push(KeyScale {
-                                    timestamp: time,
-                                    scale: [x, y, z],
-                                })
-                            }
-                        }
-                    }
-                    animated_bone.bone_name = bone_name.clone();
-                    model_animation
-                        .bone_keyframes_name
-                        .insert(bone_name.clone(), animated_bone.clone());
-                    let mut key = None;
-                    for bone in &skeleton.bones {
-                        let bone = bone.1;
-                        if bone.name == bone_name {
-                            key = Some(bone.id as usize);
-                            break;
-                        }
-                    }
-                    if let Some(key) = key {
-                        model_animation.bone_keyframes.insert(key, animated_bone);
-                    } else {
-                        println!("No key found for {}", bone_name);
-                    }
-                }
-            }
-            anims.push(model_animation);
+    // files of one test in their own directory, removed afterwards
+    fn with_files(name: &str, files: &[(&str, &str)], test: impl FnOnce(&std::path::Path)) {
+        let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file_name, contents) in files {
+            std::fs::write(dir.join(file_name), contents).unwrap();
+        }
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| test(&dir)));
+        std::fs::remove_dir_all(&dir).unwrap();
+        if let Err(panic) = result {
+            std::panic::resume_unwind(panic);
         }
     }
-    // load animations
-    Ok(anims)
+
+    #[test]
+    fn missing_texture_is_dropped() {
+        let obj = "mtllib quad.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl painted\nf 1 2 3\n";
+        let mtl = "newmtl painted\nKd 1 0 0\nmap_Kd missing.png\n";
+        with_files(
+            "obj_missing_texture",
+            &[("quad.obj", obj), ("quad.mtl", mtl)],
+            |dir| {
+                let model = load_obj(dir.join("quad.obj").to_str().unwrap()).unwrap();
+                assert_eq!(model.meshes.len(), 1);
+                assert_eq!(model.materials.len(), 1);
+                assert_eq!(model.materials[0].base_color_factor, [1.0, 0.0, 0.0, 1.0]);
+                assert!(model.materials[0].base_color_texture.is_none());
+                assert!(model.images.is_empty());
+            },
+        );
+    }
+
+    #[test]
+    fn file_without_faces_is_an_error() {
+        with_files(
+            "obj_without_faces",
+            &[
+                ("garbage.obj", "hello world\nthis is not an obj\n"),
+                ("empty.obj", ""),
+                ("points.obj", "v 0 0 0\nv 1 0 0\n"),
+            ],
+            |dir| {
+                for file_name in ["garbage.obj", "empty.obj", "points.obj"] {
+                    let path = dir.join(file_name);
+                    let err = load_obj(path.to_str().unwrap()).unwrap_err();
+                    assert!(err.to_string().contains("no geometry"), "{}", err);
+                }
+            },
+        );
+    }
 }
@@ -0,0 +1,57 @@
+// small helpers to spread cpu work (e.g. animation sampling) over worker threads
+
+// below this many items per worker the thread startup costs more than it saves
+const MIN_ITEMS_PER_WORKER: usize = 4;
+
+pub fn worker_count(item_count: usize) -> usize {
+    let available = std::thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1);
+    available.min(item_count / MIN_ITEMS_PER_WORKER).max(1)
+}
+
+// run `f` on every item using scoped threads, the results keep the item order
+pub fn map_mut<T, R, F>(items: &mut [T], f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(&mut T) -> R + Sync,
+{
+    let workers = worker_count(items.len());
+    if workers <= 1 {
+        return items.iter_mut().map(f).collect();
+    }
+    let chunk_size = items.len().div_ceil(workers);
+    std::thread::scope(|scope| {
+        let f = &f;
+        let handles: Vec<_> = items
+            .chunks_mut(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter_mut().map(f).collect::<Vec<R>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("worker thread panicked"))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_mut_keeps_the_item_order() {
+        // counts below, at and above the worker threshold, most not a multiple
+        // of the worker count
+        for count in [0, 1, 7, MIN_ITEMS_PER_WORKER * 3 + 1, 1001] {
+            let mut items: Vec<(usize, u32)> = (0..count).map(|index| (index, 0)).collect();
+            let results = map_mut(&mut items, |item| {
+                item.1 += 1;
+                item.0 * 2
+            });
+            let expected: Vec<usize> = (0..count).map(|index| index * 2).collect();
+            assert_eq!(results, expected, "{} items", count);
+            assert!(items.iter().all(|item| item.1 == 1), "{} items", count);
+        }
+    }
+}
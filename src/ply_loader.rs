@@ -0,0 +1,562 @@
+// polygon file format (ply) of 3d scanners: a text header listing the elements
+// and their properties, then the data as text or binary (either endianness);
+// the vertex positions, normals, colors and uvs and the face lists are read,
+// other elements and properties are skipped
+use crate::mesh_utils::{generate_normals, generate_tangents, srgb_to_linear, NormalMode};
+use crate::model::{Mesh, Model, ModelVertex};
+use crate::tokens::Tokens;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(tokens: &mut Tokens) -> anyhow::Result<Self> {
+        match tokens.next("a property type")? {
+            "char" | "int8" => Ok(Scalar::I8),
+            "uchar" | "uint8" => Ok(Scalar::U8),
+            "short" | "int16" => Ok(Scalar::I16),
+            "ushort" | "uint16" => Ok(Scalar::U16),
+            "int" | "int32" => Ok(Scalar::I32),
+            "uint" | "uint32" => Ok(Scalar::U32),
+            "float" | "float32" => Ok(Scalar::F32),
+            "double" | "float64" => Ok(Scalar::F64),
+            name => Err(tokens.error(format!("unknown property type '{}'", name))),
+        }
+    }
+
+    // integer colors are divided by the largest value of their type, float
+    // colors are already in 0-1 (and linear)
+    fn color_range(self) -> Option<f64> {
+        match self {
+            Scalar::I8 => Some(i8::MAX as f64),
+            Scalar::U8 => Some(u8::MAX as f64),
+            Scalar::I16 => Some(i16::MAX as f64),
+            Scalar::U16 => Some(u16::MAX as f64),
+            Scalar::I32 => Some(i32::MAX as f64),
+            Scalar::U32 => Some(u32::MAX as f64),
+            Scalar::F32 | Scalar::F64 => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    // type of the item count of list properties
+    count: Option<Scalar>,
+    scalar: Scalar,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+fn parse_header(tokens: &mut Tokens) -> anyhow::Result<(Encoding, Vec<Element>)> {
+    tokens.expect("ply")?;
+    tokens.expect("format")?;
+    let encoding = match tokens.next("a format")? {
+        "ascii" => Encoding::Ascii,
+        "binary_little_endian" => Encoding::LittleEndian,
+        "binary_big_endian" => Encoding::BigEndian,
+        format => return Err(tokens.error(format!("unknown format '{}'", format))),
+    };
+    tokens.expect("1.0")?;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        match tokens.next("element, property or end_header")? {
+            "comment" | "obj_info" => {
+                tokens.rest_of_line();
+            }
+            "element" => {
+                let name = tokens.next("an element name")?.to_string();
+                let count = tokens.number("an element count")?;
+                elements.push(Element {
+                    name,
+                    count,
+                    properties: Vec::new(),
+                });
+            }
+            "property" => {
+                let count = if tokens.peek() == Some("list") {
+                    tokens.next("list")?;
+                    Some(Scalar::parse(tokens)?)
+                } else {
+                    None
+                };
+                let scalar = Scalar::parse(tokens)?;
+                let name = tokens.next("a property name")?.to_string();
+                let element = elements.last_mut().ok_or_else(|| {
+                    tokens.error(format!("property '{}' before any element", name))
+                })?;
+                element.properties.push(Property {
+                    name,
+                    count,
+                    scalar,
+                });
+            }
+            "end_header" => return Ok((encoding, elements)),
+            word => {
+                return Err(tokens.error(format!(
+                    "expected element, property or end_header, found '{}'",
+                    word
+                )))
+            }
+        }
+    }
+}
+
+// the data after the header
+enum Values<'a> {
+    Text(Tokens<'a>),
+    Binary {
+        bytes: &'a [u8],
+        position: usize,
+        big_endian: bool,
+    },
+}
+
+impl Values<'_> {
+    fn read(&mut self, scalar: Scalar) -> anyhow::Result<f64> {
+        let (bytes, position, big_endian) = match self {
+            Values::Text(tokens) => return tokens.number("a property value"),
+            Values::Binary {
+                bytes,
+                position,
+                big_endian,
+            } => (*bytes, position, *big_endian),
+        };
+        let size = match scalar {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        };
+        let mut value = [0u8; 8];
+        value[..size].copy_from_slice(
+            bytes
+                .get(*position..*position + size)
+                .ok_or_else(|| anyhow::anyhow!("the data ends before the last element"))?,
+        );
+        *position += size;
+        if big_endian {
+            value[..size].reverse();
+        }
+        let [b0, b1, b2, b3, ..] = value;
+        Ok(match scalar {
+            Scalar::I8 => b0 as i8 as f64,
+            Scalar::U8 => b0 as f64,
+            Scalar::I16 => i16::from_le_bytes([b0, b1]) as f64,
+            Scalar::U16 => u16::from_le_bytes([b0, b1]) as f64,
+            Scalar::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+            Scalar::F64 => f64::from_le_bytes(value),
+        })
+    }
+
+    // item count of a list property
+    fn count(&mut self, scalar: Scalar) -> anyhow::Result<usize> {
+        let count = self.read(scalar)?;
+        if count < 0.0 || count.fract() != 0.0 {
+            return Err(anyhow::anyhow!("invalid list length {}", count));
+        }
+        Ok(count as usize)
+    }
+
+    fn at_end(&mut self) -> bool {
+        match self {
+            Values::Text(tokens) => tokens.peek().is_none(),
+            Values::Binary {
+                bytes, position, ..
+            } => *position == bytes.len(),
+        }
+    }
+}
+
+// what a vertex property is read into
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Position(usize),
+    Normal(usize),
+    Color(usize, Option<f64>),
+    Uv(usize),
+    Skip,
+}
+
+fn vertex_target(property: &Property) -> Target {
+    if property.count.is_some() {
+        return Target::Skip;
+    }
+    let color = |channel| Target::Color(channel, property.scalar.color_range());
+    match property.name.as_str() {
+        "x" => Target::Position(0),
+        "y" => Target::Position(1),
+        "z" => Target::Position(2),
+        "nx" => Target::Normal(0),
+        "ny" => Target::Normal(1),
+        "nz" => Target::Normal(2),
+        "red" | "diffuse_red" => color(0),
+        "green" | "diffuse_green" => color(1),
+        "blue" | "diffuse_blue" => color(2),
+        "alpha" => color(3),
+        "u" | "s" | "texture_u" | "texture_s" => Target::Uv(0),
+        "v" | "t" | "texture_v" | "texture_t" => Target::Uv(1),
+        _ => Target::Skip,
+    }
+}
+
+// true when the properties fill every given target
+fn has_targets(targets: &[Target], expected: &[Target]) -> bool {
+    expected.iter().all(|expected| {
+        targets.iter().any(|target| match (target, expected) {
+            (Target::Color(a, _), Target::Color(b, _)) => a == b,
+            _ => target == expected,
+        })
+    })
+}
+
+fn read_vertices(
+    element: &Element,
+    values: &mut Values,
+    mesh: &mut Mesh,
+) -> anyhow::Result<(bool, bool)> {
+    let targets: Vec<Target> = element.properties.iter().map(vertex_target).collect();
+    if !has_targets(&targets, &[0, 1, 2].map(Target::Position)) {
+        return Err(anyhow::anyhow!(
+            "the vertices have no x, y and z properties"
+        ));
+    }
+    let has_normals = has_targets(&targets, &[0, 1, 2].map(Target::Normal));
+    let has_colors = has_targets(
+        &targets,
+        &[0, 1, 2].map(|channel| Target::Color(channel, None)),
+    );
+    mesh.vertices.reserve(element.count.min(1 << 20));
+    for _ in 0..element.count {
+        let mut vertex = ModelVertex::default();
+        let mut color = [1.0f32; 4];
+        for (property, target) in element.properties.iter().zip(&targets) {
+            if let Some(count_scalar) = property.count {
+                for _ in 0..values.count(count_scalar)? {
+                    values.read(property.scalar)?;
+                }
+                continue;
+            }
+            let value = values.read(property.scalar)?;
+            match *target {
+                Target::Position(axis) => vertex.position[axis] = value as f32,
+                Target::Normal(axis) => vertex.normal[axis] = value as f32,
+                Target::Uv(axis) => vertex.tex_coords[axis] = value as f32,
+                // rgb stored as integers is srgb encoded
+                Target::Color(channel, Some(range)) if channel < 3 => {
+                    color[channel] = srgb_to_linear((value / range) as f32)
+                }
+                Target::Color(channel, Some(range)) => color[channel] = (value / range) as f32,
+                Target::Color(channel, None) => color[channel] = value as f32,
+                Target::Skip => {}
+            }
+        }
+        mesh.vertices.push(vertex);
+        if has_colors {
+            mesh.colors.push(color);
+        }
+    }
+    Ok((has_normals, has_colors))
+}
+
+// polygons are triangulated as fans, faces with less than 3 corners are skipped
+fn read_faces(element: &Element, values: &mut Values, mesh: &mut Mesh) -> anyhow::Result<usize> {
+    let is_index_list = |property: &Property| {
+        property.count.is_some()
+            && (property.name == "vertex_indices" || property.name == "vertex_index")
+    };
+    if !element.properties.iter().any(is_index_list) {
+        return Err(anyhow::anyhow!("the faces have no vertex_indices list"));
+    }
+    let mut skipped = 0;
+    let mut polygon: Vec<u32> = Vec::new();
+    for _ in 0..element.count {
+        for property in &element.properties {
+            let Some(count_scalar) = property.count else {
+                values.read(property.scalar)?;
+                continue;
+            };
+            let count = values.count(count_scalar)?;
+            if !is_index_list(property) {
+                for _ in 0..count {
+                    values.read(property.scalar)?;
+                }
+                continue;
+            }
+            polygon.clear();
+            for _ in 0..count {
+                let index = values.read(property.scalar)?;
+                if index < 0.0 || index.fract() != 0.0 || index > u32::MAX as f64 {
+                    return Err(anyhow::anyhow!("invalid vertex index {}", index));
+                }
+                polygon.push(index as u32);
+            }
+            if polygon.len() < 3 {
+                skipped += 1;
+                continue;
+            }
+            for corner in 1..polygon.len() - 1 {
+                mesh.indices
+                    .extend([polygon[0], polygon[corner], polygon[corner + 1]]);
+            }
+        }
+    }
+    Ok(skipped)
+}
+
+// one mesh named `name` (ply has no material)
+pub fn parse_ply(bytes: &[u8], name: &str) -> anyhow::Result<Model> {
+    if !bytes.starts_with(b"ply") {
+        return Err(anyhow::anyhow!("the file doesn't start with 'ply'"));
+    }
+    // the header is text, the line after end_header starts the data
+    let header_end = bytes
+        .windows(10)
+        .position(|window| window == b"end_header")
+        .and_then(|start| {
+            bytes[start..]
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .map(|end| start + end + 1)
+        })
+        .ok_or_else(|| anyhow::anyhow!("the header has no end_header line"))?;
+    let header_text = std::str::from_utf8(&bytes[..header_end])
+        .map_err(|err| anyhow::anyhow!("the header is not valid utf-8 ({})", err))?;
+    let mut tokens = Tokens::new(header_text);
+    let (encoding, elements) = parse_header(&mut tokens)?;
+    let mut values = match encoding {
+        // parsed again from the whole text to report the line numbers of the file
+        Encoding::Ascii => {
+            let text = std::str::from_utf8(bytes)
+                .map_err(|err| anyhow::anyhow!("ascii ply is not valid utf-8 ({})", err))?;
+            let mut tokens = Tokens::new(text);
+            parse_header(&mut tokens)?;
+            Values::Text(tokens)
+        }
+        Encoding::LittleEndian | Encoding::BigEndian => Values::Binary {
+            bytes: &bytes[header_end..],
+            position: 0,
+            big_endian: encoding == Encoding::BigEndian,
+        },
+    };
+
+    let mut mesh = Mesh {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    let mut has_vertices = false;
+    let mut has_normals = false;
+    let mut skipped_faces = 0;
+    for element in &elements {
+        let in_element =
+            |err: anyhow::Error| anyhow::anyhow!("element '{}': {}", element.name, err);
+        match element.name.as_str() {
+            "vertex" if !has_vertices => {
+                has_vertices = true;
+                (has_normals, _) =
+                    read_vertices(element, &mut values, &mut mesh).map_err(in_element)?;
+            }
+            "face" => {
+                skipped_faces += read_faces(element, &mut values, &mut mesh).map_err(in_element)?
+            }
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        let count = match property.count {
+                            Some(count_scalar) => values.count(count_scalar).map_err(in_element)?,
+                            None => 1,
+                        };
+                        for _ in 0..count {
+                            values.read(property.scalar).map_err(in_element)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if !values.at_end() {
+        return Err(anyhow::anyhow!("unexpected data after the last element"));
+    }
+    if !has_vertices {
+        return Err(anyhow::anyhow!("the file has no vertex element"));
+    }
+    let vertex_count = mesh.vertices.len();
+    if let Some(index) = mesh
+        .indices
+        .iter()
+        .find(|index| **index as usize >= vertex_count)
+    {
+        return Err(anyhow::anyhow!(
+            "a face uses vertex {}, the file has {} vertices",
+            index,
+            vertex_count
+        ));
+    }
+    if skipped_faces > 0 {
+        eprintln!(
+            "mesh '{}': {} faces with less than 3 vertices were skipped",
+            name, skipped_faces
+        );
+    }
+    if mesh.indices.is_empty() {
+        eprintln!("mesh '{}' has no faces (point cloud)", name);
+    }
+    if !has_normals {
+        eprintln!("mesh '{}' has no normals, generating them", name);
+        generate_normals(&mut mesh, NormalMode::Smooth);
+    }
+    generate_tangents(&mut mesh);
+    Ok(Model {
+        meshes: vec![mesh],
+        ..Default::default()
+    })
+}
+
+// the mesh is named after the file
+pub fn load_ply(path: &str) -> anyhow::Result<Model> {
+    let bytes = std::fs::read(path)
+        .map_err(|err| anyhow::anyhow!("{}: can't read file ({})", path, err))?;
+    let name = Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("ply");
+    parse_ply(&bytes, name).map_err(|err| anyhow::anyhow!("{}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property uchar intensity
+property list uchar int vertex_indices
+end_header
+";
+
+    // the HEADER data in binary: a unit quad with red, green, blue and white corners
+    fn binary_ply(format: &str, to_bytes: impl Fn(&[u8; 4]) -> [u8; 4]) -> Vec<u8> {
+        let mut bytes = format!("ply\nformat {} 1.0\n{}", format, HEADER).into_bytes();
+        let corners = [
+            ([0.0f32, 0.0, 0.0], [255u8, 0, 0]),
+            ([1.0, 0.0, 0.0], [0, 255, 0]),
+            ([1.0, 1.0, 0.0], [0, 0, 255]),
+            ([0.0, 1.0, 0.0], [255, 255, 255]),
+        ];
+        for (position, color) in corners {
+            for value in position {
+                bytes.extend(to_bytes(&value.to_le_bytes()));
+            }
+            bytes.extend(color);
+        }
+        bytes.extend([7, 4]);
+        for index in [0i32, 1, 2, 3] {
+            bytes.extend(to_bytes(&index.to_le_bytes()));
+        }
+        bytes
+    }
+
+    fn check_quad(model: &Model) {
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.name, "scan");
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.vertices[2].position, [1.0, 1.0, 0.0]);
+        // the quad is split in a fan
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.colors[0], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(mesh.colors[2], [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(mesh.colors[3], [1.0; 4]);
+    }
+
+    #[test]
+    fn ascii_ply() {
+        let text = format!(
+            "ply\nformat ascii 1.0\ncomment from a scanner\n{}\
+             0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n7 4 0 1 2 3\n",
+            HEADER
+        );
+        check_quad(&parse_ply(text.as_bytes(), "scan").unwrap());
+    }
+
+    #[test]
+    fn binary_ply_in_both_endianness() {
+        let little = binary_ply("binary_little_endian", |bytes| *bytes);
+        check_quad(&parse_ply(&little, "scan").unwrap());
+        let big = binary_ply("binary_big_endian", |bytes| {
+            let mut bytes = *bytes;
+            bytes.reverse();
+            bytes
+        });
+        check_quad(&parse_ply(&big, "scan").unwrap());
+    }
+
+    #[test]
+    fn lists_of_other_properties_are_skipped() {
+        let text = "ply\nformat ascii 1.0\n\
+             element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+             property list uchar float extra\n\
+             element face 2\nproperty list uchar uint vertex_index\n\
+             element edge 1\nproperty int vertex1\nproperty int vertex2\nend_header\n\
+             0 0 0 2 9 9\n1 0 0 0\n0 1 0 1 9\n3 0 1 2\n2 0 1\n0 1\n";
+        let model = parse_ply(text.as_bytes(), "scan").unwrap();
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.vertices[1].position, [1.0, 0.0, 0.0]);
+        // the two corner face is skipped
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+        assert!(mesh.colors.is_empty());
+    }
+
+    #[test]
+    fn out_of_range_face_index() {
+        let text = "ply\nformat ascii 1.0\n\
+             element vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+             element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+             0 0 0\n1 0 0\n0 1 0\n3 0 1 3\n";
+        let err = parse_ply(text.as_bytes(), "scan")
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(
+            err.contains("a face uses vertex 3, the file has 3 vertices"),
+            "{}",
+            err
+        );
+        let negative = text.replace("3 0 1 3", "3 0 1 -1");
+        let err = parse_ply(negative.as_bytes(), "scan")
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("invalid vertex index -1"), "{}", err);
+    }
+}
@@ -15,6 +15,8 @@ pub struct Renderer {
     can_render: bool,
     pub render_objects: Vec<Rc<RefCell<dyn Render>>>,
     depth_texture: crate::texture::Texture,
+    // decoded material images, shared by every model shader
+    pub texture_cache: RefCell<crate::material::TextureCache>,
 }
 
 impl Renderer {
@@ -80,6 +82,7 @@ impl Renderer {
             can_render: true,
             render_objects: Vec::new(),
             depth_texture,
+            texture_cache: RefCell::default(),
         }
     }
     pub fn resize(&mut self, new_size: WindowSize) {
@@ -0,0 +1,107 @@
+use crate::camera::Camera;
+use crate::light::Light;
+use crate::material::{ImageData, Material};
+use crate::model::{Animation, Model};
+use crate::transform::Transform;
+use cgmath::Matrix4;
+use std::sync::Arc;
+
+pub struct SceneNode {
+    pub name: String,
+    // local transform, relative to the parent node
+    pub transform: Transform,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    // index in Scene::models
+    pub model: Option<usize>,
+    // index of the skin in the source file, nodes with the same skin share a skeleton
+    pub skin: Option<usize>,
+    // index in Scene::cameras
+    pub camera: Option<usize>,
+    // index in Scene::lights
+    pub light: Option<usize>,
+}
+
+// node tree of an imported file, nodes are ordered parents first
+#[derive(Default)]
+pub struct Scene {
+    pub name: String,
+    pub nodes: Vec<SceneNode>,
+    pub roots: Vec<usize>,
+    // one model per node with a mesh, in mesh space
+    // (skinned meshes are placed by their skeleton instead)
+    pub models: Vec<Model>,
+    // shared by every model, Mesh::material indexes this list
+    pub materials: Vec<Material>,
+    pub images: Vec<Arc<ImageData>>,
+    // cameras and lights placed by their node, in scene space
+    pub cameras: Vec<Camera>,
+    pub lights: Vec<Light>,
+    // clips indexed by node name
+    pub animations: Vec<Animation>,
+}
+
+impl Scene {
+    // transform from the node space to the scene space
+    pub fn world_matrix(&self, node: usize) -> Matrix4<f32> {
+        let mut matrix = self.nodes[node].transform.matrix();
+        let mut parent = self.nodes[node].parent;
+        while let Some(index) = parent {
+            matrix = self.nodes[index].transform.matrix() * matrix;
+            parent = self.nodes[index].parent;
+        }
+        matrix
+    }
+
+    // models ready to be drawn: one per skin with every mesh using it, then one
+    // with all the static meshes baked in scene space
+    pub fn into_models(self) -> Vec<Model> {
+        let world_matrices: Vec<Matrix4<f32>> = (0..self.nodes.len())
+            .map(|node| self.world_matrix(node))
+            .collect();
+        let mut models: Vec<Option<Model>> = self.models.into_iter().map(Some).collect();
+        let mut skinned: Vec<(usize, Model)> = Vec::new();
+        let materials = self.materials;
+        let images = self.images;
+        let mut static_model = Model::default();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let Some(mut model) = node.model.and_then(|model| models[model].take()) else {
+                continue;
+            };
+            match node.skin {
+                Some(skin) => match skinned.iter_mut().find(|(other, _)| *other == skin) {
+                    Some((_, group)) => group.append(model),
+                    None => skinned.push((skin, model)),
+                },
+                None => {
+                    for mesh in &mut model.meshes {
+                        mesh.transform(world_matrices[index]);
+                    }
+                    static_model.append(model);
+                }
+            }
+        }
+        skinned.sort_by_key(|(skin, _)| *skin);
+        let mut result: Vec<Model> = skinned.into_iter().map(|(_, model)| model).collect();
+        if !static_model.meshes.is_empty() {
+            result.push(static_model);
+        }
+        for model in &mut result {
+            model.materials = materials.clone();
+            // the image bytes are shared, not copied
+            model.images = images.clone();
+        }
+        result
+    }
+
+    // every mesh in a single model, only correct with at most one skin
+    pub fn into_model(self) -> Model {
+        let mut result = Model::default();
+        for mut model in self.into_models() {
+            result.materials = std::mem::take(&mut model.materials);
+            result.images = std::mem::take(&mut model.images);
+            result.append(model);
+        }
+        result
+    }
+}
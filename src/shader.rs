@@ -228,16 +228,40 @@ pub fn create_render_pipeline(
     pipeline_label: Option<&str>,
 ) -> wgpu::RenderPipeline {
     //let shader = device.create_shader_module(shader);
+    create_render_pipeline_with_entry(
+        device,
+        layout,
+        color_format,
+        depth_format,
+        vertex_layouts,
+        &shader,
+        "vs_main",
+        pipeline_label,
+    )
+}
+
+// same pipeline with another vertex entry point, several pipelines can share the module
+#[allow(clippy::too_many_arguments)]
+pub fn create_render_pipeline_with_entry(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: &wgpu::ShaderModule,
+    vertex_entry: &str,
+    pipeline_label: Option<&str>,
+) -> wgpu::RenderPipeline {
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: pipeline_label,
         layout: Some(layout),
         vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: "vs_main",
+            module: shader,
+            entry_point: vertex_entry,
             buffers: vertex_layouts,
         },
         fragment: Some(wgpu::FragmentState {
-            module: &shader,
+            module: shader,
             entry_point: "fs_main",
             targets: &[Some(wgpu::ColorTargetState {
                 format: color_format,
@@ -0,0 +1,509 @@
+use crate::model::{Mesh, Model, ModelVertex, MAX_BONES, MAX_BONE_INFLUENCES};
+use std::fmt;
+
+// bone id stored in influence slots that are not used
+pub const UNUSED_BONE_ID: f32 = -1.0;
+// weights summing to 1 within this tolerance are not reported
+const NORMALIZED_TOLERANCE: f32 = 1e-3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkinValidationOptions {
+    // influences lighter than this (after normalization) are removed
+    pub min_weight: f32,
+    // keep only the strongest influences, e.g. 4 to fit a single attribute set
+    pub max_influences: usize,
+}
+
+impl Default for SkinValidationOptions {
+    fn default() -> Self {
+        Self {
+            min_weight: 1e-3,
+            max_influences: MAX_BONE_INFLUENCES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkinIssue {
+    // NaN, infinite or negative weight, the influence is removed
+    InvalidWeight {
+        vertex: usize,
+        slot: usize,
+        weight: f32,
+    },
+    // NaN, negative or fractional bone id on a weighted slot, the influence is removed
+    InvalidBoneId {
+        vertex: usize,
+        slot: usize,
+        bone_id: f32,
+    },
+    // bone id not present in the skeleton, the influence is removed
+    BoneIdOutOfRange {
+        vertex: usize,
+        slot: usize,
+        bone_id: u32,
+        bone_count: usize,
+    },
+    // weights did not sum to 1 and were rescaled
+    Unnormalized {
+        vertex: usize,
+        sum: f32,
+    },
+    // influence below SkinValidationOptions::min_weight, removed
+    DroppedInfluence {
+        vertex: usize,
+        slot: usize,
+        weight: f32,
+    },
+    // no usable influence left, the vertex is left unskinned
+    NoInfluences {
+        vertex: usize,
+    },
+    // influence removed to respect SkinValidationOptions::max_influences
+    ExcessInfluence {
+        vertex: usize,
+        bone_id: u32,
+        weight: f32,
+    },
+}
+
+impl SkinIssue {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SkinIssue::InvalidWeight { .. } => "invalid weight",
+            SkinIssue::InvalidBoneId { .. } => "invalid bone id",
+            SkinIssue::BoneIdOutOfRange { .. } => "bone id out of range",
+            SkinIssue::Unnormalized { .. } => "unnormalized weights",
+            SkinIssue::DroppedInfluence { .. } => "dropped influence",
+            SkinIssue::NoInfluences { .. } => "no influences",
+            SkinIssue::ExcessInfluence { .. } => "excess influence",
+        }
+    }
+}
+
+impl fmt::Display for SkinIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkinIssue::InvalidWeight {
+                vertex,
+                slot,
+                weight,
+            } => write!(f, "vertex {vertex} slot {slot}: invalid weight {weight}"),
+            SkinIssue::InvalidBoneId {
+                vertex,
+                slot,
+                bone_id,
+            } => write!(f, "vertex {vertex} slot {slot}: invalid bone id {bone_id}"),
+            SkinIssue::BoneIdOutOfRange {
+                vertex,
+                slot,
+                bone_id,
+                bone_count,
+            } => write!(
+                f,
+                "vertex {vertex} slot {slot}: bone id {bone_id} out of range (skeleton has {bone_count} bones)"
+            ),
+            SkinIssue::Unnormalized { vertex, sum } => {
+                write!(f, "vertex {vertex}: weights sum to {sum}")
+            }
+            SkinIssue::DroppedInfluence {
+                vertex,
+                slot,
+                weight,
+            } => write!(f, "vertex {vertex} slot {slot}: dropped weight {weight}"),
+            SkinIssue::NoInfluences { vertex } => write!(f, "vertex {vertex}: no influences left"),
+            SkinIssue::ExcessInfluence {
+                vertex,
+                bone_id,
+                weight,
+            } => write!(
+                f,
+                "vertex {vertex}: removed bone {bone_id} (weight {weight}) over the influence limit"
+            ),
+        }
+    }
+}
+
+// problems found (and fixed) in the skin data of one mesh
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SkinReport {
+    pub mesh_index: usize,
+    pub mesh_name: String,
+    pub bone_count: usize,
+    pub issues: Vec<SkinIssue>,
+}
+
+impl SkinReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    // issue count per kind, in first seen order
+    pub fn counts(&self) -> Vec<(&'static str, usize)> {
+        let mut counts: Vec<(&'static str, usize)> = Vec::new();
+        for issue in &self.issues {
+            match counts.iter_mut().find(|(kind, _)| *kind == issue.kind()) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((issue.kind(), 1)),
+            }
+        }
+        counts
+    }
+
+    pub fn summary(&self) -> String {
+        let counts = self
+            .counts()
+            .iter()
+            .map(|(kind, count)| format!("{count} {kind}"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        format!(
+            "mesh {} '{}': {} skin issues ({})",
+            self.mesh_index,
+            self.mesh_name,
+            self.issues.len(),
+            counts
+        )
+    }
+}
+
+// print a one line summary of every report with issues
+pub fn log_reports(reports: &[SkinReport]) {
+    for report in reports.iter().filter(|report| !report.is_clean()) {
+        eprintln!("{}", report.summary());
+    }
+}
+
+// validate and fix the skin of every mesh, meshes without a skeleton use the first
+// skeleton of the model (the json format stores it only on the first mesh)
+pub fn validate_model_skins(model: &mut Model, options: &SkinValidationOptions) -> Vec<SkinReport> {
+    let fallback_bone_count = model
+        .meshes
+        .iter()
+        .find_map(|mesh| mesh.skeleton.as_ref())
+        .map(|skeleton| skeleton.bones_ordered.len());
+    let mut reports = Vec::new();
+    for (mesh_index, mesh) in model.meshes.iter_mut().enumerate() {
+        let bone_count = mesh
+            .skeleton
+            .as_ref()
+            .map(|skeleton| skeleton.bones_ordered.len())
+            .or(fallback_bone_count);
+        if let Some(bone_count) = bone_count {
+            reports.push(validate_mesh_skin(mesh, mesh_index, bone_count, options));
+        }
+    }
+    reports
+}
+
+pub fn validate_mesh_skin(
+    mesh: &mut Mesh,
+    mesh_index: usize,
+    bone_count: usize,
+    options: &SkinValidationOptions,
+) -> SkinReport {
+    // the shader can only address MAX_BONES matrices
+    let bone_count = bone_count.min(MAX_BONES);
+    let mut issues = Vec::new();
+    for (index, vertex) in mesh.vertices.iter_mut().enumerate() {
+        validate_vertex(vertex, index, bone_count, options, &mut issues);
+    }
+    SkinReport {
+        mesh_index,
+        mesh_name: mesh.name.clone(),
+        bone_count,
+        issues,
+    }
+}
+
+pub fn validate_vertex(
+    vertex: &mut ModelVertex,
+    index: usize,
+    bone_count: usize,
+    options: &SkinValidationOptions,
+    issues: &mut Vec<SkinIssue>,
+) {
+    let influences = vertex.bone_influences();
+    let mut ids = influences.map(|(id, _)| id);
+    let mut weights = influences.map(|(_, weight)| weight);
+    let mut had_influence = false;
+    for slot in 0..ids.len() {
+        let weight = weights[slot];
+        let id = ids[slot];
+        if !weight.is_finite() || weight < 0.0 {
+            issues.push(SkinIssue::InvalidWeight {
+                vertex: index,
+                slot,
+                weight,
+            });
+            clear_slot(&mut ids, &mut weights, slot);
+            continue;
+        }
+        if weight == 0.0 {
+            // empty slot
+            clear_slot(&mut ids, &mut weights, slot);
+            continue;
+        }
+        had_influence = true;
+        if !id.is_finite() || id < 0.0 || id.fract() != 0.0 {
+            issues.push(SkinIssue::InvalidBoneId {
+                vertex: index,
+                slot,
+                bone_id: id,
+            });
+            clear_slot(&mut ids, &mut weights, slot);
+        } else if id as usize >= bone_count {
+            issues.push(SkinIssue::BoneIdOutOfRange {
+                vertex: index,
+                slot,
+                bone_id: id as u32,
+                bone_count,
+            });
+            clear_slot(&mut ids, &mut weights, slot);
+        }
+    }
+    limit_influences(
+        &mut ids,
+        &mut weights,
+        options.max_influences,
+        index,
+        issues,
+    );
+    let sum: f32 = weights.iter().sum();
+    if sum > 0.0 {
+        if (sum - 1.0).abs() > NORMALIZED_TOLERANCE {
+            issues.push(SkinIssue::Unnormalized { vertex: index, sum });
+        }
+        weights.iter_mut().for_each(|weight| *weight /= sum);
+        // drop tiny influences and renormalize what is left
+        let mut dropped = false;
+        for slot in 0..weights.len() {
+            if weights[slot] > 0.0 && weights[slot] < options.min_weight {
+                issues.push(SkinIssue::DroppedInfluence {
+                    vertex: index,
+                    slot,
+                    weight: weights[slot],
+                });
+                clear_slot(&mut ids, &mut weights, slot);
+                dropped = true;
+            }
+        }
+        let sum: f32 = weights.iter().sum();
+        if dropped && sum > 0.0 {
+            weights.iter_mut().for_each(|weight| *weight /= sum);
+        }
+    }
+    if had_influence && weights.iter().all(|weight| *weight == 0.0) {
+        issues.push(SkinIssue::NoInfluences { vertex: index });
+    }
+    // keep the used slots first so a four slot layout sees the strongest ones
+    let mut influences: Vec<(f32, f32)> = ids
+        .into_iter()
+        .zip(weights)
+        .filter(|(_, weight)| *weight > 0.0)
+        .collect();
+    influences.sort_by(|a, b| b.1.total_cmp(&a.1));
+    vertex.set_bone_influences(&influences);
+}
+
+// remove the weakest influences over `max_influences`
+fn limit_influences(
+    ids: &mut [f32],
+    weights: &mut [f32],
+    max_influences: usize,
+    vertex: usize,
+    issues: &mut Vec<SkinIssue>,
+) {
+    let mut used: Vec<usize> = (0..weights.len())
+        .filter(|slot| weights[*slot] > 0.0)
+        .collect();
+    if used.len() <= max_influences {
+        return;
+    }
+    used.sort_by(|a, b| weights[*b].total_cmp(&weights[*a]));
+    for &slot in &used[max_influences..] {
+        issues.push(SkinIssue::ExcessInfluence {
+            vertex,
+            bone_id: ids[slot] as u32,
+            weight: weights[slot],
+        });
+        clear_slot(ids, weights, slot);
+    }
+}
+
+fn clear_slot(ids: &mut [f32], weights: &mut [f32], slot: usize) {
+    ids[slot] = UNUSED_BONE_ID;
+    weights[slot] = 0.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(influences: &[(f32, f32)]) -> ModelVertex {
+        let mut vertex = ModelVertex::default();
+        vertex.set_bone_influences(influences);
+        vertex
+    }
+
+    fn validate(
+        influences: &[(f32, f32)],
+        bone_count: usize,
+        options: &SkinValidationOptions,
+    ) -> (Vec<(f32, f32)>, Vec<SkinIssue>) {
+        let mut vertex = vertex(influences);
+        let mut issues = Vec::new();
+        validate_vertex(&mut vertex, 0, bone_count, options, &mut issues);
+        let used = vertex
+            .bone_influences()
+            .into_iter()
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect();
+        (used, issues)
+    }
+
+    #[test]
+    fn clean_vertex_is_unchanged() {
+        let (used, issues) = validate(
+            &[(0.0, 0.75), (1.0, 0.25)],
+            2,
+            &SkinValidationOptions::default(),
+        );
+        assert_eq!(used, vec![(0.0, 0.75), (1.0, 0.25)]);
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn zero_sum_weights() {
+        // a vertex that is not skinned at all is left alone
+        let (used, issues) = validate(
+            &[(0.0, 0.0), (1.0, 0.0)],
+            2,
+            &SkinValidationOptions::default(),
+        );
+        assert!(used.is_empty());
+        assert!(issues.is_empty());
+        // a vertex whose only influence was removed has none left
+        let (used, issues) = validate(&[(5.0, 1.0)], 2, &SkinValidationOptions::default());
+        assert!(used.is_empty());
+        assert_eq!(issues.len(), 2);
+        assert!(matches!(issues[1], SkinIssue::NoInfluences { vertex: 0 }));
+    }
+
+    #[test]
+    fn unnormalized_weights_are_scaled() {
+        let (used, issues) = validate(
+            &[(0.0, 1.0), (1.0, 3.0)],
+            2,
+            &SkinValidationOptions::default(),
+        );
+        assert_eq!(used, vec![(1.0, 0.75), (0.0, 0.25)]);
+        assert_eq!(
+            issues,
+            vec![SkinIssue::Unnormalized {
+                vertex: 0,
+                sum: 4.0
+            }]
+        );
+    }
+
+    #[test]
+    fn out_of_range_ids() {
+        let (used, issues) = validate(
+            &[(0.0, 0.5), (2.0, 0.25), (1.5, 0.25)],
+            2,
+            &SkinValidationOptions::default(),
+        );
+        assert_eq!(used, vec![(0.0, 1.0)]);
+        assert_eq!(
+            issues[..2],
+            [
+                SkinIssue::BoneIdOutOfRange {
+                    vertex: 0,
+                    slot: 1,
+                    bone_id: 2,
+                    bone_count: 2,
+                },
+                SkinIssue::InvalidBoneId {
+                    vertex: 0,
+                    slot: 2,
+                    bone_id: 1.5,
+                },
+            ]
+        );
+        // the shader can not address more than MAX_BONES bones
+        let mut mesh = Mesh {
+            vertices: vec![vertex(&[(MAX_BONES as f32, 1.0)])],
+            ..Default::default()
+        };
+        let report = validate_mesh_skin(
+            &mut mesh,
+            0,
+            MAX_BONES + 10,
+            &SkinValidationOptions::default(),
+        );
+        assert_eq!(report.bone_count, MAX_BONES);
+        assert_eq!(report.counts()[0].1, 1);
+    }
+
+    #[test]
+    fn excess_influences() {
+        let influences: Vec<(f32, f32)> = (0..6).map(|id| (id as f32, (id + 1) as f32)).collect();
+        let options = SkinValidationOptions {
+            max_influences: 4,
+            ..Default::default()
+        };
+        let (used, issues) = validate(&influences, 6, &options);
+        // the two weakest go, the strongest come first
+        let ids: Vec<f32> = used.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![5.0, 4.0, 3.0, 2.0]);
+        let sum: f32 = used.iter().map(|(_, weight)| weight).sum();
+        assert!((sum - 1.0).abs() < 1e-6);
+        let removed: Vec<u32> = issues
+            .iter()
+            .filter_map(|issue| match issue {
+                SkinIssue::ExcessInfluence { bone_id, .. } => Some(*bone_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(removed, vec![1, 0]);
+        // all eight slots fit the default limit
+        let influences: Vec<(f32, f32)> = (0..8).map(|id| (id as f32, 0.125)).collect();
+        let (used, issues) = validate(&influences, 8, &SkinValidationOptions::default());
+        assert_eq!(used.len(), 8);
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn nan_weights() {
+        let (used, issues) = validate(
+            &[
+                (0.0, f32::NAN),
+                (1.0, f32::INFINITY),
+                (2.0, -0.5),
+                (3.0, 1.0),
+            ],
+            4,
+            &SkinValidationOptions::default(),
+        );
+        assert_eq!(used, vec![(3.0, 1.0)]);
+        assert_eq!(issues.len(), 3);
+        assert!(issues
+            .iter()
+            .all(|issue| matches!(issue, SkinIssue::InvalidWeight { .. })));
+    }
+
+    #[test]
+    fn tiny_influences_are_dropped() {
+        let (used, issues) = validate(
+            &[(0.0, 0.9995), (1.0, 0.0005)],
+            2,
+            &SkinValidationOptions::default(),
+        );
+        assert_eq!(used, vec![(0.0, 1.0)]);
+        assert!(matches!(
+            issues[0],
+            SkinIssue::DroppedInfluence { slot: 1, .. }
+        ));
+    }
+}
@@ -0,0 +1,456 @@
+// stereolithography (stl) files of cad tools and 3d printers: a triangle soup
+// with one normal per facet, as text ("solid" ... "endsolid") or binary (80 bytes
+// header, u32 facet count, 50 bytes per facet)
+use crate::mesh_utils::{generate_normals, generate_tangents, srgb_to_linear, NormalMode};
+use crate::model::{Mesh, Model, ModelVertex};
+use crate::tokens::Tokens;
+use cgmath::{InnerSpace, Vector3};
+use std::collections::HashMap;
+use std::path::Path;
+
+const BINARY_HEADER_SIZE: usize = 80;
+const BINARY_FACET_SIZE: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StlOptions {
+    // corners closer than this (on every axis) are welded into one vertex,
+    // 0 only welds corners at the same position
+    pub weld_tolerance: f32,
+    // one smooth normal per welded position instead of the facet normals, flat
+    // shading keeps the corners of facets with different normals apart
+    pub smooth_normals: bool,
+}
+
+impl Default for StlOptions {
+    fn default() -> Self {
+        Self {
+            weld_tolerance: 0.0,
+            smooth_normals: false,
+        }
+    }
+}
+
+// cell of the position, bits of the normal and of the color (zeros with smooth normals)
+type WeldKey = ([i64; 3], [u32; 3], [u32; 4]);
+
+struct Facet {
+    normal: [f32; 3],
+    corners: [[f32; 3]; 3],
+    // binary files only, from the attribute bytes
+    color: Option<[f32; 4]>,
+}
+
+// the facet normal when it is usable, else the normal of the winding
+fn facet_normal(facet: &Facet) -> [f32; 3] {
+    let normal = Vector3::from(facet.normal);
+    if normal.x.is_finite()
+        && normal.y.is_finite()
+        && normal.z.is_finite()
+        && normal.magnitude2() > 0.25
+    {
+        return normal.normalize().into();
+    }
+    let [a, b, c] = facet.corners.map(Vector3::from);
+    let normal = (b - a).cross(c - a);
+    if normal.magnitude2() > 0.0 {
+        normal.normalize().into()
+    } else {
+        [0.0, 1.0, 0.0]
+    }
+}
+
+// vertex within the tolerance of `position` on every axis, with the same normal and
+// color, the cells are as wide as the tolerance so it is in the cell of the key or
+// in one of its neighbours
+fn find_welded(
+    welded: &HashMap<WeldKey, Vec<u32>>,
+    vertices: &[ModelVertex],
+    key: &WeldKey,
+    position: [f32; 3],
+    tolerance: f32,
+) -> Option<u32> {
+    if tolerance <= 0.0 {
+        return welded.get(key).and_then(|indices| indices.first().copied());
+    }
+    let (cell, normal, color) = *key;
+    for x in -1..=1 {
+        for y in -1..=1 {
+            for z in -1..=1 {
+                let neighbour = ([cell[0] + x, cell[1] + y, cell[2] + z], normal, color);
+                let found = welded.get(&neighbour).and_then(|indices| {
+                    indices.iter().copied().find(|index| {
+                        let other = vertices[*index as usize].position;
+                        (0..3).all(|axis| (other[axis] - position[axis]).abs() <= tolerance)
+                    })
+                });
+                if found.is_some() {
+                    return found;
+                }
+            }
+        }
+    }
+    None
+}
+
+// indexed mesh from the facets, corners are shared when their position (and
+// with flat shading their normal and color) match
+fn weld(name: &str, facets: &[Facet], options: &StlOptions) -> Mesh {
+    let tolerance = options.weld_tolerance;
+    // the cell of a position (-0.0 and 0.0 are the same position)
+    let cell = |position: [f32; 3]| -> [i64; 3] {
+        if tolerance > 0.0 {
+            position.map(|value| (value / tolerance).round() as i64)
+        } else {
+            position.map(|value| (value + 0.0).to_bits() as i64)
+        }
+    };
+    let has_colors = facets.iter().any(|facet| facet.color.is_some());
+    let mut mesh = Mesh {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    // vertices of every cell, a corner can weld with a vertex of a neighbour cell
+    let mut welded: HashMap<WeldKey, Vec<u32>> = HashMap::new();
+    let mut degenerate = 0;
+    for facet in facets {
+        let normal = facet_normal(facet);
+        let color = facet.color.unwrap_or([1.0; 4]);
+        let mut triangle = [0u32; 3];
+        for (corner, position) in triangle.iter_mut().zip(facet.corners) {
+            let key = if options.smooth_normals {
+                (cell(position), [0; 3], [0; 4])
+            } else {
+                (
+                    cell(position),
+                    normal.map(|value| (value + 0.0).to_bits()),
+                    color.map(f32::to_bits),
+                )
+            };
+            *corner = match find_welded(&welded, &mesh.vertices, &key, position, tolerance) {
+                Some(index) => index,
+                None => {
+                    mesh.vertices.push(ModelVertex {
+                        position,
+                        normal,
+                        ..Default::default()
+                    });
+                    if has_colors {
+                        mesh.colors.push(color);
+                    }
+                    let index = mesh.vertices.len() as u32 - 1;
+                    welded.entry(key).or_default().push(index);
+                    index
+                }
+            };
+        }
+        // corners welded together by the tolerance
+        if triangle[0] == triangle[1] || triangle[1] == triangle[2] || triangle[0] == triangle[2] {
+            degenerate += 1;
+            continue;
+        }
+        mesh.indices.extend(triangle);
+    }
+    if degenerate > 0 {
+        eprintln!(
+            "mesh '{}': {} facets collapsed by the welding were removed",
+            name, degenerate
+        );
+    }
+    if options.smooth_normals {
+        generate_normals(&mut mesh, NormalMode::Smooth);
+    }
+    // stl has no uvs, the tangents are only orthogonal to the normals
+    generate_tangents(&mut mesh);
+    mesh
+}
+
+// a file is binary when its size matches the facet count, text files start
+// with "solid" (binary headers sometimes do too)
+pub fn is_binary_stl(bytes: &[u8]) -> bool {
+    if bytes.len() >= BINARY_HEADER_SIZE + 4 {
+        let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+        if count
+            .checked_mul(BINARY_FACET_SIZE)
+            .is_some_and(|size| size + BINARY_HEADER_SIZE + 4 == bytes.len())
+        {
+            return true;
+        }
+    }
+    !bytes.trim_ascii_start().starts_with(b"solid")
+}
+
+// 15 bits color of the attribute bytes, in the two conventions of the format:
+// materialise magics writes "COLOR=" and a default color in the header, red in
+// the low bits and bit 15 clear for facets with their own color; solidview and
+// viscam put blue in the low bits and set bit 15 for valid colors
+fn facet_color(attribute: u16, default_color: Option<[f32; 4]>) -> Option<[f32; 4]> {
+    let channel = |shift: u16| srgb_to_linear(((attribute >> shift) & 0x1f) as f32 / 31.0);
+    match default_color {
+        Some(_) if attribute & 0x8000 == 0 => Some([channel(0), channel(5), channel(10), 1.0]),
+        Some(default_color) => Some(default_color),
+        None if attribute & 0x8000 != 0 => Some([channel(10), channel(5), channel(0), 1.0]),
+        None => None,
+    }
+}
+
+fn binary_facets(bytes: &[u8]) -> anyhow::Result<Vec<Facet>> {
+    if bytes.len() < BINARY_HEADER_SIZE + 4 {
+        return Err(anyhow::anyhow!(
+            "binary stl needs at least {} bytes, the file has {}",
+            BINARY_HEADER_SIZE + 4,
+            bytes.len()
+        ));
+    }
+    let header = &bytes[..BINARY_HEADER_SIZE];
+    let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+    let data = &bytes[BINARY_HEADER_SIZE + 4..];
+    if count.checked_mul(BINARY_FACET_SIZE) != Some(data.len()) {
+        return Err(anyhow::anyhow!(
+            "{} facets need {} bytes after the header, the file has {}",
+            count,
+            count as u64 * BINARY_FACET_SIZE as u64,
+            data.len()
+        ));
+    }
+    let default_color = header
+        .windows(6)
+        .position(|window| window == b"COLOR=")
+        .and_then(|start| header.get(start + 6..start + 10))
+        .map(|rgba| {
+            [
+                srgb_to_linear(rgba[0] as f32 / 255.0),
+                srgb_to_linear(rgba[1] as f32 / 255.0),
+                srgb_to_linear(rgba[2] as f32 / 255.0),
+                rgba[3] as f32 / 255.0,
+            ]
+        });
+    let facets = data
+        .chunks_exact(BINARY_FACET_SIZE)
+        .map(|facet| {
+            let value = |index: usize| {
+                f32::from_le_bytes(facet[index * 4..index * 4 + 4].try_into().unwrap())
+            };
+            let vector = |first: usize| [value(first), value(first + 1), value(first + 2)];
+            let attribute = u16::from_le_bytes([facet[48], facet[49]]);
+            Facet {
+                normal: vector(0),
+                corners: [vector(3), vector(6), vector(9)],
+                color: facet_color(attribute, default_color),
+            }
+        })
+        .collect();
+    Ok(facets)
+}
+
+fn vector(tokens: &mut Tokens, expected: &str) -> anyhow::Result<[f32; 3]> {
+    Ok([
+        tokens.number(expected)?,
+        tokens.number(expected)?,
+        tokens.number(expected)?,
+    ])
+}
+
+// (name, facets) of every solid of a text file
+fn text_solids(text: &str) -> anyhow::Result<Vec<(String, Vec<Facet>)>> {
+    let mut tokens = Tokens::new(text);
+    let mut solids = Vec::new();
+    while tokens.peek().is_some() {
+        tokens.expect("solid")?;
+        let name = tokens.rest_of_line().join(" ");
+        let mut facets = Vec::new();
+        loop {
+            let word = tokens.next("facet or endsolid")?;
+            match word.to_ascii_lowercase().as_str() {
+                "facet" => {
+                    tokens.expect("normal")?;
+                    let normal = vector(&mut tokens, "a normal")?;
+                    tokens.expect("outer")?;
+                    tokens.expect("loop")?;
+                    let mut corners = [[0.0; 3]; 3];
+                    for corner in &mut corners {
+                        tokens.expect("vertex")?;
+                        *corner = vector(&mut tokens, "a vertex position")?;
+                    }
+                    tokens.expect("endloop")?;
+                    tokens.expect("endfacet")?;
+                    facets.push(Facet {
+                        normal,
+                        corners,
+                        color: None,
+                    });
+                }
+                "endsolid" => {
+                    tokens.rest_of_line();
+                    break;
+                }
+                _ => {
+                    return Err(
+                        tokens.error(format!("expected facet or endsolid, found '{}'", word))
+                    )
+                }
+            }
+        }
+        solids.push((name, facets));
+    }
+    if solids.is_empty() {
+        return Err(anyhow::anyhow!("the file has no solid"));
+    }
+    Ok(solids)
+}
+
+// one mesh per solid, solids without a name (and binary files) are named `name`
+pub fn parse_stl(bytes: &[u8], name: &str, options: &StlOptions) -> anyhow::Result<Model> {
+    let solids = if is_binary_stl(bytes) {
+        vec![(name.to_string(), binary_facets(bytes)?)]
+    } else {
+        let text = std::str::from_utf8(bytes)
+            .map_err(|err| anyhow::anyhow!("text stl is not valid utf-8 ({})", err))?;
+        text_solids(text)?
+    };
+    let meshes = solids
+        .into_iter()
+        .map(|(solid_name, facets)| {
+            let solid_name = if solid_name.is_empty() {
+                name
+            } else {
+                &solid_name
+            };
+            weld(solid_name, &facets, options)
+        })
+        .collect();
+    Ok(Model {
+        meshes,
+        ..Default::default()
+    })
+}
+
+// the meshes are named after the file unless the solids have names
+pub fn load_stl(path: &str, options: &StlOptions) -> anyhow::Result<Model> {
+    let bytes = std::fs::read(path)
+        .map_err(|err| anyhow::anyhow!("{}: can't read file ({})", path, err))?;
+    let name = Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("stl");
+    parse_stl(&bytes, name, options).map_err(|err| anyhow::anyhow!("{}: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a unit quad in the xy plane as two facets sharing the 0-2 diagonal
+    const QUAD: [[[f32; 3]; 3]; 2] = [
+        [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+        [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+    ];
+
+    fn binary_stl(header: &[u8], facets: &[([[f32; 3]; 3], u16)]) -> Vec<u8> {
+        let mut bytes = header.to_vec();
+        bytes.resize(BINARY_HEADER_SIZE, b' ');
+        bytes.extend((facets.len() as u32).to_le_bytes());
+        for (corners, attribute) in facets {
+            for value in [0.0, 0.0, 1.0].iter().chain(corners.iter().flatten()) {
+                bytes.extend(f32::to_le_bytes(*value));
+            }
+            bytes.extend(attribute.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn text_stl() {
+        let mut text = "solid quad\n".to_string();
+        for corners in QUAD {
+            text += "  facet normal 0 0 1\n    outer loop\n";
+            for [x, y, z] in corners {
+                text += &format!("      vertex {} {} {}\n", x, y, z);
+            }
+            text += "    endloop\n  endfacet\n";
+        }
+        text += "endsolid quad\nsolid\nendsolid\n";
+        let model = parse_stl(text.as_bytes(), "file", &StlOptions::default()).unwrap();
+
+        assert_eq!(model.meshes.len(), 2);
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.name, "quad");
+        // the diagonal corners are shared
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert!(mesh
+            .vertices
+            .iter()
+            .all(|vertex| vertex.normal == [0.0, 0.0, 1.0]));
+        assert!(mesh.colors.is_empty());
+        // an unnamed solid takes the name of the file
+        assert_eq!(model.meshes[1].name, "file");
+        assert!(model.meshes[1].vertices.is_empty());
+    }
+
+    #[test]
+    fn binary_stl_with_a_solid_header() {
+        let bytes = binary_stl(
+            b"solid exported by a cad tool",
+            &[(QUAD[0], 0), (QUAD[1], 0)],
+        );
+        assert!(is_binary_stl(&bytes));
+        let model = parse_stl(&bytes, "part", &StlOptions::default()).unwrap();
+
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.meshes[0].name, "part");
+        assert_eq!(model.meshes[0].vertices.len(), 4);
+        assert_eq!(model.meshes[0].indices.len(), 6);
+        // one byte short is not a binary file any more, and not valid text
+        assert!(parse_stl(&bytes[..bytes.len() - 1], "part", &StlOptions::default()).is_err());
+    }
+
+    #[test]
+    fn facet_colors() {
+        // solidview: bit 15 marks a color, red in the high bits
+        let red = 0x8000 | (31 << 10);
+        let bytes = binary_stl(b"binary", &[(QUAD[0], red), (QUAD[1], 0)]);
+        let mesh = &parse_stl(&bytes, "part", &StlOptions::default())
+            .unwrap()
+            .meshes[0];
+        // corners of facets with different colors are not shared
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.colors[..3], [[1.0, 0.0, 0.0, 1.0]; 3]);
+        assert_eq!(mesh.colors[3..], [[1.0; 4]; 3]);
+
+        // magics: the header has the default color, bit 15 clear for facets with
+        // their own color (red in the low bits)
+        let header = [&b"COLOR="[..], &[0, 255, 0, 255]].concat();
+        let bytes = binary_stl(&header, &[(QUAD[0], 31), (QUAD[1], 0x8000)]);
+        let mesh = &parse_stl(&bytes, "part", &StlOptions::default())
+            .unwrap()
+            .meshes[0];
+        assert_eq!(mesh.colors[0], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(mesh.colors[3], [0.0, 1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn welding() {
+        // the shared corners are off by 0.002 on both sides of a cell border
+        let mut facets = QUAD.map(|corners| (corners, 0));
+        facets[1].0[0] = [0.051, 0.0, 0.0];
+        facets[0].0[0] = [0.049, 0.0, 0.0];
+        let bytes = binary_stl(b"binary", &facets);
+        let exact = parse_stl(&bytes, "part", &StlOptions::default()).unwrap();
+        assert_eq!(exact.meshes[0].vertices.len(), 5);
+        let options = StlOptions {
+            weld_tolerance: 0.1,
+            ..Default::default()
+        };
+        let welded = parse_stl(&bytes, "part", &options).unwrap();
+        assert_eq!(welded.meshes[0].vertices.len(), 4);
+        assert_eq!(welded.meshes[0].indices, vec![0, 1, 2, 0, 2, 3]);
+
+        // a facet smaller than the tolerance collapses and is removed
+        let options = StlOptions {
+            weld_tolerance: 2.0,
+            ..Default::default()
+        };
+        let collapsed = parse_stl(&bytes, "part", &options).unwrap();
+        assert!(collapsed.meshes[0].indices.is_empty());
+    }
+}
@@ -1,20 +1,30 @@
 use cgmath::num_traits::ops::inv;
 use cgmath::{Matrix4, SquareMatrix, Zero};
 
+use crate::animation_library::{log_binding_reports, AnimationLibrary};
+use crate::animation_lod::{AnimationLod, AnimationLodSettings};
 use crate::app::UpdateCallback;
 use crate::camera::{Camera, ModelMatrixUniform};
-use crate::model::{self, AnimatedBone, Animation, Bone, BoneTransformsUniform, Model, Skeleton};
+use crate::light::Light;
+use crate::mesh_optimizer::{self, OptimizeOptions};
+use crate::model::{
+    AnimatedBone, Animation, Bone, BoneTransformsUniform, MeshVertexFormat, Model, Skeleton,
+};
+use crate::model_loader::{self, LoadError};
 use crate::model_shader::{self, ModelShader};
-use crate::obj_loader;
+use crate::scene::Scene;
 use crate::shader::{self, Render};
 use crate::transform::{self, Transform};
-use cgmath::Vector3;
+use cgmath::{InnerSpace, Quaternion, Vector3};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::Arc;
 pub struct CameraController {
     pub camera: Camera,
     speed: f32,
+    // point the camera turns around, the models are loaded at the origin
+    target: Vector3<f32>,
 }
 
 impl CameraController {
@@ -22,7 +32,11 @@ impl CameraController {
         let renderer = crate::app::get_renderer().expect("error");
         let camera: Camera =
             Camera::default(renderer.config.width as f32 / renderer.config.height as f32);
-        Self { camera, speed: 2.5 }
+        Self {
+            camera,
+            speed: 2.5,
+            target: Vector3::zero(),
+        }
     }
 }
 
@@ -54,103 +68,272 @@ impl UpdateCallback for CameraController {
         //         .translate((0.0, 0.0, -self.speed * delta_time).into());
         // }
         if crate::input::is_key_pressed(crate::input::KeyCode::A) {
-            // Rotate the camera around the up axis
+            // orbit around the target, on the camera up axis
             self.camera
                 .transform
-                .rotate_around_axis(Vector3::unit_y(), -self.speed * delta_time);
+                .orbit(self.target, Vector3::unit_y(), self.speed * delta_time);
         }
         if crate::input::is_key_pressed(crate::input::KeyCode::D) {
-            // Rotate the camera around the up axis
+            // orbit around the target, on the camera up axis
             self.camera
                 .transform
-                .rotate_around_axis(Vector3::unit_y(), self.speed * delta_time);
+                .orbit(self.target, Vector3::unit_y(), -self.speed * delta_time);
         }
         if crate::input::is_key_pressed(crate::input::KeyCode::S) {
-            // Rotate the camera down around its right axis
+            // orbit around the target, on the camera right axis
             self.camera
                 .transform
-                .rotate_around_axis(Vector3::unit_x(), -self.speed * delta_time);
+                .orbit(self.target, Vector3::unit_x(), self.speed * delta_time);
         }
         if crate::input::is_key_pressed(crate::input::KeyCode::W) {
-            // Rotate the camera up around its right axis
+            // orbit around the target, on the camera right axis
             self.camera
                 .transform
-                .rotate_around_axis(Vector3::unit_x(), self.speed * delta_time);
+                .orbit(self.target, Vector3::unit_x(), -self.speed * delta_time);
         }
     }
 }
 
 pub struct LoadedModel {
     transform: Transform,
-    model: (Model, Vec<crate::model::Animation>),
     shader: Rc<RefCell<ModelShader>>,
+    animator: ModelAnimator,
+    // model space bounding sphere
+    bounds_center: Vector3<f32>,
+    bounds_radius: f32,
+}
+
+// cpu side animation state of a loaded model, it holds no gpu resources
+// so it can be evaluated on worker threads
+pub struct ModelAnimator {
+    model: Model,
+    // clips bound to the model skeleton, shared with the animation library
+    animations: Vec<Arc<Animation>>,
     animation_player: Option<AnimationPlayer>,
+    animation_lod: Option<AnimationLod>,
     selected_anim_index: usize,
 }
 
+// bone palette produced by ModelAnimator::evaluate, ready to be uploaded
+pub struct EvaluatedPose {
+    pub transforms: BoneTransformsUniform,
+    pub bone_count: usize,
+}
+
+impl ModelAnimator {
+    pub fn new(model: Model, animations: Vec<Arc<Animation>>) -> Self {
+        let mut animation_player = None;
+        let mut animation_lod = None;
+        if !animations.is_empty() {
+            animation_player = Some(AnimationPlayer::new());
+            if let Some(skeleton) = model.meshes.first().and_then(|mesh| mesh.skeleton.as_ref()) {
+                animation_lod = Some(AnimationLod::new(AnimationLodSettings::default(), skeleton));
+            }
+        }
+        Self {
+            model,
+            animations,
+            animation_player,
+            animation_lod,
+            selected_anim_index: 0,
+        }
+    }
+
+    // sample the selected animation, returns the pose to upload this frame (if any)
+    pub fn evaluate(&mut self, delta_time: f32) -> Option<EvaluatedPose> {
+        let animation_player = self.animation_player.as_mut()?;
+        let skeleton = self.model.meshes.first()?.skeleton.as_ref()?;
+        let lod = self.animation_lod.as_mut()?;
+        // evaluate the pose only when the lod level asks for it
+        if let Some(elapsed) = lod.tick(delta_time) {
+            let new_bones = animation_player.animate_with_leaf_overrides(
+                elapsed,
+                &self.animations[self.selected_anim_index],
+                skeleton,
+                lod.leaf_overrides(),
+            );
+            lod.push_pose(new_bones);
+        }
+        let bone_count = skeleton.bones_ordered.len();
+        lod.take_pose(bone_count).map(|transforms| EvaluatedPose {
+            transforms,
+            bone_count,
+        })
+    }
+
+    pub fn next_animation(&mut self) {
+        if self.animations.is_empty() {
+            return;
+        }
+        // go next animation
+        self.selected_anim_index += 1;
+        // if it is bigger set 0
+        if self.selected_anim_index >= self.animations.len() {
+            self.selected_anim_index = 0;
+        }
+        // reset time
+        if let Some(animation_player) = &mut self.animation_player {
+            animation_player.reset();
+        }
+        if let Some(lod) = &mut self.animation_lod {
+            lod.reset();
+        }
+    }
+}
+
 impl UpdateCallback for LoadedModel {
     fn update(&mut self, delta_time: f32) {
-        if let Some(animation_player) = &mut self.animation_player {
-            let skeleton = self.model.0.meshes[0].skeleton.as_ref();
-            if let Some(skeleton) = skeleton {
-                let new_bones = animation_player.animate_with_ordered_bones(
-                    delta_time,
-                    &self.model.1[self.selected_anim_index],
-                    &skeleton,
-                );
-                // get renderer
-                let renderer = crate::app::get_renderer().expect("error");
-                // borrow shader
-                let mut shader = (*self.shader).borrow_mut();
-                // update shader
-                shader
-                    .bone_transform_buffer
-                    .change_transforms(new_bones, &renderer.queue);
-            }
-            if crate::input::is_key_just_pressed(crate::input::KeyCode::N) {
-                // go next animation
-                self.selected_anim_index += 1;
-                // if it is bigger set 0
-                if self.selected_anim_index >= self.model.1.len() {
-                    self.selected_anim_index = 0;
-                }
-                // reset time
-                if let Some(animation_player) = &mut self.animation_player {
-                    animation_player.reset();
-                }
-            }
+        self.handle_input();
+        if let Some(pose) = self.animator.evaluate(delta_time) {
+            self.upload_pose(&pose);
         }
     }
 }
 
 impl LoadedModel {
-    pub fn new(model_path: &str, anim_path: &str, transform: Transform) -> Self {
-        let renderer = crate::app::get_renderer().expect("error");
-        // load model gltf (not working)
-        //let model: (Model, Vec<crate::model::Animation>) =
-        //    crate::gltf_loader::load_gltf(path).expect("Error mesh not found");
+    // the animations of the model file are added to the library, the meshes are
+    // uploaded in vertex_format, after mesh_optimizer when `optimize` is set
+    pub fn new(
+        model_path: &str,
+        library: &mut AnimationLibrary,
+        transform: Transform,
+        vertex_format: MeshVertexFormat,
+        optimize: Option<OptimizeOptions>,
+    ) -> Result<Self, LoadError> {
+        let (model, animations) = model_loader::load_model(model_path)?;
+        if model.meshes.is_empty() {
+            return Err(LoadError::NoMesh {
+                path: model_path.to_string(),
+            });
+        }
+        for animation in animations {
+            library.add_clip(animation, model_path);
+        }
+        Ok(Self::from_model(
+            model,
+            library,
+            transform,
+            vertex_format,
+            optimize,
+        ))
+    }
 
-        let model = obj_loader::load_json_obj(model_path, anim_path).expect("model error");
-        for anim in &model.1 {
+    // load every model of a scene, they share the transform so the scene moves
+    // as a unit, its animations are added to the library and its first light is used
+    pub fn spawn_scene(
+        mut scene: Scene,
+        source: &str,
+        library: &mut AnimationLibrary,
+        transform: Transform,
+        vertex_format: MeshVertexFormat,
+        optimize: Option<OptimizeOptions>,
+    ) -> Vec<Self> {
+        for animation in std::mem::take(&mut scene.animations) {
+            library.add_clip(animation, source);
+        }
+        let light = scene.lights.first().cloned();
+        let mut models: Vec<Self> = scene
+            .into_models()
+            .into_iter()
+            .map(|model| Self::from_model(model, library, transform, vertex_format, optimize))
+            .collect();
+        if let Some(light) = light {
+            for model in &mut models {
+                model.set_light(&light);
+            }
+        }
+        models
+    }
+
+    pub fn from_model(
+        mut model: Model,
+        library: &AnimationLibrary,
+        transform: Transform,
+        vertex_format: MeshVertexFormat,
+        optimize: Option<OptimizeOptions>,
+    ) -> Self {
+        let renderer = crate::app::get_renderer().expect("error");
+        for mesh in &mut model.meshes {
+            mesh.vertex_format = vertex_format;
+        }
+        if let Some(options) = optimize {
+            for report in mesh_optimizer::optimize_model(&mut model, &options) {
+                eprintln!("{}", report);
+            }
+        }
+        // pick the library clips that animate this skeleton
+        let animations = match model.meshes.first().and_then(|mesh| mesh.skeleton.as_ref()) {
+            Some(skeleton) => {
+                let (animations, reports) = library.bind(skeleton);
+                log_binding_reports(&reports);
+                animations
+            }
+            None => Vec::new(),
+        };
+        for anim in &animations {
             println!("anim {}", anim.name);
         }
         // load shader
         let shader: Rc<RefCell<ModelShader>> = Rc::new(RefCell::new(
-            model_shader::ModelShader::new("src/model_shader.wgsl", &renderer, &model.0),
+            model_shader::ModelShader::new("src/model_shader.wgsl", &renderer, &model),
         ));
 
         renderer.add_shader(Rc::clone(&shader) as Rc<RefCell<dyn Render>>);
 
-        let mut animation_player = None;
-        if model.1.len() > 0 {
-            animation_player = Some(AnimationPlayer::new());
-        }
+        // bounding sphere used to pick the animation lod
+        let (bounds_center, bounds_radius) = match model.bounds() {
+            Some((min, max)) => {
+                let min = Vector3::from(min);
+                let max = Vector3::from(max);
+                ((min + max) * 0.5, (max - min).magnitude() * 0.5)
+            }
+            None => (Vector3::zero(), 0.0),
+        };
         LoadedModel {
             transform,
-            model,
             shader: Rc::clone(&shader),
-            animation_player,
-            selected_anim_index: 0,
+            animator: ModelAnimator::new(model, animations),
+            bounds_center,
+            bounds_radius,
+        }
+    }
+
+    pub fn animator_mut(&mut self) -> &mut ModelAnimator {
+        &mut self.animator
+    }
+
+    pub fn handle_input(&mut self) {
+        if crate::input::is_key_just_pressed(crate::input::KeyCode::N) {
+            self.animator.next_animation();
+        }
+    }
+
+    // write an evaluated pose to the bone buffer, must run on the render thread
+    pub fn upload_pose(&mut self, pose: &EvaluatedPose) {
+        // get renderer
+        let renderer = crate::app::get_renderer().expect("error");
+        // borrow shader
+        let mut shader = (*self.shader).borrow_mut();
+        // update shader
+        shader.bone_transform_buffer.change_transforms_range(
+            &pose.transforms,
+            pose.bone_count,
+            &renderer.queue,
+        );
+    }
+
+    pub fn set_light(&mut self, light: &Light) {
+        let renderer = crate::app::get_renderer().expect("error");
+        let mut shader = (*self.shader).borrow_mut();
+        shader
+            .light_buffer
+            .update_uniform(light.to_uniform(), &renderer.queue);
+    }
+
+    pub fn set_animation_lod_settings(&mut self, settings: AnimationLodSettings) {
+        if let Some(lod) = &mut self.animator.animation_lod {
+            lod.settings = settings;
+            lod.reset();
         }
     }
 
@@ -177,57 +360,126 @@ impl LoadedModel {
         let mut shader = (*self.shader).borrow_mut();
         // update camera
         shader.camera_buffer.update_camera(&camera, &renderer.queue);
+        // update animation lod level
+        if let Some(lod) = &mut self.animator.animation_lod {
+            let max_scale = self
+                .transform
+                .scale
+                .x
+                .max(self.transform.scale.y)
+                .max(self.transform.scale.z);
+            let center = self.transform.matrix() * self.bounds_center.extend(1.0);
+            lod.select_level(camera, center.truncate(), self.bounds_radius * max_scale);
+        }
     }
 }
 
 pub struct AnimationPlayer {
+    // seconds since the clip started, wrapped by the clip duration when sampled
     current_time: f32,
-    current_anim_index: usize,
-    frame_time: f32,
 }
 
 impl AnimationPlayer {
     pub fn new() -> Self {
-        AnimationPlayer {
-            current_time: 0.0,
-            current_anim_index: 0,
-            frame_time: 1.0 / 24.0, // 24 fps
-        }
+        AnimationPlayer { current_time: 0.0 }
     }
     pub fn reset(&mut self) {
-        self.current_anim_index = 0;
         self.current_time = 0.0;
     }
 
-    pub fn get_bone_model_matrix(&mut self, bone: &AnimatedBone) -> Matrix4<f32> {
-        if self.current_anim_index == bone.translation_keys.len() {
-            self.current_anim_index = 0;
-        }
-        // get bone transform
-        crate::transform::Transform::new(
-            cgmath::Vector3::from(bone.translation_keys[self.current_anim_index].translation),
-            cgmath::Quaternion::from(bone.rotation_keys[self.current_anim_index].rotation),
-            cgmath::Vector3::from(bone.scale_keys[self.current_anim_index].scale),
+    // local transform of a bone at `time`, every channel is sampled on its own keys
+    // and channels without keys keep the bind pose
+    pub fn get_bone_model_matrix(
+        &self,
+        bone: &AnimatedBone,
+        time: f32,
+        bind_pose: &Transform,
+    ) -> Matrix4<f32> {
+        let translation = sample_keys(
+            &bone.translation_keys,
+            time,
+            |key| key.timestamp,
+            |key| Vector3::from(key.translation),
+            |a, b, t| a + (b - a) * t,
+        );
+        let rotation = sample_keys(
+            &bone.rotation_keys,
+            time,
+            |key| key.timestamp,
+            |key| Quaternion::from(key.rotation),
+            |a, b, t| {
+                // take the short way around
+                let b = if a.dot(b) < 0.0 { -b } else { b };
+                a.nlerp(b, t)
+            },
+        );
+        let scale = sample_keys(
+            &bone.scale_keys,
+            time,
+            |key| key.timestamp,
+            |key| Vector3::from(key.scale),
+            |a, b, t| a + (b - a) * t,
+        );
+        Transform::new(
+            translation.unwrap_or(bind_pose.position),
+            rotation.unwrap_or(bind_pose.rotation),
+            scale.unwrap_or(bind_pose.scale),
         )
         .matrix()
     }
 
+    // clip time of the current frame, looping over the clip
+    fn clip_time(&self, animation: &Animation) -> f32 {
+        let duration = clip_duration(animation);
+        if duration > 0.0 {
+            self.current_time % duration
+        } else {
+            0.0
+        }
+    }
+
     pub fn animate_with_ordered_bones(
         &mut self,
         delta_time: f32,
         animation: &Animation,
         skeleton: &Skeleton,
+    ) -> BoneTransformsUniform {
+        self.animate_with_leaf_overrides(delta_time, animation, skeleton, &[])
+    }
+
+    // same as animate_with_ordered_bones, but bones with an override
+    // use it as local transform instead of sampling the animation
+    pub fn animate_with_leaf_overrides(
+        &mut self,
+        delta_time: f32,
+        animation: &Animation,
+        skeleton: &Skeleton,
+        leaf_overrides: &[Option<Matrix4<f32>>],
     ) -> BoneTransformsUniform {
         let mut bone_transforms: Vec<cgmath::Matrix4<f32>> = Vec::new();
         for _ in 0..skeleton.bones_ordered.len() {
             bone_transforms.push(Matrix4::zero());
         }
         let mut final_transforms: BoneTransformsUniform = BoneTransformsUniform::new();
+        let time = self.clip_time(animation);
         for bone in &skeleton.bones_ordered {
             let mut transform = Matrix4::identity();
-            if let Some(anim_bone) = animation.bone_keyframes_name.get_key_value(&(bone.name)) {
-                //animation.bone_keyframes.get_key_value(&(bone.id as usize)) {
-                transform = self.get_bone_model_matrix(&anim_bone.1);
+            if let Some(Some(local)) = leaf_overrides.get(bone.id as usize) {
+                if let Some(parent) = bone.parent_id {
+                    transform = bone_transforms[parent] * local;
+                }
+                bone_transforms[bone.id as usize] = transform;
+            } else {
+                // bones without a track stay in their bind pose
+                let bind_pose = bind_pose(skeleton, bone);
+                transform = match animation.track(&bone.name) {
+                    Some(anim_bone) => self.get_bone_model_matrix(anim_bone, time, &bind_pose),
+                    None => bind_pose.matrix(),
+                };
+                // nodes between the parent and this bone that are not bones
+                if let Some(offset) = bone.parent_offset {
+                    transform = Matrix4::from(offset) * transform;
+                }
                 if let Some(parent) = bone.parent_id {
                     transform = bone_transforms[parent] * transform;
                 }
@@ -242,12 +494,8 @@ impl AnimationPlayer {
     }
 
     fn update_time(&mut self, delta_time: f32) {
-        // update time
+        // throttled players receive the time of every skipped frame at once
         self.current_time += delta_time;
-        if self.current_time > self.frame_time {
-            self.current_anim_index += 1;
-            self.current_time = 0.0;
-        }
     }
     pub fn animate(
         &mut self,
@@ -281,10 +529,10 @@ impl AnimationPlayer {
         }
         let mut tranform: Matrix4<f32> = Matrix4::identity();
         // calculate
-        if let Some(anim_bone) = animation.bone_keyframes.get_key_value(&(bone.id as usize)) {
-            let anim_bone = anim_bone.1;
+        if let Some(anim_bone) = animation.track_by_id(bone.id as usize) {
             // set transform to this
-            tranform = self.get_bone_model_matrix(anim_bone);
+            let time = self.clip_time(animation);
+            tranform = self.get_bone_model_matrix(anim_bone, time, &bind_pose(skeleton, bone));
             // if it has a parent
             if let Some(parent_id) = bone.parent_id {
                 // get the parent transform
@@ -303,3 +551,170 @@ impl AnimationPlayer {
         tranform
     }
 }
+
+// value of a channel at `time`, interpolated between the surrounding keys
+// and held before the first and after the last key
+fn sample_keys<K, T: Copy>(
+    keys: &[K],
+    time: f32,
+    timestamp: impl Fn(&K) -> f32,
+    value: impl Fn(&K) -> T,
+    mix: impl Fn(T, T, f32) -> T,
+) -> Option<T> {
+    let next = keys.partition_point(|key| timestamp(key) <= time);
+    if next == 0 {
+        return keys.first().map(value);
+    }
+    if next == keys.len() {
+        return keys.last().map(value);
+    }
+    let (a, b) = (&keys[next - 1], &keys[next]);
+    let span = timestamp(b) - timestamp(a);
+    let t = if span > 0.0 {
+        (time - timestamp(a)) / span
+    } else {
+        0.0
+    };
+    Some(mix(value(a), value(b), t))
+}
+
+// last key time of every track
+fn clip_duration(animation: &Animation) -> f32 {
+    animation
+        .tracks()
+        .iter()
+        .flat_map(|track| {
+            let translations = track.translation_keys.iter().map(|key| key.timestamp);
+            let rotations = track.rotation_keys.iter().map(|key| key.timestamp);
+            let scales = track.scale_keys.iter().map(|key| key.timestamp);
+            translations.chain(rotations).chain(scales)
+        })
+        .fold(0.0, f32::max)
+}
+
+// local bind pose of a bone, without the non bone nodes above it
+fn bind_pose(skeleton: &Skeleton, bone: &Bone) -> Transform {
+    let mut local = skeleton.bind_local_matrix(bone);
+    if let Some(offset) = bone
+        .parent_offset
+        .and_then(|offset| Matrix4::from(offset).invert())
+    {
+        local = offset * local;
+    }
+    Transform::from_matrix(local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary_format::{self, BinaryWriteOptions};
+    use crate::model::{KeyRotation, KeyTranslation};
+
+    #[test]
+    fn model_without_meshes_is_a_load_error() {
+        let path = std::env::temp_dir().join(format!("empty_{}.rrb", std::process::id()));
+        let path = path.to_string_lossy();
+        binary_format::write_binary(&path, &Model::default(), [], &BinaryWriteOptions::default())
+            .unwrap();
+        let mut library = AnimationLibrary::new();
+        let result = LoadedModel::new(
+            &path,
+            &mut library,
+            Transform::identity(),
+            MeshVertexFormat::Full,
+            None,
+        );
+        std::fs::remove_file(path.as_ref()).unwrap();
+
+        assert!(matches!(result, Err(LoadError::NoMesh { .. })));
+    }
+
+    fn bone(id: u32, name: &str, parent_id: Option<usize>, bind_position: [f32; 3]) -> Bone {
+        let inverse_bind = Matrix4::from_translation(-Vector3::from(bind_position));
+        Bone {
+            id,
+            name: name.to_string(),
+            parent_id,
+            inverse_bind_matrix: inverse_bind.into(),
+            index: id as usize,
+            parent_offset: None,
+        }
+    }
+
+    fn position(transforms: &BoneTransformsUniform, bone: usize, point: [f32; 3]) -> [f32; 3] {
+        let point = Matrix4::from(transforms.transforms[bone]) * Vector3::from(point).extend(1.0);
+        [point.x, point.y, point.z]
+    }
+
+    fn assert_near(a: [f32; 3], b: [f32; 3]) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5),
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn channels_with_different_key_counts() {
+        let bones = vec![
+            bone(0, "root", None, [0.0, 0.0, 0.0]),
+            bone(1, "tip", Some(0), [0.0, 1.0, 0.0]),
+        ];
+        let skeleton = Skeleton {
+            name: "rig".to_string(),
+            bones: bones
+                .iter()
+                .cloned()
+                .map(|bone| (bone.id as usize, bone))
+                .collect(),
+            bones_ordered: bones,
+        };
+        // two translation keys, three rotation keys (0, 90 and 180 degrees about z),
+        // no scale key and no track for the tip
+        let half = 0.5f32.sqrt();
+        let mut animation = Animation::new("clip");
+        animation.add_track(AnimatedBone {
+            bone_name: "root".to_string(),
+            translation_keys: vec![
+                KeyTranslation {
+                    timestamp: 0.0,
+                    translation: [0.0, 0.0, 0.0],
+                },
+                KeyTranslation {
+                    timestamp: 1.0,
+                    translation: [2.0, 0.0, 0.0],
+                },
+            ],
+            rotation_keys: vec![
+                KeyRotation {
+                    timestamp: 0.0,
+                    rotation: [0.0, 0.0, 0.0, 1.0],
+                },
+                KeyRotation {
+                    timestamp: 0.5,
+                    rotation: [0.0, 0.0, half, half],
+                },
+                KeyRotation {
+                    timestamp: 1.0,
+                    rotation: [0.0, 0.0, 1.0, 0.0],
+                },
+            ],
+            ..Default::default()
+        });
+        let mut player = AnimationPlayer::new();
+        let pose = player.animate_with_ordered_bones(0.25, &animation, &skeleton);
+        assert_near(position(&pose, 0, [1.0, 0.0, 0.0]), [1.0, 0.0, 0.0]);
+        assert_near(position(&pose, 1, [0.0, 1.0, 0.0]), [0.0, 1.0, 0.0]);
+        // 0.25s: halfway between the first two rotation keys
+        let pose = player.animate_with_ordered_bones(0.25, &animation, &skeleton);
+        assert_near(position(&pose, 0, [1.0, 0.0, 0.0]), [0.5 + half, half, 0.0]);
+        // 0.5s: on the second rotation key, the tip keeps its bind offset from the root
+        let pose = player.animate_with_ordered_bones(0.75, &animation, &skeleton);
+        assert_near(position(&pose, 0, [1.0, 0.0, 0.0]), [1.0, 1.0, 0.0]);
+        assert_near(position(&pose, 1, [0.0, 1.0, 0.0]), [0.0, 0.0, 0.0]);
+        // 1.25s: the clip loops back to 0.25s
+        let pose = player.animate_with_ordered_bones(0.0, &animation, &skeleton);
+        assert_near(position(&pose, 0, [1.0, 0.0, 0.0]), [0.5 + half, half, 0.0]);
+    }
+}
@@ -22,6 +22,23 @@ impl Texture {
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+    ) -> Result<Self> {
+        Self::from_image_format(
+            device,
+            queue,
+            img,
+            label,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    // data maps (normals, metallic roughness, occlusion) are not srgb encoded
+    pub fn from_image_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -37,7 +54,7 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
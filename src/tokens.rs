@@ -0,0 +1,71 @@
+// whitespace separated words of the text formats (bvh, ascii stl and ply),
+// with the line number of the last word for the error messages
+pub struct Tokens<'a> {
+    words: std::iter::Peekable<Box<dyn Iterator<Item = (usize, &'a str)> + 'a>>,
+    line: usize,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let words: Box<dyn Iterator<Item = (usize, &'a str)>> =
+            Box::new(text.lines().enumerate().flat_map(|(line, words)| {
+                words.split_whitespace().map(move |word| (line + 1, word))
+            }));
+        Self {
+            words: words.peekable(),
+            line: 1,
+        }
+    }
+
+    // line of the last word returned
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn next(&mut self, expected: &str) -> anyhow::Result<&'a str> {
+        match self.words.next() {
+            Some((line, word)) => {
+                self.line = line;
+                Ok(word)
+            }
+            None => Err(anyhow::anyhow!(
+                "line {}: expected {}, found the end of the file",
+                self.line,
+                expected
+            )),
+        }
+    }
+
+    pub fn peek(&mut self) -> Option<&'a str> {
+        self.words.peek().map(|(_, word)| *word)
+    }
+
+    // the words left on the line of the last word
+    pub fn rest_of_line(&mut self) -> Vec<&'a str> {
+        let mut words = Vec::new();
+        while let Some((_, word)) = self.words.next_if(|(line, _)| *line == self.line) {
+            words.push(word);
+        }
+        words
+    }
+
+    // keywords are case insensitive
+    pub fn expect(&mut self, keyword: &str) -> anyhow::Result<()> {
+        let word = self.next(keyword)?;
+        if word.eq_ignore_ascii_case(keyword) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected {}, found '{}'", keyword, word)))
+        }
+    }
+
+    pub fn number<T: std::str::FromStr>(&mut self, expected: &str) -> anyhow::Result<T> {
+        let word = self.next(expected)?;
+        word.parse()
+            .map_err(|_| self.error(format!("expected {}, found '{}'", expected, word)))
+    }
+
+    pub fn error(&self, message: String) -> anyhow::Error {
+        anyhow::anyhow!("line {}: {}", self.line, message)
+    }
+}
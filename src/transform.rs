@@ -28,6 +28,37 @@ impl Transform {
         }
     }
 
+    // decompose an affine matrix (translation * rotation * scale, no shear)
+    pub fn from_matrix(matrix: Matrix4<f32>) -> Self {
+        let position = matrix.w.truncate();
+        let mut scale = Vector3::new(
+            matrix.x.truncate().magnitude(),
+            matrix.y.truncate().magnitude(),
+            matrix.z.truncate().magnitude(),
+        );
+        // a mirroring matrix has a negative scale, put it on x
+        if cgmath::SquareMatrix::determinant(&matrix) < 0.0 {
+            scale.x = -scale.x;
+        }
+        let axis = |column: cgmath::Vector4<f32>, scale: f32| {
+            if scale != 0.0 {
+                column.truncate() / scale
+            } else {
+                Vector3::zero()
+            }
+        };
+        let rotation_matrix = cgmath::Matrix3::from_cols(
+            axis(matrix.x, scale.x),
+            axis(matrix.y, scale.y),
+            axis(matrix.z, scale.z),
+        );
+        Transform {
+            position,
+            rotation: Quaternion::from(rotation_matrix).normalize(),
+            scale,
+        }
+    }
+
     pub fn matrix(&self) -> Matrix4<f32> {
         Matrix4::from_translation(self.position)
             * Matrix4::from(self.rotation)
@@ -67,4 +98,13 @@ impl Transform {
         let quaternion = Quaternion::from_axis_angle(axis, Rad(angle));
         self.rotation = quaternion * self.rotation;
     }
+
+    // turn around `target` on one of our own axes, the target keeps its place in
+    // our local space (an orbit camera keeps looking at it)
+    pub fn orbit(&mut self, target: Vector3<f32>, local_axis: Vector3<f32>, angle: f32) {
+        let axis = self.rotation.rotate_vector(local_axis);
+        let quaternion = Quaternion::from_axis_angle(axis, Rad(angle));
+        self.position = target + quaternion.rotate_vector(self.position - target);
+        self.rotation = quaternion * self.rotation;
+    }
 }
@@ -0,0 +1,510 @@
+// compressed vertex layout, encoded at upload time and decoded by the vs_packed
+// entry points of the model shader: float positions, half float uv sets, octahedral
+// snorm16 normals, snorm8 tangents, u8/u16 bone ids and unorm8/16 weights. static
+// meshes leave the skin attributes out
+use crate::mesh_utils::is_skinned;
+use crate::model::{Mesh, ModelVertex};
+use std::fmt;
+
+// bytes of the attributes every packed vertex has
+const BASE_SIZE: u64 = 28;
+
+// precision of the bone weights, the ids get the smallest type fitting the mesh
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum WeightPrecision {
+    Unorm8,
+    #[default]
+    Unorm16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JointPrecision {
+    // ids up to 254, 255 marks the unused slots
+    U8,
+    // ids up to 65534, 65535 marks the unused slots
+    U16,
+}
+
+impl JointPrecision {
+    fn unused(self) -> u16 {
+        match self {
+            JointPrecision::U8 => u8::MAX as u16,
+            JointPrecision::U16 => u16::MAX,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PackedLayout {
+    // None for static meshes
+    pub skin: Option<(JointPrecision, WeightPrecision)>,
+}
+
+const fn attribute(
+    offset: u64,
+    shader_location: u32,
+    format: wgpu::VertexFormat,
+) -> wgpu::VertexAttribute {
+    wgpu::VertexAttribute {
+        offset,
+        shader_location,
+        format,
+    }
+}
+
+const BASE_ATTRIBUTES: [wgpu::VertexAttribute; 5] = [
+    // position
+    attribute(0, 0, wgpu::VertexFormat::Float32x3),
+    // tex_coords
+    attribute(12, 1, wgpu::VertexFormat::Float16x2),
+    // normal, octahedral
+    attribute(16, 2, wgpu::VertexFormat::Snorm16x2),
+    // tangent, w is the handedness
+    attribute(20, 3, wgpu::VertexFormat::Snorm8x4),
+    // tex_coords_1
+    attribute(24, 9, wgpu::VertexFormat::Float16x2),
+];
+
+// the base attributes then bone_ids, bone_ids_1, bone_weights, bone_weights_1
+const fn skinned_attributes(
+    joints: wgpu::VertexFormat,
+    weights: wgpu::VertexFormat,
+) -> [wgpu::VertexAttribute; 9] {
+    let joint_size = joints.size();
+    let weight_size = weights.size();
+    [
+        BASE_ATTRIBUTES[0],
+        BASE_ATTRIBUTES[1],
+        BASE_ATTRIBUTES[2],
+        BASE_ATTRIBUTES[3],
+        BASE_ATTRIBUTES[4],
+        attribute(BASE_SIZE, 4, joints),
+        attribute(BASE_SIZE + joint_size, 6, joints),
+        attribute(BASE_SIZE + 2 * joint_size, 5, weights),
+        attribute(BASE_SIZE + 2 * joint_size + weight_size, 7, weights),
+    ]
+}
+
+const U8_UNORM8: [wgpu::VertexAttribute; 9] =
+    skinned_attributes(wgpu::VertexFormat::Uint8x4, wgpu::VertexFormat::Unorm8x4);
+const U8_UNORM16: [wgpu::VertexAttribute; 9] =
+    skinned_attributes(wgpu::VertexFormat::Uint8x4, wgpu::VertexFormat::Unorm16x4);
+const U16_UNORM8: [wgpu::VertexAttribute; 9] =
+    skinned_attributes(wgpu::VertexFormat::Uint16x4, wgpu::VertexFormat::Unorm8x4);
+const U16_UNORM16: [wgpu::VertexAttribute; 9] =
+    skinned_attributes(wgpu::VertexFormat::Uint16x4, wgpu::VertexFormat::Unorm16x4);
+
+impl PackedLayout {
+    // u8 ids when every influencing bone fits, the skin is left out of static meshes
+    pub fn for_mesh(mesh: &Mesh, weights: WeightPrecision) -> Self {
+        if !is_skinned(mesh) {
+            return Self { skin: None };
+        }
+        let small_ids = mesh.vertices.iter().all(|vertex| {
+            vertex
+                .bone_influences()
+                .iter()
+                .all(|(id, weight)| *weight <= 0.0 || *id < JointPrecision::U8.unused() as f32)
+        });
+        let joints = if small_ids {
+            JointPrecision::U8
+        } else {
+            JointPrecision::U16
+        };
+        Self {
+            skin: Some((joints, weights)),
+        }
+    }
+
+    fn attributes(&self) -> &'static [wgpu::VertexAttribute] {
+        match self.skin {
+            None => &BASE_ATTRIBUTES,
+            Some((JointPrecision::U8, WeightPrecision::Unorm8)) => &U8_UNORM8,
+            Some((JointPrecision::U8, WeightPrecision::Unorm16)) => &U8_UNORM16,
+            Some((JointPrecision::U16, WeightPrecision::Unorm8)) => &U16_UNORM8,
+            Some((JointPrecision::U16, WeightPrecision::Unorm16)) => &U16_UNORM16,
+        }
+    }
+
+    pub fn stride(&self) -> usize {
+        let skin_size = match self.skin {
+            None => 0,
+            Some((joints, weights)) => {
+                let joint_size = match joints {
+                    JointPrecision::U8 => 4,
+                    JointPrecision::U16 => 8,
+                };
+                let weight_size = match weights {
+                    WeightPrecision::Unorm8 => 4,
+                    WeightPrecision::Unorm16 => 8,
+                };
+                2 * (joint_size + weight_size)
+            }
+        };
+        BASE_SIZE as usize + skin_size
+    }
+
+    pub fn desc(&self) -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: self.stride() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: self.attributes(),
+        }
+    }
+
+    // vertex entry point of the model shader reading this layout
+    pub fn vertex_entry(&self) -> &'static str {
+        match self.skin {
+            Some(_) => "vs_packed",
+            None => "vs_packed_static",
+        }
+    }
+}
+
+// nearest half float (ties to even), out of range values become infinities
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    // the bits kept and dropped, subnormal halfs keep the implicit bit
+    let (kept, dropped, shift) = if half_exponent > 0 {
+        let kept = ((half_exponent as u32) << 10) | (mantissa >> 13);
+        (kept, mantissa & 0x1fff, 13)
+    } else if half_exponent >= -10 {
+        let shift = (14 - half_exponent) as u32;
+        let mantissa = mantissa | 0x80_0000;
+        (mantissa >> shift, mantissa & ((1 << shift) - 1), shift)
+    } else {
+        return sign;
+    };
+    let halfway = 1 << (shift - 1);
+    // a carry into the exponent is the right result (up to the infinity)
+    let rounded = if dropped > halfway || (dropped == halfway && kept & 1 == 1) {
+        kept + 1
+    } else {
+        kept
+    };
+    sign | rounded as u16
+}
+
+fn snorm16(value: f32) -> i16 {
+    (value.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+fn snorm8(value: f32) -> i8 {
+    (value.clamp(-1.0, 1.0) * i8::MAX as f32).round() as i8
+}
+
+// the normal projected on the octahedron and unfolded into [-1, 1]²
+pub fn octahedral_encode(normal: [f32; 3]) -> [f32; 2] {
+    let [x, y, z] = normal;
+    let length = x.abs() + y.abs() + z.abs();
+    if length <= 0.0 || !length.is_finite() {
+        return [0.0, 0.0];
+    }
+    let (x, y) = (x / length, y / length);
+    if z >= 0.0 {
+        return [x, y];
+    }
+    let sign = |value: f32| if value >= 0.0 { 1.0 } else { -1.0 };
+    [(1.0 - y.abs()) * sign(x), (1.0 - x.abs()) * sign(y)]
+}
+
+// the ids and the normalized quantized weights of the 8 influence slots, the
+// rounding error goes to the biggest weight so they still sum to one
+fn quantize_skin(vertex: &ModelVertex, joints: JointPrecision, max: u32) -> ([u16; 8], [u32; 8]) {
+    let influences = vertex.bone_influences();
+    let total: f32 = influences.iter().map(|(_, weight)| weight.max(0.0)).sum();
+    let mut ids = [joints.unused(); 8];
+    let mut weights = [0u32; 8];
+    if total <= 0.0 {
+        return (ids, weights);
+    }
+    for (slot, (id, weight)) in influences.iter().enumerate() {
+        if *weight > 0.0 && *id >= 0.0 {
+            ids[slot] = (*id as u16).min(joints.unused());
+            weights[slot] = (weight / total * max as f32).round() as u32;
+        }
+    }
+    let sum: u32 = weights.iter().sum();
+    let biggest = (0..8).max_by_key(|slot| weights[*slot]).unwrap();
+    weights[biggest] = (weights[biggest] + max).saturating_sub(sum);
+    (ids, weights)
+}
+
+// vertex buffer contents of the mesh in the layout
+pub fn encode_vertices(mesh: &Mesh, layout: &PackedLayout) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(mesh.vertices.len() * layout.stride());
+    for vertex in &mesh.vertices {
+        for value in vertex.position {
+            bytes.extend(value.to_ne_bytes());
+        }
+        for value in vertex.tex_coords {
+            bytes.extend(f32_to_f16(value).to_ne_bytes());
+        }
+        for value in octahedral_encode(vertex.normal) {
+            bytes.extend(snorm16(value).to_ne_bytes());
+        }
+        let [x, y, z, w] = vertex.tangent;
+        let handedness = if w < 0.0 { -1.0 } else { 1.0 };
+        for value in [x, y, z, handedness] {
+            bytes.extend(snorm8(value).to_ne_bytes());
+        }
+        for value in vertex.tex_coords_1 {
+            bytes.extend(f32_to_f16(value).to_ne_bytes());
+        }
+        let Some((joints, weights)) = layout.skin else {
+            continue;
+        };
+        let max = match weights {
+            WeightPrecision::Unorm8 => u8::MAX as u32,
+            WeightPrecision::Unorm16 => u16::MAX as u32,
+        };
+        let (ids, quantized) = quantize_skin(vertex, joints, max);
+        for id in ids {
+            match joints {
+                JointPrecision::U8 => bytes.push(id as u8),
+                JointPrecision::U16 => bytes.extend(id.to_ne_bytes()),
+            }
+        }
+        for weight in quantized {
+            match weights {
+                WeightPrecision::Unorm8 => bytes.push(weight as u8),
+                WeightPrecision::Unorm16 => bytes.extend((weight as u16).to_ne_bytes()),
+            }
+        }
+    }
+    bytes
+}
+
+// vertex buffer size of a mesh uploaded as ModelVertex and packed
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexMemory {
+    pub mesh: String,
+    pub vertices: usize,
+    pub full_bytes: usize,
+    pub packed_bytes: usize,
+}
+
+impl VertexMemory {
+    // the sizes of the data each layout uploads, the mesh is encoded in both
+    pub fn new(mesh: &Mesh, layout: &PackedLayout) -> Self {
+        Self {
+            mesh: mesh.name.clone(),
+            vertices: mesh.vertices.len(),
+            full_bytes: bytemuck::cast_slice::<ModelVertex, u8>(&mesh.vertices).len(),
+            packed_bytes: encode_vertices(mesh, layout).len(),
+        }
+    }
+
+    // the size of the packed vertex buffer the mesh was uploaded to
+    pub fn uploaded(mesh: &Mesh, packed_buffer: &wgpu::Buffer) -> Self {
+        Self {
+            mesh: mesh.name.clone(),
+            vertices: mesh.vertices.len(),
+            full_bytes: bytemuck::cast_slice::<ModelVertex, u8>(&mesh.vertices).len(),
+            packed_bytes: packed_buffer.size() as usize,
+        }
+    }
+}
+
+impl fmt::Display for VertexMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mesh '{}': {} vertices, {} -> {} bytes packed ({:.0}%)",
+            self.mesh,
+            self.vertices,
+            self.full_bytes,
+            self.packed_bytes,
+            self.packed_bytes as f32 * 100.0 / self.full_bytes.max(1) as f32
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // reference decoder of the half floats
+    fn f16_to_f32(half: u16) -> f32 {
+        let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let exponent = ((half >> 10) & 0x1f) as i32;
+        let mantissa = (half & 0x3ff) as f32;
+        match exponent {
+            0 => sign * mantissa * 2f32.powi(-24),
+            0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+            0x1f => f32::NAN,
+            _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+        }
+    }
+
+    // the decoding of the vs_packed entry points
+    fn octahedral_decode([x, y]: [f32; 2]) -> [f32; 3] {
+        let z = 1.0 - x.abs() - y.abs();
+        let fold = (-z).max(0.0);
+        let x = if x >= 0.0 { x - fold } else { x + fold };
+        let y = if y >= 0.0 { y - fold } else { y + fold };
+        let length = (x * x + y * y + z * z).sqrt();
+        [x / length, y / length, z / length]
+    }
+
+    fn layouts() -> Vec<PackedLayout> {
+        let mut layouts = vec![PackedLayout { skin: None }];
+        for joints in [JointPrecision::U8, JointPrecision::U16] {
+            for weights in [WeightPrecision::Unorm8, WeightPrecision::Unorm16] {
+                layouts.push(PackedLayout {
+                    skin: Some((joints, weights)),
+                });
+            }
+        }
+        layouts
+    }
+
+    #[test]
+    fn every_half_float_round_trips() {
+        for half in 0..=u16::MAX {
+            let value = f16_to_f32(half);
+            if value.is_nan() {
+                let nan = f32_to_f16(value);
+                assert!(nan & 0x7c00 == 0x7c00 && nan & 0x3ff != 0, "{:#06x}", nan);
+            } else {
+                assert_eq!(f32_to_f16(value), half, "{:#06x}", half);
+            }
+        }
+    }
+
+    #[test]
+    fn half_float_subnormals_and_rounding() {
+        let smallest = 2f32.powi(-24);
+        assert_eq!(f32_to_f16(smallest), 0x0001);
+        assert_eq!(f32_to_f16(-smallest), 0x8001);
+        // below half of the smallest subnormal is zero, the halfway point goes to even
+        assert_eq!(f32_to_f16(smallest * 0.25), 0x0000);
+        assert_eq!(f32_to_f16(smallest * 0.5), 0x0000);
+        assert_eq!(f32_to_f16(smallest * 0.75), 0x0001);
+        assert_eq!(f32_to_f16(smallest * 1.5), 0x0002);
+        // the largest subnormal, and rounding up into the smallest normal
+        assert_eq!(f32_to_f16(smallest * 1023.0), 0x03ff);
+        assert_eq!(f32_to_f16(smallest * 1023.5), 0x0400);
+        // ties to even between normals, anything above the tie rounds up
+        let ulp = 2f32.powi(-10);
+        assert_eq!(f32_to_f16(1.0 + ulp * 0.5), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + ulp * 1.5), 0x3c02);
+        assert_eq!(f32_to_f16(1.0 + ulp * 0.5 + 2f32.powi(-20)), 0x3c01);
+    }
+
+    #[test]
+    fn half_float_infinities() {
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        // rounded down to the largest half below the tie, to the infinity from it
+        assert_eq!(f32_to_f16(65519.0), 0x7bff);
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16(-1e10), 0xfc00);
+    }
+
+    #[test]
+    fn octahedral_round_trip_error() {
+        let mut normals = vec![
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, -1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0],
+        ];
+        for i in 0..64 {
+            for j in 0..128 {
+                let theta = (i as f32 + 0.5) / 64.0 * std::f32::consts::PI;
+                let phi = j as f32 / 128.0 * std::f32::consts::TAU;
+                normals.push([
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                ]);
+            }
+        }
+        let mut max_error = 0.0f64;
+        for normal in normals {
+            // as read from the Snorm16x2 attribute
+            let encoded =
+                octahedral_encode(normal).map(|value| snorm16(value) as f32 / i16::MAX as f32);
+            let decoded = octahedral_decode(encoded).map(f64::from);
+            let [x, y, z] = normal.map(f64::from);
+            let cross = [
+                y * decoded[2] - z * decoded[1],
+                z * decoded[0] - x * decoded[2],
+                x * decoded[1] - y * decoded[0],
+            ];
+            let sin = cross.iter().map(|value| value * value).sum::<f64>().sqrt();
+            let cos = x * decoded[0] + y * decoded[1] + z * decoded[2];
+            max_error = max_error.max(sin.atan2(cos));
+        }
+        // 0.006 degrees, the snorm16 steps give about 6e-5 radians
+        assert!(max_error < 1e-4, "{} radians", max_error);
+    }
+
+    #[test]
+    fn quantized_weights_sum_to_max() {
+        for max in [u8::MAX as u32, u16::MAX as u32] {
+            for seed in 0..256u32 {
+                let count = seed % 8 + 1;
+                let influences: Vec<(f32, f32)> = (0..count)
+                    .map(|slot| {
+                        let weight = (seed * 7919 + slot * 104_729) % 1000;
+                        (slot as f32 * 3.0, weight as f32 / 1000.0 + 0.001)
+                    })
+                    .collect();
+                let mut vertex = ModelVertex::default();
+                vertex.set_bone_influences(&influences);
+                let (ids, weights) = quantize_skin(&vertex, JointPrecision::U8, max);
+                assert_eq!(weights.iter().sum::<u32>(), max, "{:?}", influences);
+                for slot in count as usize..8 {
+                    assert_eq!(ids[slot], JointPrecision::U8.unused());
+                    assert_eq!(weights[slot], 0);
+                }
+            }
+        }
+        // a vertex without influences keeps every slot unused
+        let (ids, weights) = quantize_skin(&ModelVertex::default(), JointPrecision::U16, 255);
+        assert_eq!(ids, [u16::MAX; 8]);
+        assert_eq!(weights, [0; 8]);
+    }
+
+    #[test]
+    fn stride_matches_the_attribute_offsets() {
+        let mesh = Mesh {
+            vertices: vec![ModelVertex::default(); 3],
+            ..Default::default()
+        };
+        for layout in layouts() {
+            let mut attributes = layout.attributes().to_vec();
+            attributes.sort_by_key(|attribute| attribute.offset);
+            // packed without gaps or overlaps, up to the stride
+            let mut end = 0;
+            for attribute in &attributes {
+                assert_eq!(attribute.offset, end, "{:?}", layout);
+                end += attribute.format.size();
+            }
+            assert_eq!(layout.stride() as u64, end, "{:?}", layout);
+            assert_eq!(layout.stride() % 4, 0, "{:?}", layout);
+            assert_eq!(
+                encode_vertices(&mesh, &layout).len(),
+                3 * layout.stride(),
+                "{:?}",
+                layout
+            );
+        }
+    }
+}